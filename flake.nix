@@ -27,7 +27,9 @@
         plugins = pkgs.callPackage ./plugins.nix { };
 
         packages = {
-          _nix-jebrains-plugins-generator = pkgs.callPackage ./generator/pkg.nix { };
+          _nix-jebrains-plugins-generator = pkgs.callPackage ./generator/pkg.nix {
+            rev = self.rev or self.dirtyRev or null;
+          };
         };
 
         devShells = {
@@ -42,10 +44,31 @@
             jetbrains: ide-or-name: plugin-ids:
             let
               ide = if builtins.typeOf ide-or-name == "string" then jetbrains."${ide-or-name}" else ide-or-name;
+              idePlugins = plugins."${ide.pname}"."${ide.version}";
+              # Also install the marketplace plugins the requested plugins depend on, recursively.
+              withDependencies = builtins.genericClosure {
+                startSet = builtins.map (p: { key = p; }) plugin-ids;
+                operator =
+                  item:
+                  builtins.map (p: { key = p; }) (
+                    builtins.filter (p: idePlugins ? "${p}") (idePlugins."${item.key}".dependencies or [ ])
+                  );
+              };
+              selected = builtins.map (item: idePlugins."${item.key}") withDependencies;
+              paid = builtins.filter (p: p.paid or false) selected;
+              bundled = builtins.filter (p: idePlugins."${p}".bundled or false) plugin-ids;
             in
-            jetbrains.plugins.addPlugins ide (
-              builtins.map (p: plugins."${ide.pname}"."${ide.version}"."${p}") plugin-ids
-            );
+            pkgs.lib.warnIf (bundled != [ ])
+              "buildIdeWithPlugins: these plugins are already bundled with ${ide.pname} ${ide.version}: ${
+                builtins.concatStringsSep ", " bundled
+              }"
+              (
+                pkgs.lib.warnIf (paid != [ ])
+                  "buildIdeWithPlugins: these plugins require a paid license: ${
+                    builtins.concatStringsSep ", " (builtins.map (p: p.name) paid)
+                  }"
+                  (jetbrains.plugins.addPlugins ide selected)
+              );
         };
       }
     );
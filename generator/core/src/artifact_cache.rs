@@ -0,0 +1,184 @@
+//! Cache of downloaded plugin artifacts by URL, so that a run after a failure doesn't download
+//! the artifacts hashed shortly before again. Bounded in size: the least recently used
+//! artifacts are removed beyond it.
+//!
+//! Marketplace download URLs contain the version, so their artifacts are taken to never change.
+//! Artifacts republished under the same URL (see `--verify-artifacts`) are only noticed once
+//! they left the cache.
+
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::fs::{File, read_dir, remove_dir_all, remove_file};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tempfile::{NamedTempFile, TempDir};
+use tokio::sync::Mutex;
+use tokio::task::spawn_blocking;
+
+/// Prefix of the per-run working directories in the cache directory.
+const WORK_DIR_PREFIX: &str = ".run-";
+/// Working directories of runs that crashed are removed after this long.
+const STALE_WORK_DIR: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub struct ArtifactCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    /// Where artifacts are downloaded to before they are complete, removed with the cache.
+    work_dir: TempDir,
+    /// Serializes evictions, which scan the whole directory.
+    evicting: Mutex<()>,
+}
+
+impl ArtifactCache {
+    /// Opens (or creates) the cache in `dir`, with a new working directory for this run.
+    pub fn open(dir: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        remove_stale_work_dirs(&dir)?;
+        let work_dir = tempfile::Builder::new()
+            .prefix(WORK_DIR_PREFIX)
+            .tempdir_in(&dir)?;
+        Ok(Self {
+            dir,
+            max_bytes,
+            work_dir,
+            evicting: Mutex::new(()),
+        })
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:x}", Sha256::digest(url.as_bytes())))
+    }
+
+    /// The cached artifact of `url`, marked as recently used.
+    pub async fn get(&self, url: &str) -> io::Result<Option<File>> {
+        let path = self.path(url);
+        spawn_blocking(
+            move || match File::options().read(true).write(true).open(&path) {
+                Ok(file) => {
+                    file.set_modified(SystemTime::now())?;
+                    Ok(Some(file))
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            },
+        )
+        .await?
+    }
+
+    /// A new file in the working directory to download an artifact to, see [`Self::insert`].
+    pub fn download_file(&self) -> io::Result<NamedTempFile> {
+        NamedTempFile::new_in(self.work_dir.path())
+    }
+
+    /// Adds the completely downloaded artifact `file` of `url` and evicts the least recently
+    /// used artifacts beyond the size limit. Returns the file to read the artifact from.
+    pub async fn insert(&self, url: &str, file: NamedTempFile) -> anyhow::Result<File> {
+        let path = self.path(url);
+        let file = spawn_blocking(move || file.persist(path)).await??;
+        let _evicting = self.evicting.lock().await;
+        let dir = self.dir.clone();
+        let max_bytes = self.max_bytes;
+        spawn_blocking(move || evict(&dir, max_bytes)).await??;
+        Ok(file)
+    }
+}
+
+/// Whether `name` is that of an artifact in the cache, a SHA-256 in hex.
+fn is_artifact(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Removes the least recently used artifacts in `dir` until they take at most `max_bytes`.
+fn evict(dir: &Path, max_bytes: u64) -> io::Result<()> {
+    let mut artifacts = Vec::new();
+    let mut total = 0;
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        if !is_artifact(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        total += metadata.len();
+        artifacts.push((metadata.modified()?, metadata.len(), entry.path()));
+    }
+    artifacts.sort();
+    for (_, size, path) in artifacts {
+        if total <= max_bytes {
+            break;
+        }
+        debug!("Evicting {} from the artifact cache", path.display());
+        match remove_file(&path) {
+            Ok(()) => total -= size,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => total -= size,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Removes the working directories of runs that didn't clean up after themselves.
+fn remove_stale_work_dirs(dir: &Path) -> io::Result<()> {
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(WORK_DIR_PREFIX)
+        {
+            continue;
+        }
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age > STALE_WORK_DIR
+            && let Err(e) = remove_dir_all(entry.path())
+        {
+            warn!(
+                "Failed removing stale working directory {}: {e}",
+                entry.path().display()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifact_names() {
+        assert!(is_artifact(&format!("{:x}", Sha256::digest(b"url"))));
+        assert!(!is_artifact(".run-abc"));
+        assert!(!is_artifact(&"g".repeat(64)));
+        assert!(!is_artifact(&"a".repeat(63)));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        let artifact = |name: &str, age: u64| {
+            let path = dir.path().join(format!("{:x}", Sha256::digest(name)));
+            std::fs::write(&path, [0; 10]).unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age))
+                .unwrap();
+            path
+        };
+        let oldest = artifact("a", 30);
+        let older = artifact("b", 20);
+        let newest = artifact("c", 10);
+        let other = dir.path().join("unrelated");
+        std::fs::write(&other, [0; 100]).unwrap();
+
+        evict(dir.path(), 30).unwrap();
+        assert!(oldest.exists() && older.exists() && newest.exists());
+        evict(dir.path(), 15).unwrap();
+        assert!(!oldest.exists() && !older.exists());
+        assert!(newest.exists() && other.exists());
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::anyhow;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Component value of `*` and `SNAPSHOT`, which compare greater than any real number.
+const WILDCARD: u32 = u32::MAX;
+
+/// An IntelliJ platform build number such as `243.21565.193`, `243.*` or `IU-243.21565`.
+///
+/// Ordering follows IntelliJ's `BuildNumber`: components are compared left to right, `*` and
+/// `SNAPSHOT` match anything from their position on, and if one build number is a prefix of the
+/// other, the longer one is greater (so `243.21565` > `243`). As nothing after a wildcard is
+/// compared, it is dropped when parsing: `243.*.1` is the same build number as `243.*`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BuildNumber {
+    components: Vec<u32>,
+}
+
+impl BuildNumber {
+    /// Whether a build with this number satisfies the `since-build`/`until-build` range of a
+    /// plugin. Both bounds are inclusive, missing bounds are open.
+    pub fn is_within(&self, since: Option<&BuildNumber>, until: Option<&BuildNumber>) -> bool {
+        since.is_none_or(|since| self >= since) && until.is_none_or(|until| self <= until)
+    }
+
+    /// Parses a `since-build`/`until-build` bound. Missing and empty bounds are open.
+    pub fn parse_bound(bound: Option<&str>) -> anyhow::Result<Option<BuildNumber>> {
+        bound
+            .map(str::trim)
+            .filter(|bound| !bound.is_empty())
+            .map(BuildNumber::from_str)
+            .transpose()
+    }
+}
+
+impl FromStr for BuildNumber {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Strip product code prefixes like `IU-`.
+        let number = s.split_once('-').map_or(s, |(_, number)| number);
+        let mut components = number
+            .split('.')
+            .map(|component| match component {
+                "*" | "SNAPSHOT" => Ok(WILDCARD),
+                component => component
+                    .parse()
+                    .map_err(|_| anyhow!("invalid build number: {s:?}")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(wildcard) = components.iter().position(|c| *c == WILDCARD) {
+            components.truncate(wildcard + 1);
+        }
+        Ok(Self { components })
+    }
+}
+
+impl Ord for BuildNumber {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for (a, b) in self.components.iter().zip(&other.components) {
+            match (*a, *b) {
+                (WILDCARD, WILDCARD) => return Ordering::Equal,
+                (WILDCARD, _) => return Ordering::Greater,
+                (_, WILDCARD) => return Ordering::Less,
+                (a, b) if a != b => return a.cmp(&b),
+                _ => {}
+            }
+        }
+        self.components.len().cmp(&other.components.len())
+    }
+}
+
+impl PartialOrd for BuildNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for BuildNumber {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, component) in self.components.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            match *component {
+                WILDCARD => f.write_str("*")?,
+                component => write!(f, "{component}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(s: &str) -> BuildNumber {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn orders_components_numerically() {
+        assert!(build("243.21565.193") > build("243.9999"));
+        assert!(build("251.1") > build("243.21565.193"));
+        assert!(build("243.21565.193") < build("243.21565.194"));
+    }
+
+    #[test]
+    fn longer_build_number_is_greater() {
+        assert!(build("243.21565.193") > build("243"));
+        assert!(build("243.21565") > build("243"));
+        assert!(build("243.21565.193") > build("243.21565"));
+    }
+
+    #[test]
+    fn wildcard_matches_anything_from_its_position() {
+        assert!(build("243.*") > build("243.21565.193"));
+        assert!(build("243.*") > build("243"));
+        assert!(build("243.*") < build("244"));
+        assert_eq!(build("243.SNAPSHOT"), build("243.*"));
+    }
+
+    #[test]
+    fn ordering_is_consistent_with_equality() {
+        assert_eq!(build("243.*"), build("243.*.1"));
+        assert_eq!(build("243.*").cmp(&build("243.*.1")), Ordering::Equal);
+        assert_eq!(build("243.*.1").to_string(), "243.*");
+        assert_ne!(build("243"), build("243.0"));
+        assert_ne!(build("243").cmp(&build("243.0")), Ordering::Equal);
+    }
+
+    #[test]
+    fn strips_product_code() {
+        assert_eq!(build("IU-243.21565.193"), build("243.21565.193"));
+        assert_eq!(build("IC-243.*").to_string(), "243.*");
+    }
+
+    #[test]
+    fn rejects_invalid_build_numbers() {
+        assert!("".parse::<BuildNumber>().is_err());
+        assert!("243.x".parse::<BuildNumber>().is_err());
+        assert!("243..1".parse::<BuildNumber>().is_err());
+    }
+
+    #[test]
+    fn within_inclusive_bounds() {
+        let ide = build("243.21565.193");
+        assert!(ide.is_within(Some(&build("243")), Some(&build("243.*"))));
+        assert!(ide.is_within(Some(&build("243.21565.193")), Some(&build("243.21565.193"))));
+        assert!(!ide.is_within(Some(&build("243.21565.194")), None));
+        assert!(!ide.is_within(None, Some(&build("243.21565"))));
+        assert!(!ide.is_within(None, Some(&build("243"))));
+        assert!(ide.is_within(None, None));
+    }
+
+    #[test]
+    fn empty_bounds_are_open() {
+        assert_eq!(BuildNumber::parse_bound(None).unwrap(), None);
+        assert_eq!(BuildNumber::parse_bound(Some("")).unwrap(), None);
+        assert_eq!(BuildNumber::parse_bound(Some(" ")).unwrap(), None);
+        assert_eq!(
+            BuildNumber::parse_bound(Some("IU-243.*")).unwrap(),
+            Some(build("243.*"))
+        );
+        assert!(BuildNumber::parse_bound(Some("243.x")).is_err());
+    }
+}
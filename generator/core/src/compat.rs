@@ -0,0 +1,141 @@
+//! Which plugin version the generator picks for an IDE build.
+
+use crate::build_number::BuildNumber;
+use crate::version_order::compare_versions;
+use log::debug;
+
+/// A plugin version and the range of IDE builds it declares support for.
+pub trait CompatibilityInfo {
+    fn version(&self) -> &str;
+    /// Inclusive lower bound, e.g. `243`. Open if missing.
+    fn since_build(&self) -> Option<&str>;
+    /// Inclusive upper bound, e.g. `251.*`. Open if missing.
+    fn until_build(&self) -> Option<&str>;
+
+    /// Whether the plugin version supports `build`. Versions with unparsable bounds are
+    /// treated as incompatible, empty ones as open.
+    fn is_compatible_with(&self, build: &BuildNumber) -> bool {
+        let parse = BuildNumber::parse_bound;
+        match (parse(self.since_build()), parse(self.until_build())) {
+            (Ok(since), Ok(until)) => build.is_within(since.as_ref(), until.as_ref()),
+            (Err(e), _) | (_, Err(e)) => {
+                debug!("{e}");
+                false
+            }
+        }
+    }
+}
+
+/// A plugin version as listed by the marketplace, for use with [`compatible_version`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginUpdate {
+    pub version: String,
+    pub since_build: Option<String>,
+    pub until_build: Option<String>,
+}
+
+impl CompatibilityInfo for PluginUpdate {
+    fn version(&self) -> &str {
+        &self.version
+    }
+
+    fn since_build(&self) -> Option<&str> {
+        self.since_build.as_deref()
+    }
+
+    fn until_build(&self) -> Option<&str> {
+        self.until_build.as_deref()
+    }
+}
+
+/// The newest of `updates` that supports `build`, by plugin version order. If several
+/// updates have the same version, the one listed first wins.
+///
+/// This is the version the generator records for an IDE with this build number.
+pub fn compatible_version<'a, U: CompatibilityInfo>(
+    build_number: &BuildNumber,
+    updates: &'a [U],
+) -> Option<&'a U> {
+    // Iterate in reverse, so that on ties the version listed first wins.
+    updates
+        .iter()
+        .rev()
+        .filter(|update| update.is_compatible_with(build_number))
+        .max_by(|a, b| compare_versions(a.version(), b.version()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(version: &str, since: Option<&str>, until: Option<&str>) -> PluginUpdate {
+        PluginUpdate {
+            version: version.to_string(),
+            since_build: since.map(str::to_string),
+            until_build: until.map(str::to_string),
+        }
+    }
+
+    fn pick<'a>(build: &str, updates: &'a [PluginUpdate]) -> Option<&'a str> {
+        compatible_version(&build.parse().unwrap(), updates).map(|u| u.version.as_str())
+    }
+
+    #[test]
+    fn since_and_until_are_inclusive() {
+        let updates = [update("1.0", Some("243.100"), Some("243.200"))];
+        assert_eq!(pick("243.100", &updates), Some("1.0"));
+        assert_eq!(pick("243.200", &updates), Some("1.0"));
+        assert_eq!(pick("243.99", &updates), None);
+        assert_eq!(pick("243.201", &updates), None);
+    }
+
+    #[test]
+    fn picks_newest_compatible() {
+        let updates = [
+            update("2.0", Some("251"), None),
+            update("1.10", Some("243"), Some("243.*")),
+            update("1.9", Some("243"), Some("243.*")),
+            update("1.2", Some("233"), Some("242.*")),
+        ];
+        assert_eq!(pick("243.21565.193", &updates), Some("1.10"));
+        assert_eq!(pick("242.1", &updates), Some("1.2"));
+        assert_eq!(pick("251.1", &updates), Some("2.0"));
+    }
+
+    #[test]
+    fn wildcard_until() {
+        let updates = [update("1.0", Some("243"), Some("243.*"))];
+        assert_eq!(pick("243.99999.99999", &updates), Some("1.0"));
+        assert_eq!(pick("244.1", &updates), None);
+    }
+
+    #[test]
+    fn missing_or_empty_until_is_open() {
+        let updates = [update("1.0", Some("243"), None)];
+        assert_eq!(pick("999.1", &updates), Some("1.0"));
+        let updates = [update("1.0", Some("243"), Some(""))];
+        assert_eq!(pick("999.1", &updates), Some("1.0"));
+        let updates = [update("1.0", None, None)];
+        assert_eq!(pick("1", &updates), Some("1.0"));
+    }
+
+    #[test]
+    fn first_listed_wins_ties() {
+        let updates = [
+            update("1.0", Some("243"), None),
+            update("1.0", Some("241"), None),
+        ];
+        let picked = compatible_version(&"243.1".parse().unwrap(), &updates).unwrap();
+        assert_eq!(picked.since_build.as_deref(), Some("243"));
+    }
+
+    #[test]
+    fn no_match() {
+        let updates = [
+            update("1.0", Some("251"), None),
+            update("0.9", Some("243"), Some("not a build")),
+        ];
+        assert_eq!(pick("243.1", &updates), None);
+        assert_eq!(pick("243.1", &[]), None);
+    }
+}
@@ -0,0 +1,100 @@
+//! Compressed copies of the database files, for consumers that download them instead of
+//! checking out the repository.
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// How the JSON files of the database are compressed, in addition to the plain JSON.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Compression {
+    /// No compressed copies.
+    #[default]
+    None,
+    /// `.json.gz` next to every `.json`.
+    Gzip,
+    /// `.json.zst` next to every `.json`.
+    Zstd,
+}
+
+impl Compression {
+    /// The compressions that write files.
+    pub const ENABLED: [Self; 2] = [Self::Gzip, Self::Zstd];
+
+    /// Extension appended to the JSON file name, if enabled.
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("gz"),
+            Self::Zstd => Some("zst"),
+        }
+    }
+
+    /// The compressed copy of `path`, if enabled.
+    pub fn path_of(self, path: &Path) -> Option<PathBuf> {
+        let mut name = path.file_name()?.to_os_string();
+        name.push(".");
+        name.push(self.extension()?);
+        Some(path.with_file_name(name))
+    }
+
+    /// The compression of `path` by its extension, and the path without it.
+    pub fn of_path(path: &Path) -> Option<(Self, PathBuf)> {
+        let extension = path.extension()?;
+        let compression = Self::ENABLED
+            .into_iter()
+            .find(|compression| compression.extension() == extension.to_str())?;
+        Some((compression, path.with_extension("")))
+    }
+
+    /// Compresses `contents`. Equal contents always compress to the same bytes.
+    pub fn encode(self, contents: &[u8]) -> io::Result<Vec<u8>> {
+        self.encode_to(contents, Vec::new())
+    }
+
+    /// Compresses the file `src` to `dst`.
+    pub fn encode_file(self, src: &Path, dst: &Path) -> io::Result<()> {
+        let file = self.encode_to(File::open(src)?, File::create(dst)?)?;
+        file.sync_all()
+    }
+
+    fn encode_to<W: Write>(self, mut reader: impl Read, writer: W) -> io::Result<W> {
+        Ok(match self {
+            Self::None => {
+                let mut writer = writer;
+                io::copy(&mut reader, &mut writer)?;
+                writer
+            }
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(writer, flate2::Compression::best());
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?
+            }
+            Self::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, 19)?;
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?
+            }
+        })
+    }
+
+    /// Decompresses `contents` to a string.
+    pub fn decode(self, contents: &[u8]) -> io::Result<String> {
+        let mut decoded = String::new();
+        match self {
+            Self::None => {
+                decoded = String::from_utf8(contents.to_vec()).map_err(io::Error::other)?;
+            }
+            Self::Gzip => {
+                GzDecoder::new(contents).read_to_string(&mut decoded)?;
+            }
+            Self::Zstd => {
+                zstd::Decoder::new(contents)?.read_to_string(&mut decoded)?;
+            }
+        }
+        Ok(decoded)
+    }
+}
@@ -0,0 +1,226 @@
+//! The optional generator configuration file (`--config`, TOML).
+
+use crate::ides::{AndroidStudioChannel, IdeProduct, PROCESSED_VERSION_PREFIXES, ReleaseRange};
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::exists;
+use std::path::Path;
+use tokio::fs::read_to_string;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    pub exclude: Exclude,
+    pub android_studio: AndroidStudio,
+    pub feeds: Feeds,
+    pub versions: Versions,
+    pub http: Http,
+    pub release_channels: ReleaseChannels,
+}
+
+/// Which versions of each IDE are processed, keyed by nix key (`idea`, `android-studio`, ...).
+/// The `default` window applies to IDEs without a window of their own.
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "BTreeMap<String, VersionWindow>")]
+pub struct Versions {
+    default: VersionWindow,
+    products: HashMap<IdeProduct, VersionWindow>,
+}
+
+/// The versions of an IDE that are processed: those matching one of `prefixes` or in `range`
+/// (all of them if neither is set), limited to the newest `latest-lines` release lines
+/// (`<year>.<minor>`) and `latest-years` years of the IDE's versions.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct VersionWindow {
+    /// Version prefixes like `2025.` or `2024.3.`.
+    pub prefixes: Vec<String>,
+    /// Inclusive range of release lines like `2024.1..2025.2`.
+    pub range: Option<ReleaseRange>,
+    pub latest_lines: Option<usize>,
+    pub latest_years: Option<usize>,
+}
+
+/// Where the IDE version feeds are fetched from.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Feeds {
+    /// Tried in order if updates.xml can't be fetched.
+    pub jetbrains_mirrors: Vec<String>,
+    /// Tried in order if the Android Studio releases list can't be fetched.
+    pub android_studio_mirrors: Vec<String>,
+    /// If all URLs of a feed fail, use the last copy in the HTTP cache, however old.
+    pub stale_fallback: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AndroidStudio {
+    /// Release channels to generate mappings for. Defaults to all.
+    pub channels: Vec<AndroidStudioChannel>,
+}
+
+impl Default for AndroidStudio {
+    fn default() -> Self {
+        Self {
+            channels: AndroidStudioChannel::ALL.to_vec(),
+        }
+    }
+}
+
+/// Headers of the requests made by the generator. Downloads by `nix-prefetch-url` (see
+/// `--hasher`) don't get them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Http {
+    /// Replaces [`DEFAULT_USER_AGENT`](crate::http_client::DEFAULT_USER_AGENT).
+    pub user_agent: Option<String>,
+    /// Sent with every request.
+    pub headers: BTreeMap<String, String>,
+    /// Host name -> headers only sent to that host, e.g. the credentials of a private mirror.
+    pub host_headers: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Marketplace release channels (e.g. `eap`, `alpha`) considered besides stable, by glob on
+/// plugin IDs. The newest compatible version of them is recorded as the EAP version of a plugin
+/// in the IDE mappings, if it is newer than the stable one.
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct ReleaseChannels(BTreeMap<String, Vec<String>>);
+
+impl ReleaseChannels {
+    /// The channels configured for `pluginkey`, in order and without duplicates.
+    pub fn of(&self, pluginkey: &str) -> Vec<&str> {
+        let mut channels: Vec<&str> = Vec::new();
+        for (pattern, names) in &self.0 {
+            if glob_match(pattern, pluginkey) {
+                for name in names {
+                    if !channels.contains(&name.as_str()) {
+                        channels.push(name);
+                    }
+                }
+            }
+        }
+        channels
+    }
+}
+
+/// Plugins that are never processed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Exclude {
+    /// Globs (`*`, `?`) matched against plugin IDs.
+    pub ids: Vec<String>,
+    /// Globs matched case-insensitively against vendor names from the plugin details.
+    pub vendors: Vec<String>,
+}
+
+impl Config {
+    /// Loads the configuration at `path`, or the defaults if it doesn't exist.
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        if !exists(path)? {
+            return Ok(Self::default());
+        }
+        toml::from_str(&read_to_string(path).await?)
+            .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))
+    }
+}
+
+impl Versions {
+    pub fn window(&self, product: IdeProduct) -> &VersionWindow {
+        self.products.get(&product).unwrap_or(&self.default)
+    }
+}
+
+impl Default for Versions {
+    fn default() -> Self {
+        Self {
+            default: VersionWindow {
+                prefixes: PROCESSED_VERSION_PREFIXES
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+                ..VersionWindow::default()
+            },
+            products: HashMap::new(),
+        }
+    }
+}
+
+impl TryFrom<BTreeMap<String, VersionWindow>> for Versions {
+    type Error = anyhow::Error;
+
+    /// Windows given in the configuration replace the built-in ones.
+    fn try_from(windows: BTreeMap<String, VersionWindow>) -> Result<Self, Self::Error> {
+        let mut versions = Self::default();
+        for (key, window) in windows {
+            if key == "default" {
+                versions.default = window;
+                continue;
+            }
+            let product = IdeProduct::try_from_nix_key(&key)
+                .ok_or_else(|| anyhow!("unknown IDE {key:?} in [versions]"))?;
+            versions.products.insert(product, window);
+        }
+        Ok(versions)
+    }
+}
+
+impl VersionWindow {
+    pub fn matches(&self, version: &str) -> bool {
+        if self.prefixes.is_empty() && self.range.is_none() {
+            return true;
+        }
+        self.prefixes
+            .iter()
+            .any(|prefix| version.starts_with(prefix.as_str()))
+            || self.range.is_some_and(|range| range.contains(version))
+    }
+}
+
+impl Exclude {
+    pub fn excludes_id(&self, pluginkey: &str) -> bool {
+        self.ids
+            .iter()
+            .any(|pattern| glob_match(pattern, pluginkey))
+    }
+
+    pub fn excludes_vendor(&self, vendor: &str) -> bool {
+        let vendor = vendor.trim().to_lowercase();
+        self.vendors
+            .iter()
+            .any(|pattern| glob_match(&pattern.to_lowercase(), &vendor))
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any (possibly empty) string and `?` any
+/// single character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` in the pattern and the text position it was tried at.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, t));
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
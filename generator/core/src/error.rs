@@ -0,0 +1,88 @@
+use crate::hashing::UnpackError;
+use reqwest::StatusCode;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use tokio::time::error::Elapsed;
+
+/// A request got an unsuccessful HTTP status.
+#[derive(Debug)]
+pub struct StatusError {
+    pub what: String,
+    pub status: StatusCode,
+}
+
+impl StatusError {
+    pub fn new(what: impl Into<String>, status: StatusCode) -> Self {
+        Self {
+            what: what.into(),
+            status,
+        }
+    }
+}
+
+impl Display for StatusError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.what, self.status)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// A request that can't be served from the HTTP cache in offline mode.
+#[derive(Debug)]
+pub struct OfflineError {
+    pub url: String,
+}
+
+impl Display for OfflineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: not cached, but running offline", self.url)
+    }
+}
+
+impl std::error::Error for OfflineError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Might go away when retrying, e.g. network errors, timeouts and server errors.
+    Transient,
+    /// Will fail the same way again, e.g. missing artifacts or unparsable responses.
+    Permanent,
+}
+
+/// Classifies an error by the first cause in its chain that is known to be transient or
+/// permanent. Unknown errors are considered transient.
+pub fn classify(error: &anyhow::Error) -> ErrorKind {
+    error
+        .chain()
+        .find_map(|cause| {
+            if let Some(e) = cause.downcast_ref::<StatusError>() {
+                Some(classify_status(e.status))
+            } else if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                Some(match e.status() {
+                    Some(status) => classify_status(status),
+                    None if e.is_decode() => ErrorKind::Permanent,
+                    None => ErrorKind::Transient,
+                })
+            } else if cause.is::<serde_xml_rs::Error>()
+                || cause.is::<serde_json::Error>()
+                || cause.is::<UnpackError>()
+                || cause.is::<OfflineError>()
+            {
+                Some(ErrorKind::Permanent)
+            } else if cause.is::<io::Error>() || cause.is::<Elapsed>() {
+                Some(ErrorKind::Transient)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(ErrorKind::Transient)
+}
+
+fn classify_status(status: StatusCode) -> ErrorKind {
+    match status {
+        StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => ErrorKind::Transient,
+        status if status.is_client_error() => ErrorKind::Permanent,
+        _ => ErrorKind::Transient,
+    }
+}
@@ -0,0 +1,88 @@
+//! Hooks into [`db_update`](crate::plugins::db_update) and the saving of the database, to
+//! observe a run without touching the pipeline.
+
+use crate::error;
+use crate::ides::IdeVersion;
+use crate::plugins::{PluginDbEntry, PluginVersion};
+use log::{debug, info, warn};
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+/// Receives the events of a run. All methods do nothing by default; [`LogEvents`] logs them.
+///
+/// Plugins are processed concurrently, so events of different plugins interleave.
+pub trait GeneratorEvents: Send + Sync {
+    /// Processing of a plugin (or a retry of it) starts.
+    fn on_plugin_started(&self, _pluginkey: &str) {}
+
+    /// A plugin, or one version of it, is left out of the database.
+    fn on_plugin_skipped(&self, _pluginkey: &str, _reason: SkipReason<'_>) {}
+
+    /// A plugin version was added to the database, or its entry changed.
+    fn on_entry_added(&self, _key: &PluginVersion, _entry: &PluginDbEntry) {}
+
+    /// The mapping file of an IDE was written to `path`.
+    fn on_ide_written(&self, _ide: &IdeVersion, _path: &Path) {}
+
+    /// A plugin failed for good, after all retries.
+    fn on_failure(&self, _pluginkey: &str, _error: &anyhow::Error) {}
+}
+
+/// Why a plugin was skipped, see [`GeneratorEvents::on_plugin_skipped`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason<'a> {
+    /// No version of the plugin is compatible with any of the processed IDEs.
+    Incompatible,
+    /// The plugin is known to break the marketplace endpoints.
+    Broken,
+    /// The marketplace has no details for the plugin.
+    NoDetails,
+    /// The plugin's vendor is excluded in the configuration.
+    ExcludedVendor(&'a str),
+    /// The artifact of this version can't be downloaded.
+    Unavailable { version: &'a str },
+}
+
+impl Display for SkipReason<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Incompatible => write!(f, "no IDE supported"),
+            SkipReason::Broken => write!(f, "plugin is marked as broken"),
+            SkipReason::NoDetails => write!(f, "no plugin details available"),
+            SkipReason::ExcludedVendor(vendor) => write!(f, "vendor {vendor} is excluded"),
+            SkipReason::Unavailable { version } => write!(f, "version {version} not available"),
+        }
+    }
+}
+
+/// Logs all events, the default.
+pub struct LogEvents;
+
+impl GeneratorEvents for LogEvents {
+    fn on_plugin_started(&self, pluginkey: &str) {
+        debug!("Processing {pluginkey}...");
+    }
+
+    fn on_plugin_skipped(&self, pluginkey: &str, reason: SkipReason<'_>) {
+        match reason {
+            SkipReason::Incompatible => debug!("{pluginkey}: {reason}."),
+            SkipReason::ExcludedVendor(_) => info!("{pluginkey}: {reason}, skipping."),
+            _ => warn!("{pluginkey}: {reason}, skipping."),
+        }
+    }
+
+    fn on_entry_added(&self, key: &PluginVersion, entry: &PluginDbEntry) {
+        debug!(plugin:% = key.name, version:% = key.version; "{key}: stored {}", entry.hash);
+    }
+
+    fn on_ide_written(&self, ide: &IdeVersion, path: &Path) {
+        debug!(ide:% = ide; "Wrote {}", path.display());
+    }
+
+    fn on_failure(&self, pluginkey: &str, e: &anyhow::Error) {
+        debug!(
+            plugin = pluginkey, kind:? = error::classify(e);
+            "{pluginkey}: giving up: {e:#}"
+        );
+    }
+}
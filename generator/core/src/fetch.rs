@@ -0,0 +1,368 @@
+//! The transport of all metadata requests (IDE feeds, plugin indices, marketplace API), so that
+//! it can be replaced by canned responses, see [`FixtureFetcher`].
+//!
+//! Artifact downloads for hashing go through the [`Hasher`](crate::hashing::Hasher) instead.
+
+use crate::error::OfflineError;
+use crate::http_cache::{self, CachedResponse};
+use crate::http_client::HttpContext;
+use crate::rate_limit;
+use anyhow::{Context, anyhow};
+use futures::future::BoxFuture;
+use reqwest::header::{CONTENT_LENGTH, ETAG};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Makes the HTTP requests of a run.
+pub trait Fetcher: Send + Sync {
+    /// GETs `url`. Responses with any status are returned, see [`CachedResponse::success`].
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, anyhow::Result<CachedResponse>>;
+
+    /// Sends a HEAD request to `url`, following redirects.
+    fn head<'a>(&'a self, url: &'a str) -> BoxFuture<'a, anyhow::Result<HeadResponse>>;
+
+    /// POSTs `body` as JSON to `url`.
+    fn post_json<'a>(
+        &'a self,
+        url: &'a str,
+        body: &'a Value,
+    ) -> BoxFuture<'a, anyhow::Result<CachedResponse>>;
+
+    /// The last successful GET response for `url` regardless of its age, and its age, as a
+    /// fallback when fetching fails. `None` if there is no cache or no entry.
+    fn get_stale<'a>(
+        &'a self,
+        _url: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<Option<(String, Duration)>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Fails with an [`OfflineError`] in offline mode, for downloads that don't go through the
+    /// fetcher.
+    fn check_online(&self, _url: &str) -> Result<(), OfflineError> {
+        Ok(())
+    }
+}
+
+/// The parts of a HEAD response the generator looks at.
+pub struct HeadResponse {
+    pub status: StatusCode,
+    /// The URL after following redirects.
+    pub url: String,
+    pub content_length: Option<u64>,
+    pub etag: Option<String>,
+}
+
+/// Requests over the network, through the HTTP cache (GET only) and the rate limit.
+pub struct HttpFetcher {
+    http: HttpContext,
+    client: Client,
+}
+
+impl HttpFetcher {
+    pub fn new(http: &HttpContext) -> anyhow::Result<Self> {
+        Ok(Self {
+            http: http.clone(),
+            client: http.builder().timeout(Duration::from_secs(600)).build()?,
+        })
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, anyhow::Result<CachedResponse>> {
+        Box::pin(http_cache::get(&self.http, &self.client, url))
+    }
+
+    fn head<'a>(&'a self, url: &'a str) -> BoxFuture<'a, anyhow::Result<HeadResponse>> {
+        Box::pin(async move {
+            self.check_online(url)?;
+            let resp = rate_limit::send(&self.http, self.client.head(url)).await?;
+            let header = |name| {
+                resp.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            Ok(HeadResponse {
+                status: resp.status(),
+                url: resp.url().to_string(),
+                content_length: header(CONTENT_LENGTH).and_then(|len| len.parse().ok()),
+                etag: header(ETAG),
+            })
+        })
+    }
+
+    fn post_json<'a>(
+        &'a self,
+        url: &'a str,
+        body: &'a Value,
+    ) -> BoxFuture<'a, anyhow::Result<CachedResponse>> {
+        Box::pin(async move {
+            self.check_online(url)?;
+            let resp = rate_limit::send(&self.http, self.client.post(url).json(body)).await?;
+            Ok(CachedResponse {
+                status: resp.status(),
+                body: resp.text().await?,
+            })
+        })
+    }
+
+    fn get_stale<'a>(
+        &'a self,
+        url: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<Option<(String, Duration)>>> {
+        Box::pin(http_cache::get_stale(&self.http, url))
+    }
+
+    fn check_online(&self, url: &str) -> Result<(), OfflineError> {
+        http_cache::check_online(&self.http, url)
+    }
+}
+
+/// Sends the requests of another [`Fetcher`] whose URL starts with a prefix to another one, e.g.
+/// to a mock marketplace. URLs that HEAD requests are redirected to are reported with the
+/// original prefix, so that the database stores the upstream download paths.
+pub struct RewritingFetcher {
+    inner: Arc<dyn Fetcher>,
+    /// `(from, to)`, the first matching rule applies.
+    rewrites: Vec<(String, String)>,
+}
+
+impl RewritingFetcher {
+    pub fn new(inner: Arc<dyn Fetcher>, rewrites: Vec<(String, String)>) -> Self {
+        Self { inner, rewrites }
+    }
+
+    fn rewrite(&self, url: &str) -> String {
+        self.rewrites
+            .iter()
+            .find_map(|(from, to)| Some(format!("{to}{}", url.strip_prefix(from.as_str())?)))
+            .unwrap_or_else(|| url.to_string())
+    }
+
+    fn unrewrite(&self, url: &str) -> String {
+        self.rewrites
+            .iter()
+            .find_map(|(from, to)| Some(format!("{from}{}", url.strip_prefix(to.as_str())?)))
+            .unwrap_or_else(|| url.to_string())
+    }
+}
+
+impl Fetcher for RewritingFetcher {
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, anyhow::Result<CachedResponse>> {
+        Box::pin(async move { self.inner.get(&self.rewrite(url)).await })
+    }
+
+    fn head<'a>(&'a self, url: &'a str) -> BoxFuture<'a, anyhow::Result<HeadResponse>> {
+        Box::pin(async move {
+            let mut head = self.inner.head(&self.rewrite(url)).await?;
+            head.url = self.unrewrite(&head.url);
+            Ok(head)
+        })
+    }
+
+    fn post_json<'a>(
+        &'a self,
+        url: &'a str,
+        body: &'a Value,
+    ) -> BoxFuture<'a, anyhow::Result<CachedResponse>> {
+        Box::pin(async move { self.inner.post_json(&self.rewrite(url), body).await })
+    }
+
+    fn get_stale<'a>(
+        &'a self,
+        url: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<Option<(String, Duration)>>> {
+        Box::pin(async move { self.inner.get_stale(&self.rewrite(url)).await })
+    }
+
+    fn check_online(&self, url: &str) -> Result<(), OfflineError> {
+        self.inner.check_online(&self.rewrite(url))
+    }
+}
+
+/// Serves canned responses from a directory instead of the network, for tests and offline
+/// experiments.
+///
+/// The directory contains a `fixtures.json` mapping `"<METHOD> <URL>"` to a response, e.g.
+/// `{"GET https://example.com/a.json": {"file": "a.json"}, "HEAD https://example.com/b.zip":
+/// {"size": 123}}`. `file` is relative to the directory, `status` defaults to 200. Requests
+/// without a fixture fail like requests in offline mode. See `core/testdata/fixtures` for an
+/// example.
+pub struct FixtureFetcher {
+    dir: PathBuf,
+    fixtures: HashMap<String, Fixture>,
+    requests: Mutex<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Fixture {
+    #[serde(default = "status_ok")]
+    status: u16,
+    /// The response body.
+    file: Option<PathBuf>,
+    /// HEAD only: the URL redirected to, defaults to the requested one.
+    url: Option<String>,
+    /// HEAD only: the `Content-Length`.
+    size: Option<u64>,
+    /// HEAD only: the `ETag`.
+    etag: Option<String>,
+}
+
+fn status_ok() -> u16 {
+    200
+}
+
+impl FixtureFetcher {
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let manifest = dir.join("fixtures.json");
+        let fixtures = serde_json::from_str(
+            &std::fs::read_to_string(&manifest)
+                .with_context(|| format!("cannot read {}", manifest.display()))?,
+        )
+        .with_context(|| format!("invalid {}", manifest.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            fixtures,
+            requests: Default::default(),
+        })
+    }
+
+    /// All requests made so far, as `"<METHOD> <URL>"`.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn fixture(&self, method: &str, url: &str) -> anyhow::Result<&Fixture> {
+        let key = format!("{method} {url}");
+        self.requests.lock().unwrap().push(key.clone());
+        self.fixtures.get(&key).ok_or_else(|| {
+            anyhow::Error::new(OfflineError {
+                url: url.to_string(),
+            })
+            .context(format!("no fixture for {key}"))
+        })
+    }
+
+    fn response(&self, method: &str, url: &str) -> anyhow::Result<CachedResponse> {
+        let fixture = self.fixture(method, url)?;
+        let body = match &fixture.file {
+            Some(file) => std::fs::read_to_string(self.dir.join(file))
+                .with_context(|| format!("fixture for {method} {url}"))?,
+            None => String::new(),
+        };
+        Ok(CachedResponse {
+            status: StatusCode::from_u16(fixture.status)
+                .map_err(|e| anyhow!("fixture for {method} {url}: {e}"))?,
+            body,
+        })
+    }
+}
+
+impl Fetcher for FixtureFetcher {
+    fn get<'a>(&'a self, url: &'a str) -> BoxFuture<'a, anyhow::Result<CachedResponse>> {
+        Box::pin(async move { self.response("GET", url) })
+    }
+
+    fn head<'a>(&'a self, url: &'a str) -> BoxFuture<'a, anyhow::Result<HeadResponse>> {
+        Box::pin(async move {
+            let fixture = self.fixture("HEAD", url)?;
+            Ok(HeadResponse {
+                status: StatusCode::from_u16(fixture.status)
+                    .map_err(|e| anyhow!("fixture for HEAD {url}: {e}"))?,
+                url: fixture.url.clone().unwrap_or_else(|| url.to_string()),
+                content_length: fixture.size,
+                etag: fixture.etag.clone(),
+            })
+        })
+    }
+
+    fn post_json<'a>(
+        &'a self,
+        url: &'a str,
+        _body: &'a Value,
+    ) -> BoxFuture<'a, anyhow::Result<CachedResponse>> {
+        Box::pin(async move { self.response("POST", url) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins;
+    use crate::run_context::RunContext;
+
+    const INDEX: &str = "https://downloads.marketplace.jetbrains.com/files/pluginsXMLIds.json";
+
+    fn fetcher() -> FixtureFetcher {
+        FixtureFetcher::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/fixtures"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn plugin_index_from_fixture() {
+        let fetcher = fetcher();
+        let ids = plugins::index(&fetcher, &RunContext::default(), INDEX)
+            .await
+            .unwrap();
+        assert_eq!(
+            ids,
+            [
+                "com.example.fixture",
+                "String Manipulation",
+                "org.example.other"
+            ]
+        );
+        assert_eq!(fetcher.requests(), [format!("GET {INDEX}")]);
+    }
+
+    #[tokio::test]
+    async fn head_from_fixture() {
+        let head = fetcher()
+            .head(
+                "https://plugins.jetbrains.com/plugin/download\
+                 ?pluginId=com.example.fixture&version=1.0.0",
+            )
+            .await
+            .unwrap();
+        assert_eq!(head.status, StatusCode::OK);
+        assert_eq!(
+            head.url,
+            "https://downloads.marketplace.jetbrains.com/files/1/1/fixture-1.0.0.zip"
+        );
+        assert_eq!(head.content_length, Some(885));
+        assert_eq!(head.etag.as_deref(), Some("\"fixture\""));
+    }
+
+    #[tokio::test]
+    async fn fixture_status() {
+        let fetcher = fetcher();
+        let url = "https://downloads.marketplace.jetbrains.com/files/gone.json";
+        let response = fetcher.get(url).await.unwrap();
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert!(
+            plugins::index(&fetcher, &RunContext::default(), url)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_fixture_fails_like_offline_mode() {
+        let fetcher = fetcher();
+        let Err(error) = fetcher
+            .get("https://plugins.jetbrains.com/api/plugins/intellij/unknown")
+            .await
+        else {
+            panic!("expected no fixture");
+        };
+        assert!(error.downcast_ref::<OfflineError>().is_some(), "{error:#}");
+        assert_eq!(fetcher.requests().len(), 1);
+    }
+}
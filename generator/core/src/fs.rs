@@ -0,0 +1,98 @@
+use crate::compression::Compression;
+use serde::Serialize;
+use std::io::{BufWriter, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, read, rename};
+use tokio::io::AsyncWriteExt;
+
+/// Writes `contents` to a temporary file next to `path` and renames it over `path`, so
+/// readers never see a partially written file.
+pub async fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    let tmp_path = tmp_path(path)?;
+
+    let mut file = File::create(&tmp_path).await?;
+    file.write_all(contents.as_ref()).await?;
+    file.sync_all().await?;
+    drop(file);
+    rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Like [`write_atomic`], but leaves `path` untouched if it already has exactly these contents,
+/// so its modification time only changes with its contents. Returns whether it was written.
+pub async fn write_if_changed(path: &Path, contents: impl AsRef<[u8]>) -> anyhow::Result<bool> {
+    match read(path).await {
+        Ok(existing) if existing == contents.as_ref() => return Ok(false),
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    write_atomic(path, contents).await?;
+    Ok(true)
+}
+
+/// Like [`write_if_changed`] for `value` serialized as JSON, but streams the serialization to
+/// the file instead of building it in memory first. Blocking, for large files.
+pub fn write_json_if_changed(
+    path: &Path,
+    value: &impl Serialize,
+    compact: bool,
+) -> anyhow::Result<bool> {
+    let tmp_path = tmp_path(path)?;
+
+    let mut writer = BufWriter::new(std::fs::File::create(&tmp_path)?);
+    if compact {
+        serde_json::to_writer(&mut writer, value)?;
+    } else {
+        serde_json::to_writer_pretty(&mut writer, value)?;
+    }
+    let file = writer.into_inner().map_err(|e| e.into_error())?;
+    file.sync_all()?;
+    drop(file);
+    if same_contents(&tmp_path, path)? {
+        std::fs::remove_file(&tmp_path)?;
+        return Ok(false);
+    }
+    std::fs::rename(&tmp_path, path)?;
+    Ok(true)
+}
+
+/// Compresses the file `src` to `dst`, atomically like [`write_atomic`]. Blocking.
+pub fn compress_file(src: &Path, dst: &Path, compression: Compression) -> anyhow::Result<()> {
+    let tmp_path = tmp_path(dst)?;
+    compression.encode_file(src, &tmp_path)?;
+    std::fs::rename(&tmp_path, dst)?;
+    Ok(())
+}
+
+/// The temporary file `path` is written to before it is renamed over it.
+fn tmp_path(path: &Path) -> anyhow::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("not a file path: {}", path.display()))?;
+    Ok(path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy())))
+}
+
+/// Whether the files `a` and `b` exist and have the same contents, compared in chunks.
+fn same_contents(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let (mut a, mut b) = match (std::fs::File::open(a), std::fs::File::open(b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        (Err(e), _) | (_, Err(e)) => return Err(e),
+    };
+    let mut remaining = a.metadata()?.len();
+    if remaining != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let (mut chunk_a, mut chunk_b) = ([0; 8192], [0; 8192]);
+    while remaining > 0 {
+        let len = remaining.min(chunk_a.len() as u64) as usize;
+        a.read_exact(&mut chunk_a[..len])?;
+        b.read_exact(&mut chunk_b[..len])?;
+        if chunk_a[..len] != chunk_b[..len] {
+            return Ok(false);
+        }
+        remaining -= len as u64;
+    }
+    Ok(true)
+}
@@ -0,0 +1,475 @@
+use crate::artifact_cache::ArtifactCache;
+use crate::error::StatusError;
+use crate::http_client::HttpContext;
+use crate::mirror::Mirror;
+use crate::nar::{NarNode, NarWriter};
+use crate::nix_store::StorePaths;
+use crate::rate_limit;
+use anyhow::{Context, anyhow};
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use lazy_static::lazy_static;
+use log::debug;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use std::collections::btree_map::Entry;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::task::spawn_blocking;
+use which::which;
+use zip::ZipArchive;
+
+lazy_static! {
+    static ref NIX_PREFETCH_URL: PathBuf =
+        which("nix-prefetch-url").expect("nix-prefetch-url not in PATH");
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum HasherKind {
+    /// Download and hash artifacts in-process.
+    #[default]
+    Native,
+    /// Use `nix-prefetch-url` (slower, writes to the Nix store).
+    Nix,
+}
+
+impl HasherKind {
+    /// `mirror` and `cache` are only supported by the native hasher, `nix-prefetch-url` doesn't
+    /// keep the downloaded artifact around. The Nix hasher records the store paths it adds in
+    /// `out_dir`, see [`StorePaths`]. Downloads go through `http`.
+    pub fn build(
+        self,
+        http: &HttpContext,
+        out_dir: &Path,
+        mirror: Option<Mirror>,
+        cache: Option<ArtifactCache>,
+    ) -> anyhow::Result<Arc<dyn Hasher>> {
+        Ok(match (self, mirror, cache) {
+            (HasherKind::Native, mirror, cache) => {
+                Arc::new(NativeHasher::new(http, mirror, cache)?)
+            }
+            (HasherKind::Nix, None, None) => Arc::new(NixHasher::new(http, out_dir)),
+            (HasherKind::Nix, Some(_), _) => {
+                return Err(anyhow!("--mirror is not supported by --hasher nix"));
+            }
+            (HasherKind::Nix, _, Some(_)) => {
+                return Err(anyhow!("--artifact-cache is not supported by --hasher nix"));
+            }
+        })
+    }
+}
+
+/// Computes the hashes Nix expects for plugin artifacts.
+///
+/// `unpack` and `executable` have the same meaning as the `nix-prefetch-url` flags of the same
+/// name; the result is the raw SHA-256 digest of the NAR serialization (recursive hash) if
+/// either of them is set, otherwise of the file itself (flat hash).
+///
+/// If the hasher has a [`mirror`](Hasher::mirror), the artifact is also copied to it as
+/// `mirror_key`, at [`Mirror::location`].
+pub trait Hasher: Send + Sync {
+    fn hash<'a>(
+        &'a self,
+        name: &'a str,
+        url: &'a str,
+        unpack: bool,
+        executable: bool,
+        mirror_key: Option<&'a str>,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<u8>>>;
+
+    fn mirror(&self) -> Option<&Mirror> {
+        None
+    }
+
+    /// Cleans up after the run, e.g. what hashing left in the Nix store.
+    fn finish(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The artifact can't be unpacked the way Nix would unpack it, e.g. because its ZIP contains
+/// invalid file names. It can still be hashed (and fetched) without unpacking.
+#[derive(Debug)]
+pub struct UnpackError(pub String);
+
+impl Display for UnpackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot unpack artifact: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnpackError {}
+
+/// Shells out to `nix-prefetch-url`. The store paths it adds are deleted by
+/// [`Hasher::finish`].
+pub struct NixHasher {
+    http: HttpContext,
+    store_paths: StorePaths,
+}
+
+impl NixHasher {
+    pub fn new(http: &HttpContext, out_dir: &Path) -> Self {
+        Self {
+            http: http.clone(),
+            store_paths: StorePaths::new(out_dir),
+        }
+    }
+}
+
+impl Hasher for NixHasher {
+    fn hash<'a>(
+        &'a self,
+        name: &'a str,
+        url: &'a str,
+        unpack: bool,
+        executable: bool,
+        _mirror_key: Option<&'a str>,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let (hash_nix32, store_path) =
+                get_nix32_hash(&self.http, name, url, unpack, executable).await?;
+            self.store_paths.record(&store_path).await?;
+            nix_base32::from_nix_base32(&hash_nix32)
+                .ok_or_else(|| anyhow!("{url}: failed decoding nix hash"))
+        })
+    }
+
+    fn finish(&self) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(self.store_paths.delete_all())
+    }
+}
+
+async fn get_nix32_hash(
+    http: &HttpContext,
+    name: &str,
+    url: &str,
+    unpack: bool,
+    executable: bool,
+) -> anyhow::Result<(String, String)> {
+    let mut parameters = Vec::with_capacity(8);
+    parameters.push("--print-path");
+    parameters.push("--type");
+    parameters.push("sha256");
+    parameters.push("--name");
+    parameters.push(name);
+    if unpack {
+        parameters.push("--unpack");
+    }
+    if executable {
+        parameters.push("--executable");
+    }
+    parameters.push(url);
+
+    rate_limit::acquire(http).await;
+    let mut command = Command::new(&*NIX_PREFETCH_URL);
+    if let Some(proxy) = http.proxy_url() {
+        command.env("http_proxy", proxy).env("https_proxy", proxy);
+    }
+    let child = command
+        .args(parameters)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let result = child.wait_with_output().await?;
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        // Nix refuses to unpack archives with names it can't represent in the store.
+        if unpack && (stderr.contains("invalid file name") || stderr.contains("bad archive")) {
+            return Err(UnpackError(stderr.trim().to_string()).into());
+        }
+        return Err(anyhow!(
+            "nix-prefetch-url failed for {url}: {}",
+            stderr.trim()
+        ));
+    }
+    let out = String::from_utf8(result.stdout)?.trim().to_string();
+    let Some((hash, path)) = &out.split_once('\n') else {
+        return Err(anyhow!(
+            "nix-prefetch-url generated invalid output to stdout: {out}"
+        ));
+    };
+
+    Ok((hash.to_string(), path.to_string()))
+}
+
+/// Streams the artifact into a temporary file (or the [`ArtifactCache`]) and hashes it
+/// in-process, without touching the Nix store.
+pub struct NativeHasher {
+    http: HttpContext,
+    client: Client,
+    mirror: Option<Mirror>,
+    cache: Option<ArtifactCache>,
+}
+
+impl NativeHasher {
+    pub fn new(
+        http: &HttpContext,
+        mirror: Option<Mirror>,
+        cache: Option<ArtifactCache>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            http: http.clone(),
+            client: http.builder().timeout(Duration::from_secs(1200)).build()?,
+            mirror,
+            cache,
+        })
+    }
+
+    async fn download(&self, url: &str) -> anyhow::Result<File> {
+        if let Some(cache) = &self.cache
+            && let Some(file) = cache.get(url).await?
+        {
+            debug!("{url}: using the cached artifact");
+            return Ok(file);
+        }
+        let resp = rate_limit::send(&self.http, self.client.get(url)).await?;
+        if !resp.status().is_success() {
+            return Err(StatusError::new(format!("{url}: download failed"), resp.status()).into());
+        }
+        let download = match &self.cache {
+            Some(cache) => Some(cache.download_file()?),
+            None => None,
+        };
+        let std_file = match &download {
+            Some(download) => download.reopen()?,
+            None => tempfile::tempfile()?,
+        };
+        let mut file = tokio::fs::File::from_std(std_file);
+        let mut stream = resp.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            rate_limit::throttle_download(&self.http, chunk.len()).await;
+            tokio::io::AsyncWriteExt::write_all(&mut file, &chunk).await?;
+        }
+        tokio::io::AsyncWriteExt::flush(&mut file).await?;
+        let mut file = file.into_std().await;
+        if let Some((cache, download)) = self.cache.as_ref().zip(download) {
+            file = cache.insert(url, download).await?;
+        }
+        file.rewind()?;
+        Ok(file)
+    }
+}
+
+impl Hasher for NativeHasher {
+    fn hash<'a>(
+        &'a self,
+        _name: &'a str,
+        url: &'a str,
+        unpack: bool,
+        executable: bool,
+        mirror_key: Option<&'a str>,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<u8>>> {
+        Box::pin(async move {
+            let mut file = self.download(url).await?;
+            if let Some((mirror, key)) = self.mirror.as_ref().zip(mirror_key) {
+                mirror
+                    .store(&mut file, key)
+                    .await
+                    .with_context(|| format!("{url}: failed mirroring to {mirror}"))?;
+            }
+            let url = url.to_string();
+            spawn_blocking(move || {
+                if unpack {
+                    hash_unpacked_zip(file)
+                } else if executable {
+                    hash_single_file(file, true)
+                } else {
+                    hash_flat(file)
+                }
+                .map_err(|e| e.context(format!("{url}: failed hashing")))
+            })
+            .await?
+        })
+    }
+
+    fn mirror(&self) -> Option<&Mirror> {
+        self.mirror.as_ref()
+    }
+}
+
+fn hash_flat(mut file: File) -> anyhow::Result<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+fn hash_single_file(file: File, executable: bool) -> anyhow::Result<Vec<u8>> {
+    let root = NarNode::Regular {
+        executable,
+        size: file.metadata()?.len(),
+        contents: (),
+    };
+    let mut file = file;
+    let mut writer = NarWriter::new(Sha256::new());
+    writer.write_nar(&root, &mut |(), out| io::copy(&mut file, out))?;
+    Ok(writer.into_inner().finalize().to_vec())
+}
+
+/// Hashes a ZIP the way `nix-prefetch-url --unpack` (and `fetchzip`) would see it: unpacked,
+/// with a single top-level entry becoming the root.
+fn hash_unpacked_zip(file: File) -> anyhow::Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(file)?;
+
+    let mut root = NarNode::empty_directory();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        let Some(path) = entry.enclosed_name() else {
+            return Err(UnpackError(format!(
+                "ZIP contains invalid file name: {:?}",
+                entry.name()?
+            ))
+            .into());
+        };
+        let node = if entry.is_dir() {
+            NarNode::empty_directory()
+        } else if entry.is_symlink() {
+            let mut target = String::new();
+            entry.read_to_string(&mut target)?;
+            NarNode::Symlink(target)
+        } else {
+            NarNode::Regular {
+                executable: entry.unix_mode().is_some_and(|mode| mode & 0o100 != 0),
+                size: entry.size(),
+                contents: index,
+            }
+        };
+        insert_into_tree(&mut root, &path, node)?;
+    }
+
+    let root = match root {
+        NarNode::Directory(mut entries) if entries.len() == 1 => {
+            entries.pop_first().expect("length checked").1
+        }
+        root => root,
+    };
+
+    let mut writer = NarWriter::new(Sha256::new());
+    writer.write_nar(&root, &mut |index, out| {
+        io::copy(
+            &mut archive.by_index(*index).map_err(io::Error::other)?,
+            out,
+        )
+    })?;
+    Ok(writer.into_inner().finalize().to_vec())
+}
+
+fn insert_into_tree<C>(root: &mut NarNode<C>, path: &Path, node: NarNode<C>) -> anyhow::Result<()> {
+    let mut components = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .peekable();
+
+    let mut current = root;
+    while let Some(name) = components.next() {
+        let NarNode::Directory(entries) = current else {
+            return Err(
+                UnpackError(format!("ZIP entry {} is nested in a file", path.display())).into(),
+            );
+        };
+        if components.peek().is_none() {
+            match entries.entry(name) {
+                // Explicit directory entries may come after files inside of them.
+                Entry::Occupied(existing) if matches!(node, NarNode::Directory(_)) => {
+                    if !matches!(existing.get(), NarNode::Directory(_)) {
+                        return Err(UnpackError(format!(
+                            "ZIP entry {} is both file and directory",
+                            path.display()
+                        ))
+                        .into());
+                    }
+                }
+                Entry::Occupied(mut existing) => {
+                    existing.insert(node);
+                }
+                Entry::Vacant(vacant) => {
+                    vacant.insert(node);
+                }
+            }
+            return Ok(());
+        }
+        current = entries.entry(name).or_insert_with(NarNode::empty_directory);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    //! The expected hashes are what `nix-prefetch-url [--executable|--unpack] file://...`
+    //! should print for the files in `testdata/hashing`. They were computed with a separate
+    //! (Python) NAR implementation that unpacks the ZIPs to disk first, not with Nix itself;
+    //! re-check them with Nix when changing the fixtures.
+
+    use super::*;
+
+    fn fixture(name: &str) -> File {
+        File::open(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("testdata/hashing")
+                .join(name),
+        )
+        .unwrap()
+    }
+
+    fn nix32(hash: anyhow::Result<Vec<u8>>) -> String {
+        nix_base32::to_nix_base32(&hash.unwrap())
+    }
+
+    #[test]
+    fn flat_hash() {
+        assert_eq!(
+            nix32(hash_flat(fixture("plugin.jar"))),
+            "1201z7206i1vm2bgxbv9zawpjsfvk59gn8fxzlfscj4bw3bcjnzj"
+        );
+    }
+
+    #[test]
+    fn single_file_hash() {
+        assert_eq!(
+            nix32(hash_single_file(fixture("plugin.jar"), false)),
+            "1ifsp86jcrzz5c9hd6pcw2iqfr7l3za9fx2naaalxvn7bf1z6jh6"
+        );
+        assert_eq!(
+            nix32(hash_single_file(fixture("plugin.jar"), true)),
+            "0pl8zg478xlpzv56mgah4cj2piz5divvncwxc3vhmbxyf35zv3pn"
+        );
+    }
+
+    #[test]
+    fn unpacked_hash_of_single_root() {
+        // `fixture/` with an executable, a symlink, an empty directory and an explicit
+        // directory entry after a file in it.
+        assert_eq!(
+            nix32(hash_unpacked_zip(fixture("plugin.zip"))),
+            "17i7xn4pdd18xchnal8ng32jzq5nlfp3waajijsk3c7rqspkwbdq"
+        );
+    }
+
+    #[test]
+    fn unpacked_hash_of_several_roots() {
+        assert_eq!(
+            nix32(hash_unpacked_zip(fixture("multi-root.zip"))),
+            "0a8h4x7nwlgjay0kcgbqakvwbah83a967pifs983lqhvaq0kqjly"
+        );
+    }
+
+    #[test]
+    fn unpacking_rejects_files_that_are_not_zips() {
+        let mut file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut file, b"not a zip").unwrap();
+        file.rewind().unwrap();
+        assert!(hash_unpacked_zip(file).is_err());
+    }
+}
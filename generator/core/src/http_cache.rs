@@ -0,0 +1,181 @@
+use crate::error::{OfflineError, StatusError};
+use crate::fs::write_atomic;
+use crate::http_client::HttpContext;
+use crate::rate_limit;
+use log::{debug, warn};
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::exists;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{create_dir_all, read_to_string};
+
+/// See [`HttpContext::with_cache`].
+pub(crate) struct HttpCache {
+    dir: PathBuf,
+    max_age: Duration,
+    offline: bool,
+}
+
+/// Fails with an [`OfflineError`] in offline mode, for requests that are never cached.
+pub fn check_online(http: &HttpContext, url: &str) -> Result<(), OfflineError> {
+    match &http.cache {
+        Some(cache) if cache.offline => Err(OfflineError {
+            url: url.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+/// A response body, possibly from the cache.
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl CachedResponse {
+    /// Fails with a [`StatusError`] unless the request was successful.
+    pub fn success(self, what: &str) -> anyhow::Result<Self> {
+        if self.status.is_success() {
+            Ok(self)
+        } else {
+            Err(StatusError::new(what, self.status).into())
+        }
+    }
+
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_str(&self.body)
+    }
+}
+
+impl HttpCache {
+    pub(crate) fn new(dir: PathBuf, max_age: Duration, offline: bool) -> Self {
+        Self {
+            dir,
+            max_age,
+            offline,
+        }
+    }
+
+    fn entry_path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:x}.json", Sha256::digest(url.as_bytes())))
+    }
+}
+
+async fn load_entry(path: &Path, url: &str) -> anyhow::Result<Option<CacheEntry>> {
+    if !exists(path)? {
+        return Ok(None);
+    }
+    match serde_json::from_str::<CacheEntry>(&read_to_string(path).await?) {
+        Ok(entry) if entry.url == url => Ok(Some(entry)),
+        Ok(_) => Ok(None),
+        Err(e) => {
+            warn!("{}: ignoring broken cache entry: {e}", path.display());
+            Ok(None)
+        }
+    }
+}
+
+/// The last successful response for `url` regardless of its age, and its age. `None` if the
+/// cache is disabled or has no entry.
+pub async fn get_stale(
+    http: &HttpContext,
+    url: &str,
+) -> anyhow::Result<Option<(String, Duration)>> {
+    let Some(cache) = &http.cache else {
+        return Ok(None);
+    };
+    let Some(entry) = load_entry(&cache.entry_path(url), url).await? else {
+        return Ok(None);
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let age = Duration::from_secs(now.saturating_sub(entry.fetched_at));
+    Ok(Some((entry.body, age)))
+}
+
+/// GETs `url` through the cache (if configured) and the rate limit.
+pub async fn get(http: &HttpContext, client: &Client, url: &str) -> anyhow::Result<CachedResponse> {
+    let Some(cache) = &http.cache else {
+        let resp = rate_limit::send(http, client.get(url)).await?;
+        let status = resp.status();
+        let body = resp.text().await?;
+        http.run.add_downloaded(body.len() as u64);
+        return Ok(CachedResponse { status, body });
+    };
+
+    let path = cache.entry_path(url);
+    let cached = load_entry(&path, url).await?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let mut request = client.get(url);
+    if let Some(entry) = &cached {
+        if cache.offline || now.saturating_sub(entry.fetched_at) < cache.max_age.as_secs() {
+            debug!("{url}: served from cache");
+            return Ok(CachedResponse {
+                status: StatusCode::OK,
+                body: entry.body.clone(),
+            });
+        }
+        if let Some(etag) = &entry.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &entry.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    check_online(http, url)?;
+
+    let resp = rate_limit::send(http, request).await?;
+    let status = resp.status();
+    let entry = match cached {
+        Some(mut entry) if status == StatusCode::NOT_MODIFIED => {
+            debug!("{url}: revalidated cache entry");
+            entry.fetched_at = now;
+            entry
+        }
+        _ if status.is_success() => {
+            let header = |name| {
+                resp.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            let etag = header(ETAG);
+            let last_modified = header(LAST_MODIFIED);
+            let body = resp.text().await?;
+            http.run.add_downloaded(body.len() as u64);
+            CacheEntry {
+                url: url.to_string(),
+                fetched_at: now,
+                etag,
+                last_modified,
+                body,
+            }
+        }
+        _ => {
+            let body = resp.text().await?;
+            http.run.add_downloaded(body.len() as u64);
+            return Ok(CachedResponse { status, body });
+        }
+    };
+    create_dir_all(&cache.dir).await?;
+    write_atomic(&path, serde_json::to_string(&entry)?).await?;
+    Ok(CachedResponse {
+        status: StatusCode::OK,
+        body: entry.body,
+    })
+}
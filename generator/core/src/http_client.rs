@@ -0,0 +1,207 @@
+use crate::config::Http;
+use crate::http_cache::HttpCache;
+use crate::rate_limit::RateLimiter;
+use crate::run_context::RunContext;
+use anyhow::anyhow;
+use reqwest::header::{AUTHORIZATION, HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Certificate, Client, ClientBuilder, Proxy, Request};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Identifies the generator as a bulk consumer of the marketplace.
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "nix-jetbrains-plugins-generator/",
+    env!("CARGO_PKG_VERSION"),
+    " (+",
+    env!("CARGO_PKG_REPOSITORY"),
+    ")"
+);
+
+/// Hosts that get the marketplace token.
+const MARKETPLACE_HOSTS: &[&str] = &[
+    "plugins.jetbrains.com",
+    "downloads.marketplace.jetbrains.com",
+];
+
+/// Proxy, certificates, User-Agent and headers of all HTTP clients. The default is reqwest's
+/// defaults, which include honoring `HTTP(S)_PROXY`.
+#[derive(Default)]
+pub struct HttpConfig {
+    proxy_url: Option<String>,
+    proxy: Option<Proxy>,
+    extra_ca_certs: Vec<Certificate>,
+    /// `Authorization` header for marketplace requests.
+    marketplace_auth: Option<HeaderValue>,
+    /// [`DEFAULT_USER_AGENT`] if unset.
+    user_agent: Option<String>,
+    /// Sent with every request.
+    headers: HeaderMap,
+    /// Host name -> headers only sent to that host.
+    host_headers: HashMap<String, HeaderMap>,
+}
+
+impl HttpConfig {
+    /// Routes all requests through `proxy` (instead of the one from `HTTP(S)_PROXY`) and
+    /// trusts the certificates in the PEM files `extra_ca_certs` in addition to the system
+    /// ones. Marketplace requests are authorized with `marketplace_token`. The User-Agent and
+    /// additional headers come from `http`.
+    pub fn new(
+        proxy_url: Option<&str>,
+        extra_ca_certs: &[PathBuf],
+        marketplace_token: Option<&str>,
+        http: &Http,
+    ) -> anyhow::Result<Self> {
+        let proxy = proxy_url.map(Proxy::all).transpose()?;
+        let extra_ca_certs = extra_ca_certs
+            .iter()
+            .map(|path| {
+                let pem = std::fs::read(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+                match Certificate::from_pem_bundle(&pem) {
+                    Ok(certs) if !certs.is_empty() => Ok(certs),
+                    Ok(_) => Err(anyhow!("{}: no PEM certificates found", path.display())),
+                    Err(e) => Err(anyhow!("{}: invalid PEM certificate: {e}", path.display())),
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+        let marketplace_auth = marketplace_token
+            .map(|token| {
+                let mut value = HeaderValue::from_str(&format!("Bearer {}", token.trim()))?;
+                value.set_sensitive(true);
+                anyhow::Ok(value)
+            })
+            .transpose()?;
+        Ok(Self {
+            proxy_url: proxy_url.map(str::to_string),
+            proxy,
+            extra_ca_certs,
+            marketplace_auth,
+            user_agent: http.user_agent.clone(),
+            headers: header_map(&http.headers, false)?,
+            host_headers: http
+                .host_headers
+                .iter()
+                .map(|(host, headers)| Ok((host.clone(), header_map(headers, true)?)))
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+}
+
+/// What all requests of a run go through: the [`HttpConfig`] of the clients, the rate limits
+/// and the HTTP cache. Cheap to clone. The default has reqwest's defaults, no limits and no
+/// cache.
+#[derive(Clone, Default)]
+pub struct HttpContext {
+    config: Arc<HttpConfig>,
+    /// Shared by all marketplace requests. Unset means unlimited.
+    pub(crate) requests: Option<Arc<RateLimiter>>,
+    /// Shared by all artifact downloads, in bytes. Unset means unlimited.
+    pub(crate) bandwidth: Option<Arc<RateLimiter>>,
+    /// Shared by all GET requests of metadata. Unset means no caching.
+    pub(crate) cache: Option<Arc<HttpCache>>,
+    /// Counts the requests and downloaded bytes.
+    pub(crate) run: RunContext,
+}
+
+/// Parses configured headers. Headers for a single host are likely credentials, so they are
+/// marked `sensitive` to keep them out of debug output.
+fn header_map(headers: &BTreeMap<String, String>, sensitive: bool) -> anyhow::Result<HeaderMap> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| anyhow!("invalid header name {name:?}: {e}"))?;
+            let mut value = HeaderValue::from_str(value)
+                .map_err(|e| anyhow!("invalid value of header {name}: {e}"))?;
+            value.set_sensitive(sensitive);
+            Ok((name, value))
+        })
+        .collect()
+}
+
+impl HttpContext {
+    pub fn new(config: HttpConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            ..Self::default()
+        }
+    }
+
+    /// Limits all marketplace requests to `requests_per_second` on average, allowing bursts
+    /// of `burst` requests. A rate of 0 disables limiting, but HTTP 429 is still honored.
+    pub fn with_rate_limit(mut self, requests_per_second: f64, burst: u32) -> Self {
+        let limiter = RateLimiter::new(requests_per_second, f64::from(burst.max(1)));
+        self.requests = Some(Arc::new(limiter));
+        self
+    }
+
+    /// Limits all artifact downloads together to `bytes_per_second`, allowing bursts of a
+    /// second's worth. A rate of 0 disables limiting.
+    pub fn with_bandwidth_limit(mut self, bytes_per_second: f64) -> Self {
+        let limiter = RateLimiter::new(bytes_per_second, bytes_per_second.max(1.0));
+        self.bandwidth = Some(Arc::new(limiter));
+        self
+    }
+
+    /// Caches successful GET responses in `dir`. Responses younger than `max_age` are served
+    /// without a request, older ones are revalidated with their ETag or Last-Modified date.
+    /// If `offline`, cached responses of any age are served and everything else fails.
+    pub fn with_cache(mut self, dir: PathBuf, max_age: Duration, offline: bool) -> Self {
+        self.cache = Some(Arc::new(HttpCache::new(dir, max_age, offline)));
+        self
+    }
+
+    /// Counts requests and downloaded bytes in `run`.
+    pub fn with_run(mut self, run: &RunContext) -> Self {
+        self.run = run.clone();
+        self
+    }
+
+    /// The configured proxy, for tools other than reqwest.
+    pub fn proxy_url(&self) -> Option<&str> {
+        self.config.proxy_url.as_deref()
+    }
+
+    /// A client builder with the configured proxy, certificates, User-Agent and headers.
+    pub fn builder(&self) -> ClientBuilder {
+        let config = &self.config;
+        let mut builder = Client::builder()
+            .user_agent(config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+            .default_headers(config.headers.clone());
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        for cert in &config.extra_ca_certs {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        builder
+    }
+
+    /// A client with the configured proxy, certificates, User-Agent and headers.
+    pub fn client(&self) -> anyhow::Result<Client> {
+        Ok(self.builder().build()?)
+    }
+
+    /// Adds the marketplace token to `request`, if configured and the request goes to the
+    /// marketplace, and the headers configured for the host of `request`.
+    pub fn authorize(&self, request: &mut Request) {
+        let config = &self.config;
+        let Some(host) = request.url().host_str().map(str::to_string) else {
+            return;
+        };
+        if let Some(auth) = &config.marketplace_auth
+            && MARKETPLACE_HOSTS.contains(&host.as_str())
+        {
+            request.headers_mut().insert(AUTHORIZATION, auth.clone());
+        }
+        if let Some(headers) = config.host_headers.get(&host) {
+            for (name, value) in headers {
+                request.headers_mut().insert(name, value.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::config::{Feeds, Versions};
+use crate::fetch::Fetcher;
+use crate::ides::{IdeProduct, IdeVersion, ReleaseRange, allowed_build_version, fetch_feed};
+use crate::run_context::RunContext;
+use anyhow::anyhow;
+use log::{debug, warn};
+use serde::Deserialize;
+
+const ANDROID_STUDIO_VERSIONS: &str = "https://jb.gg/android-studio-releases-list.json";
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Body {
+    content: Content,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Content {
+    item: Vec<Item>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Item {
+    version: String,
+    build: String,
+    #[serde(rename = "platformBuild")]
+    platform_build: String,
+    channel: String,
+}
+
+/// Release channels in the Android Studio releases list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AndroidStudioChannel {
+    Release,
+    Beta,
+    Canary,
+    Rc,
+    Patch,
+}
+
+impl AndroidStudioChannel {
+    pub const ALL: [Self; 5] = [
+        Self::Release,
+        Self::Beta,
+        Self::Canary,
+        Self::Rc,
+        Self::Patch,
+    ];
+
+    fn from_list(channel: &str) -> Option<Self> {
+        match channel.to_lowercase().as_str() {
+            "release" => Some(Self::Release),
+            "beta" => Some(Self::Beta),
+            "canary" => Some(Self::Canary),
+            "rc" => Some(Self::Rc),
+            "patch" => Some(Self::Patch),
+            _ => None,
+        }
+    }
+}
+
+/// Collects the Android Studio versions in `channels`. Versions in channels unknown to us are
+/// always included.
+pub async fn collect_ids(
+    fetcher: &dyn Fetcher,
+    run: &RunContext,
+    channels: &[AndroidStudioChannel],
+    feeds: &Feeds,
+    windows: &Versions,
+    backfill: Option<ReleaseRange>,
+) -> anyhow::Result<Vec<IdeVersion>> {
+    let body: Body = serde_json::from_str(
+        &fetch_feed(
+            fetcher,
+            run,
+            "Android Studio versions",
+            ANDROID_STUDIO_VERSIONS,
+            &feeds.android_studio_mirrors,
+            feeds,
+        )
+        .await?,
+    )?;
+
+    let mut versions: Vec<IdeVersion> = Vec::new();
+
+    for item in body.content.item {
+        if !item.build.starts_with("AI-") {
+            return Err(anyhow!(
+                "Unexpected product code: {} don't start with AI",
+                item.build
+            ));
+        }
+        if let Some(channel) = AndroidStudioChannel::from_list(&item.channel)
+            && !channels.contains(&channel)
+        {
+            debug!(
+                "Ignoring {} {}: {} channel not selected",
+                IdeProduct::AndroidStudio.nix_key(),
+                item.version,
+                item.channel
+            );
+            continue;
+        }
+
+        if allowed_build_version(IdeProduct::AndroidStudio, &item.version, windows, backfill) {
+            versions.push(IdeVersion {
+                ide: IdeProduct::AndroidStudio,
+                version: item.version,
+                build_number: item.platform_build,
+            })
+        } else {
+            warn!(
+                "Ignoring {} {}: too old",
+                IdeProduct::AndroidStudio.nix_key(),
+                item.version
+            );
+        }
+    }
+
+    Ok(versions)
+}
@@ -0,0 +1,148 @@
+use crate::build_number::BuildNumber;
+use crate::config::{Feeds, Versions};
+use crate::fetch::Fetcher;
+use crate::ides::{
+    IdeChannel, IdeProduct, IdeVersion, ReleaseRange, UnknownProduct, allowed_build_version,
+    fetch_feed,
+};
+use crate::run_context::RunContext;
+use log::warn;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+const JETBRAINS_VERSIONS: &str = "https://www.jetbrains.com/updates/updates.xml";
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Products {
+    product: Vec<Product>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct Product {
+    #[serde(rename = "@name")]
+    name: Option<String>,
+    code: Vec<String>,
+    channel: Option<Vec<Channel>>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub struct Channel {
+    #[serde(rename = "@id")]
+    id: String,
+    #[serde(rename = "@status")]
+    status: Option<String>,
+    build: Vec<Build>,
+}
+
+impl Channel {
+    fn ide_channel(&self) -> Option<IdeChannel> {
+        if self.id.ends_with("RELEASE-licensing-RELEASE") {
+            return Some(IdeChannel::Stable);
+        }
+        let status = match &self.status {
+            Some(status) => status.to_lowercase(),
+            // e.g. IC-IU-EAP-licensing-EAP
+            None => self.id.rsplit('-').next()?.to_lowercase(),
+        };
+        Some(match status.as_str() {
+            "eap" => IdeChannel::Eap,
+            "beta" => IdeChannel::Beta,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Clone)]
+pub struct Build {
+    #[serde(rename = "@number")]
+    number: String,
+    #[serde(rename = "@fullNumber")]
+    full_number: Option<String>,
+    #[serde(rename = "@version")]
+    version: String,
+}
+
+pub async fn collect_ids(
+    fetcher: &dyn Fetcher,
+    run: &RunContext,
+    channels: &[IdeChannel],
+    feeds: &Feeds,
+    windows: &Versions,
+    backfill: Option<ReleaseRange>,
+) -> anyhow::Result<(Vec<IdeVersion>, Vec<UnknownProduct>)> {
+    let products: Products = serde_xml_rs::from_str(
+        &fetch_feed(
+            fetcher,
+            run,
+            "JetBrains IDE versions",
+            JETBRAINS_VERSIONS,
+            &feeds.jetbrains_mirrors,
+            feeds,
+        )
+        .await?,
+    )?;
+
+    let mut already_processed = HashSet::new();
+    let mut versions: Vec<IdeVersion> = Vec::new();
+    let mut unknown_products = Vec::new();
+
+    for product in products.product {
+        if !product
+            .code
+            .iter()
+            .any(|code| IdeProduct::try_from_code(code).is_some())
+        {
+            unknown_products.push(UnknownProduct {
+                name: product.name.unwrap_or_default(),
+                codes: product.code,
+            });
+            continue;
+        }
+        for code in product.code {
+            if let Some(ideobj) = IdeProduct::try_from_code(&code)
+                && already_processed.insert(ideobj)
+                && let Some(product_channels) = product.channel.as_ref()
+            {
+                // Pre-release builds may share a version label; keep the newest build for each.
+                let mut prerelease: HashMap<String, (BuildNumber, IdeVersion)> = HashMap::new();
+                for channel in product_channels {
+                    let Some(ide_channel) = channel.ide_channel() else {
+                        continue;
+                    };
+                    if !channels.contains(&ide_channel) {
+                        continue;
+                    }
+                    for build in &channel.build {
+                        if !allowed_build_version(ideobj, &build.version, windows, backfill) {
+                            warn!("Ignoring {} {}: too old", ideobj.nix_key(), build.version);
+                            continue;
+                        }
+                        let ideversion = IdeVersion {
+                            ide: ideobj,
+                            version: ide_channel.version_label(&build.version),
+                            build_number: build
+                                .full_number
+                                .as_ref()
+                                .map_or_else(|| build.number.clone(), Clone::clone),
+                        };
+                        if ide_channel == IdeChannel::Stable {
+                            versions.push(ideversion);
+                            continue;
+                        }
+                        let build_number: BuildNumber = ideversion.build_number.parse()?;
+                        match prerelease.get(&ideversion.version) {
+                            Some((newest, _)) if *newest >= build_number => {}
+                            _ => {
+                                prerelease
+                                    .insert(ideversion.version.clone(), (build_number, ideversion));
+                            }
+                        }
+                    }
+                }
+                versions.extend(prerelease.into_values().map(|(_, ideversion)| ideversion));
+            }
+        }
+    }
+
+    Ok((versions, unknown_products))
+}
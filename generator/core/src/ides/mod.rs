@@ -0,0 +1,275 @@
+mod android_studio;
+mod jetbrains;
+pub mod nixpkgs;
+mod registry;
+
+pub use android_studio::AndroidStudioChannel;
+pub use registry::IdeProduct;
+
+use crate::config::{Config, Feeds, Versions};
+use crate::fetch::Fetcher;
+use crate::run_context::RunContext;
+use anyhow::anyhow;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::iter;
+use std::str::FromStr;
+
+/// Versions processed of IDEs without a `[versions]` entry in the configuration.
+pub const PROCESSED_VERSION_PREFIXES: &[&str] = &["2027.", "2026.", "2025.", "2024.3."];
+
+/// Release channels of JetBrains IDEs in updates.xml.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum IdeChannel {
+    Stable,
+    Eap,
+    Beta,
+}
+
+impl IdeChannel {
+    /// Version label used in the IDE JSON filename, e.g. `2025.3` or `2025.3-eap`.
+    /// Versions that already name their channel (like `2025.3 EAP 3`) become `2025.3-eap3`.
+    fn version_label(&self, version: &str) -> String {
+        let suffix = match self {
+            IdeChannel::Stable => return version.to_string(),
+            IdeChannel::Eap => "eap",
+            IdeChannel::Beta => "beta",
+        };
+        let version = version.to_lowercase();
+        match version.split_once(suffix) {
+            Some((base, counter)) => format!(
+                "{}-{suffix}{}",
+                base.trim().trim_end_matches('-'),
+                counter.trim().replace(' ', "")
+            ),
+            None => format!("{}-{suffix}", version.trim()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub struct IdeVersion {
+    pub ide: IdeProduct,
+    pub version: String,
+    pub build_number: String,
+}
+
+impl IdeVersion {
+    /// Create from a JSON filename.
+    /// WARNING: Does not populate build number!
+    pub fn from_json_filename(filename: &str) -> Option<Self> {
+        let filename = filename.strip_suffix(".json")?;
+        // Both nix keys and version labels (`2025.3-eap`) may contain dashes, but versions
+        // always start with a digit.
+        let split = filename
+            .match_indices('-')
+            .find(|(i, _)| filename[i + 1..].starts_with(|c: char| c.is_ascii_digit()))?
+            .0;
+        let (product, version) = (&filename[..split], &filename[split + 1..]);
+        Some(Self {
+            ide: IdeProduct::try_from_nix_key(product)?,
+            version: version.to_string(),
+            build_number: "".to_string(),
+        })
+    }
+
+    pub fn to_json_filename(&self) -> String {
+        format!("{self}.json")
+    }
+}
+
+impl Display for IdeVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ide.nix_key(), self.version)
+    }
+}
+
+/// A product in updates.xml none of whose codes is known, e.g. a newly released IDE.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnknownProduct {
+    pub name: String,
+    pub codes: Vec<String>,
+}
+
+impl Display for UnknownProduct {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name, self.codes.join(", "))
+    }
+}
+
+/// Collects the IDE versions to process, and the products in updates.xml that were skipped
+/// because their product codes are unknown.
+/// Versions in `backfill` are processed in addition to the ones selected by
+/// [`Config::versions`]. The feeds are recorded in `run`.
+pub async fn collect_ids(
+    fetcher: &dyn Fetcher,
+    run: &RunContext,
+    channels: &[IdeChannel],
+    config: &Config,
+    backfill: Option<ReleaseRange>,
+) -> anyhow::Result<(Vec<IdeVersion>, Vec<UnknownProduct>)> {
+    let versions = &config.versions;
+    let ((jetbrains, unknown_products), android_studio) = tokio::try_join!(
+        jetbrains::collect_ids(fetcher, run, channels, &config.feeds, versions, backfill),
+        android_studio::collect_ids(
+            fetcher,
+            run,
+            &config.android_studio.channels,
+            &config.feeds,
+            versions,
+            backfill
+        )
+    )?;
+
+    let ides = keep_latest([jetbrains, android_studio].concat(), versions, backfill);
+    Ok((ides, unknown_products))
+}
+
+/// Drops the versions of each IDE outside of the newest release lines or years its window
+/// keeps. Backfilled versions are always kept.
+fn keep_latest(
+    ides: Vec<IdeVersion>,
+    versions: &Versions,
+    backfill: Option<ReleaseRange>,
+) -> Vec<IdeVersion> {
+    let backfilled = |ide: &IdeVersion| backfill.is_some_and(|range| range.contains(&ide.version));
+    let mut releases: HashMap<IdeProduct, BTreeSet<(u32, u32)>> = HashMap::new();
+    for ide in ides.iter().filter(|ide| !backfilled(ide)) {
+        if let Some(release) = parse_release(&ide.version) {
+            releases.entry(ide.ide).or_default().insert(release);
+        }
+    }
+    let kept: HashMap<IdeProduct, BTreeSet<(u32, u32)>> = releases
+        .into_iter()
+        .map(|(product, releases)| {
+            let window = versions.window(product);
+            // Newest first.
+            let releases: Vec<(u32, u32)> = releases.into_iter().rev().collect();
+            let mut years: Vec<u32> = releases.iter().map(|(year, _)| *year).collect();
+            years.dedup();
+            years.truncate(window.latest_years.unwrap_or(usize::MAX));
+            let kept = releases
+                .into_iter()
+                .take(window.latest_lines.unwrap_or(usize::MAX))
+                .filter(|(year, _)| years.contains(year))
+                .collect();
+            (product, kept)
+        })
+        .collect();
+
+    ides.into_iter()
+        .filter(|ide| {
+            let keep = backfilled(ide)
+                || parse_release(&ide.version).is_none_or(|release| {
+                    kept.get(&ide.ide)
+                        .is_none_or(|kept| kept.contains(&release))
+                });
+            if !keep {
+                debug!("Ignoring {ide}: outside of the latest release lines");
+            }
+            keep
+        })
+        .collect()
+}
+
+/// Fetches an IDE feed from `url` or, if that fails, from its `mirrors` in order. If all of
+/// them fail, the last cached copy is used if [`Feeds::stale_fallback`] is set.
+async fn fetch_feed(
+    fetcher: &dyn Fetcher,
+    run: &RunContext,
+    what: &str,
+    url: &str,
+    mirrors: &[String],
+    feeds: &Feeds,
+) -> anyhow::Result<String> {
+    let urls: Vec<&str> = iter::once(url)
+        .chain(mirrors.iter().map(String::as_str))
+        .collect();
+    let mut last_error = None;
+    for url in &urls {
+        match fetcher.get(url).await.and_then(|resp| resp.success(what)) {
+            Ok(resp) => {
+                run.record_feed(url, &resp.body).await?;
+                return Ok(resp.body);
+            }
+            Err(e) => {
+                warn!("{what}: fetching {url} failed: {e}");
+                last_error = Some(e);
+            }
+        }
+    }
+    if feeds.stale_fallback {
+        for url in &urls {
+            if let Some((body, age)) = fetcher.get_stale(url).await? {
+                warn!(
+                    "{what}: using the cached copy of {url}, which is {} old",
+                    humantime::format_duration(age)
+                );
+                run.record_feed(url, &body).await?;
+                return Ok(body);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow!("{what}: no URL to fetch")))
+}
+
+/// An inclusive range of `<year>.<minor>` release lines, e.g. `2023.1..2024.2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ReleaseRange {
+    start: (u32, u32),
+    end: (u32, u32),
+}
+
+impl ReleaseRange {
+    pub fn contains(&self, version: &str) -> bool {
+        parse_release(version).is_some_and(|release| self.start <= release && release <= self.end)
+    }
+}
+
+impl FromStr for ReleaseRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("invalid release range {s:?}, expected e.g. 2023.1..2024.2");
+        let (start, end) = s.split_once("..").ok_or_else(invalid)?;
+        let start = parse_release(start).ok_or_else(invalid)?;
+        let end = parse_release(end).ok_or_else(invalid)?;
+        if start > end {
+            return Err(invalid());
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl TryFrom<String> for ReleaseRange {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// The `(year, minor)` release line of a version like `2024.2`, `2024.2.3` or `2024.2-eap`.
+fn parse_release(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.trim().split('.');
+    let year = parts.next()?.parse().ok()?;
+    let minor = parts.next()?;
+    let minor_end = minor
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(minor.len());
+    Some((year, minor[..minor_end].parse().ok()?))
+}
+
+fn allowed_build_version(
+    product: IdeProduct,
+    version: &str,
+    versions: &Versions,
+    backfill: Option<ReleaseRange>,
+) -> bool {
+    backfill.is_some_and(|range| range.contains(version))
+        || versions.window(product).matches(version)
+}
@@ -0,0 +1,88 @@
+use crate::fetch::Fetcher;
+use crate::ides::{IdeProduct, IdeVersion};
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::HashSet;
+
+pub const NIXPKGS_VERSIONS: &str = "https://raw.githubusercontent.com/NixOS/nixpkgs/nixos-unstable/pkgs/applications/editors/jetbrains/bin/versions.json";
+
+/// What to do with IDE versions that nixpkgs doesn't package.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum NixpkgsCheck {
+    /// Don't query nixpkgs.
+    #[default]
+    Off,
+    /// Only log IDE versions missing from nixpkgs.
+    Flag,
+    /// Don't generate mappings for IDE versions missing from nixpkgs.
+    Restrict,
+}
+
+/// Fetches the JetBrains `versions.json` from nixpkgs and returns all (product, version)
+/// pairs in it, for all systems.
+pub async fn fetch_versions(
+    fetcher: &dyn Fetcher,
+    url: &str,
+) -> anyhow::Result<HashSet<(IdeProduct, String)>> {
+    let json: Value = fetcher
+        .get(url)
+        .await?
+        .success("nixpkgs versions")?
+        .json()?;
+    let mut versions = HashSet::new();
+    // { "<system>": { "<nixpkgs attribute>": { "version": "...", ... } } }
+    for ides in json
+        .as_object()
+        .into_iter()
+        .flat_map(|systems| systems.values())
+    {
+        for (name, ide) in ides.as_object().into_iter().flatten() {
+            let Some(version) = ide.get("version").and_then(Value::as_str) else {
+                continue;
+            };
+            if let Some(product) = IdeProduct::try_from_nixpkgs_name(name) {
+                versions.insert((product, version.to_string()));
+            }
+        }
+    }
+    Ok(versions)
+}
+
+/// Applies `mode` to `ides`, given the versions nixpkgs ships. Android Studio is not part of
+/// the JetBrains versions.json and is always kept.
+pub fn cross_check(
+    ides: Vec<IdeVersion>,
+    known: &HashSet<(IdeProduct, String)>,
+    mode: NixpkgsCheck,
+) -> Vec<IdeVersion> {
+    let before = ides.len();
+    let ides: Vec<_> = ides
+        .into_iter()
+        .filter(|ide| {
+            if ide.ide == IdeProduct::AndroidStudio
+                || known.contains(&(ide.ide, ide.version.clone()))
+            {
+                return true;
+            }
+            warn!(
+                "{} {} is not packaged in nixpkgs{}",
+                ide.ide.nix_key(),
+                ide.version,
+                if mode == NixpkgsCheck::Restrict {
+                    ", skipping"
+                } else {
+                    ""
+                }
+            );
+            mode != NixpkgsCheck::Restrict
+        })
+        .collect();
+    if mode == NixpkgsCheck::Restrict {
+        info!(
+            "Restricted to IDE versions in nixpkgs: {} of {before} kept.",
+            ides.len()
+        );
+    }
+    ides
+}
@@ -0,0 +1,192 @@
+/// All IDE products the generator knows about.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum IdeProduct {
+    IntelliJIdea,
+    PhpStorm,
+    WebStorm,
+    PyCharm,
+    RubyMine,
+    CLion,
+    GoLand,
+    DataGrip,
+    DataSpell,
+    Rider,
+    AndroidStudio,
+    RustRover,
+    Aqua,
+    Writerside,
+    Mps,
+    Gateway,
+}
+
+/// Static information about an [`IdeProduct`].
+pub struct ProductInfo {
+    pub product: IdeProduct,
+    /// Product codes in updates.xml and build numbers. The first one is the primary code.
+    pub codes: &'static [&'static str],
+    /// Key in nixpkgs' `jetbrains` set, also used for the IDE JSON filenames.
+    pub nix_key: &'static str,
+    /// Other (legacy) nixpkgs names of the same IDE, which share the plugin mappings.
+    pub nix_aliases: &'static [&'static str],
+    pub display_name: &'static str,
+}
+
+/// The product registry. IntelliJ IDEA and PyCharm were unified with their community editions,
+/// so the community codes resolve to the unified product.
+pub const PRODUCTS: &[ProductInfo] = &[
+    ProductInfo {
+        product: IdeProduct::IntelliJIdea,
+        codes: &["IU", "IC"],
+        nix_key: "idea",
+        nix_aliases: &["idea-community", "idea-ultimate", "idea-oss"],
+        display_name: "IntelliJ IDEA",
+    },
+    ProductInfo {
+        product: IdeProduct::PhpStorm,
+        codes: &["PS"],
+        nix_key: "phpstorm",
+        nix_aliases: &[],
+        display_name: "PhpStorm",
+    },
+    ProductInfo {
+        product: IdeProduct::WebStorm,
+        codes: &["WS"],
+        nix_key: "webstorm",
+        nix_aliases: &[],
+        display_name: "WebStorm",
+    },
+    ProductInfo {
+        product: IdeProduct::PyCharm,
+        codes: &["PY", "PC"],
+        nix_key: "pycharm",
+        nix_aliases: &["pycharm-community", "pycharm-professional", "pycharm-oss"],
+        display_name: "PyCharm",
+    },
+    ProductInfo {
+        product: IdeProduct::RubyMine,
+        codes: &["RM"],
+        nix_key: "ruby-mine",
+        nix_aliases: &[],
+        display_name: "RubyMine",
+    },
+    ProductInfo {
+        product: IdeProduct::CLion,
+        codes: &["CL"],
+        nix_key: "clion",
+        nix_aliases: &[],
+        display_name: "CLion",
+    },
+    ProductInfo {
+        product: IdeProduct::GoLand,
+        codes: &["GO"],
+        nix_key: "goland",
+        nix_aliases: &[],
+        display_name: "GoLand",
+    },
+    ProductInfo {
+        product: IdeProduct::DataGrip,
+        codes: &["DB"],
+        nix_key: "datagrip",
+        nix_aliases: &[],
+        display_name: "DataGrip",
+    },
+    ProductInfo {
+        product: IdeProduct::DataSpell,
+        codes: &["DS"],
+        nix_key: "dataspell",
+        nix_aliases: &[],
+        display_name: "DataSpell",
+    },
+    ProductInfo {
+        product: IdeProduct::Rider,
+        codes: &["RD"],
+        nix_key: "rider",
+        nix_aliases: &[],
+        display_name: "Rider",
+    },
+    ProductInfo {
+        product: IdeProduct::AndroidStudio,
+        codes: &["AI"],
+        nix_key: "android-studio",
+        nix_aliases: &[],
+        display_name: "Android Studio",
+    },
+    ProductInfo {
+        product: IdeProduct::RustRover,
+        codes: &["RR"],
+        nix_key: "rust-rover",
+        nix_aliases: &[],
+        display_name: "RustRover",
+    },
+    ProductInfo {
+        product: IdeProduct::Aqua,
+        codes: &["QA"],
+        nix_key: "aqua",
+        nix_aliases: &[],
+        display_name: "Aqua",
+    },
+    ProductInfo {
+        product: IdeProduct::Writerside,
+        codes: &["WRS"],
+        nix_key: "writerside",
+        nix_aliases: &[],
+        display_name: "Writerside",
+    },
+    ProductInfo {
+        product: IdeProduct::Mps,
+        codes: &["MPS"],
+        nix_key: "mps",
+        nix_aliases: &[],
+        display_name: "MPS",
+    },
+    ProductInfo {
+        product: IdeProduct::Gateway,
+        codes: &["GW"],
+        nix_key: "gateway",
+        nix_aliases: &[],
+        display_name: "Gateway",
+    },
+];
+
+impl IdeProduct {
+    pub fn info(&self) -> &'static ProductInfo {
+        PRODUCTS
+            .iter()
+            .find(|info| info.product == *self)
+            .expect("every product is in the registry")
+    }
+
+    pub fn try_from_code(code: &str) -> Option<Self> {
+        PRODUCTS
+            .iter()
+            .find(|info| info.codes.contains(&code))
+            .map(|info| info.product)
+    }
+
+    pub fn try_from_nix_key(key: &str) -> Option<Self> {
+        PRODUCTS
+            .iter()
+            .find(|info| info.nix_key == key)
+            .map(|info| info.product)
+    }
+
+    /// Like [`IdeProduct::try_from_nix_key`], but also accepts the legacy nixpkgs names.
+    pub fn try_from_nixpkgs_name(name: &str) -> Option<Self> {
+        PRODUCTS
+            .iter()
+            .find(|info| info.nix_key == name || info.nix_aliases.contains(&name))
+            .map(|info| info.product)
+    }
+
+    pub fn product_code(&self) -> &'static str {
+        self.info().codes[0]
+    }
+
+    pub fn nix_key(&self) -> &'static str {
+        self.info().nix_key
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        self.info().display_name
+    }
+}
@@ -0,0 +1,70 @@
+use log::debug;
+use std::collections::HashSet;
+use std::fs::exists;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::fs::{OpenOptions, read_to_string, remove_file};
+use tokio::io::AsyncWriteExt;
+
+const JOURNAL_FILE: &str = "generate.journal";
+
+/// Append-only record of the plugin keys a Generate run has finished processing, one per line.
+///
+/// Keys are only written once the database containing their results has been saved (see
+/// [`Journal::commit`]), so every key in the file is safe to skip when resuming.
+pub struct Journal {
+    path: PathBuf,
+    pending: Mutex<Vec<String>>,
+}
+
+impl Journal {
+    /// Opens the journal in `out_dir`. If `resume` is set, returns the keys recorded by the
+    /// previous run, otherwise any previous journal is discarded.
+    pub async fn open(out_dir: &Path, resume: bool) -> anyhow::Result<(Self, HashSet<String>)> {
+        let path = out_dir.join(JOURNAL_FILE);
+        let mut done = HashSet::new();
+        if exists(&path)? {
+            if resume {
+                done.extend(read_to_string(&path).await?.lines().map(str::to_string));
+            } else {
+                remove_file(&path).await?;
+            }
+        }
+        let journal = Self {
+            path,
+            pending: Default::default(),
+        };
+        Ok((journal, done))
+    }
+
+    /// Marks `pluginkey` as processed. Not persisted until the next [`Journal::commit`].
+    pub fn record(&self, pluginkey: &str) {
+        self.pending.lock().unwrap().push(pluginkey.to_string());
+    }
+
+    /// Persists all keys recorded so far. Must only be called after the DB has been saved.
+    pub async fn commit(&self) -> anyhow::Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(());
+        }
+        debug!("Committing {} plugins to the journal.", pending.len());
+        let mut contents = pending.join("\n");
+        contents.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(contents.as_bytes()).await?;
+        Ok(file.sync_all().await?)
+    }
+
+    /// Removes the journal after a complete run.
+    pub async fn finish(self) -> anyhow::Result<()> {
+        if exists(&self.path)? {
+            remove_file(&self.path).await?;
+        }
+        Ok(())
+    }
+}
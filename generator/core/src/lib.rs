@@ -0,0 +1,66 @@
+//! The update pipeline of nix-jetbrains-plugins: fetches the IDE versions from the JetBrains
+//! and Android Studio feeds, resolves the compatible version of every marketplace plugin for
+//! each of them, hashes the artifacts and maintains the plugin database in the output directory.
+//!
+//! [`pipeline::generate`] runs a whole update like the `generate` command. The pieces are
+//! available on their own: [`ides::collect_ids`] for the IDE feeds, [`plugins::api`] for the
+//! marketplace, [`compat::compatible_version`] for the choice of plugin version per IDE build,
+//! [`plugins::db_update`] to update the database with it and [`plugins::PluginDb`] with its
+//! [`plugins::Storage`] backends for the database.
+//!
+//! All requests go through an [`http_client::HttpContext`], which holds the proxy, certificates
+//! and headers of the HTTP clients, the rate limits and the HTTP cache of a run. The rest of the
+//! state of a run (cancellation, progress bar, metrics and the records for the run summary) is
+//! kept in a [`run_context::RunContext`], so runs in the same process don't share it.
+
+pub mod artifact_cache;
+/// IDE build numbers and their comparison.
+pub mod build_number;
+pub mod compat;
+pub mod compression;
+pub mod config;
+/// Error types that decide how failures are reported and retried.
+pub mod error;
+/// Hooks to observe a run, see [`events::GeneratorEvents`].
+pub mod events;
+pub mod fetch;
+mod fs;
+/// Computing the Nix hashes of plugin artifacts.
+pub mod hashing;
+/// Optional on-disk cache of metadata responses.
+pub mod http_cache;
+/// The shared HTTP client settings (proxy, CA certificates, marketplace token).
+pub mod http_client;
+/// The IDE versions to generate plugin mappings for, from the JetBrains and Android Studio feeds.
+pub mod ides;
+/// Journal of processed plugins, to resume interrupted runs.
+pub mod journal;
+/// Lock on the output directory against concurrent runs.
+pub mod lock;
+/// Logging setup of the generator.
+pub mod logging;
+pub mod metrics;
+mod migrations;
+pub mod mirror;
+mod nar;
+/// Deletion of the Nix store paths added by `--hasher nix`.
+pub mod nix_store;
+/// Webhook notifications about finished runs.
+pub mod notify;
+pub mod pipeline;
+/// Plugin metadata (names, vendors) and the aliases derived from it.
+pub mod plugin_meta;
+/// The plugin database, marketplace client and compatibility resolution.
+pub mod plugins;
+/// Progress of the plugins processed by a run.
+pub mod progress;
+/// Limit of the marketplace request rate.
+pub mod rate_limit;
+/// The state of a single run, see [`run_context::RunContext`].
+pub mod run_context;
+/// The summary of a `generate` run, written next to the output.
+pub mod run_summary;
+/// JSON Schemas of the output files, and validation against them.
+pub mod schema;
+/// Ordering of plugin version strings.
+pub mod version_order;
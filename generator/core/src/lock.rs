@@ -0,0 +1,61 @@
+use anyhow::anyhow;
+use log::warn;
+use std::fs::{OpenOptions, read_to_string, remove_file};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+const LOCK_FILE: &str = "generator.lock";
+
+/// Lock file in the output directory that prevents concurrent runs. Removed on drop.
+pub struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Acquires the lock for `out_dir`. With `force`, an existing (presumably stale) lock
+    /// is broken instead of failing.
+    pub fn acquire(out_dir: &Path, force: bool) -> anyhow::Result<Self> {
+        let path = out_dir.join(LOCK_FILE);
+        let contents = format!(
+            "pid={}\nstarted={}\n",
+            process::id(),
+            humantime::format_rfc3339_seconds(SystemTime::now())
+        );
+
+        let mut options = OpenOptions::new();
+        options.write(true);
+        if force {
+            options.create(true).truncate(true);
+        } else {
+            options.create_new(true);
+        }
+        let mut file = match options.open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let holder = read_to_string(&path).unwrap_or_default();
+                return Err(anyhow!(
+                    "{} is locked by another run ({}). If that run is no longer active, \
+                    use --force to break the lock.",
+                    out_dir.display(),
+                    holder.trim().replace('\n', ", ")
+                ));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if force {
+            warn!("Forcing lock on {}.", out_dir.display());
+        }
+        file.write_all(contents.as_bytes())?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        if let Err(e) = remove_file(&self.path) {
+            warn!("Failed to remove lock file {}: {e}", self.path.display());
+        }
+    }
+}
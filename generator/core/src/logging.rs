@@ -0,0 +1,107 @@
+use crate::progress::ActiveBar;
+use log::{LevelFilter, Record};
+use log4rs::append::Append;
+use log4rs::append::console::{ConsoleAppender, Target};
+use log4rs::append::rolling_file::RollingFileAppender;
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
+use log4rs::config::{Appender, Root};
+use log4rs::encode::Encode;
+use log4rs::encode::json::JsonEncoder;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::filter::threshold::ThresholdFilter;
+use log4rs::{Config, Handle, init_config};
+use std::path::PathBuf;
+
+/// How log records are written to stderr.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per record. Structured fields (plugin, ide, phase, duration_ms, ...)
+    /// are in `attributes`.
+    Json,
+}
+
+impl LogFormat {
+    fn encoder(self) -> Box<dyn Encode> {
+        match self {
+            LogFormat::Text => Box::new(PatternEncoder::default()),
+            LogFormat::Json => Box::new(JsonEncoder::new()),
+        }
+    }
+}
+
+/// A log file that always gets debug records, rolled over to `<path>.1` ... `<path>.<keep>`
+/// once it exceeds `max_size` bytes.
+pub struct LogFile {
+    pub path: PathBuf,
+    pub max_size: u64,
+    pub keep: u32,
+}
+
+/// Logs to stderr in `format`, hiding the progress bar in `active_bar` while writing, and to
+/// `log_file` if given.
+pub fn setup_logging(
+    format: LogFormat,
+    log_file: Option<&LogFile>,
+    active_bar: &ActiveBar,
+) -> anyhow::Result<Handle> {
+    let threshold = if cfg!(debug_assertions) {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+
+    let console = ConsoleAppender::builder()
+        .target(Target::Stderr)
+        .encoder(format.encoder())
+        .build();
+    let mut config = Config::builder().appender(
+        Appender::builder()
+            .filter(Box::new(ThresholdFilter::new(threshold)))
+            .build(
+                "stderr",
+                Box::new(ProgressAwareAppender(console, active_bar.clone())),
+            ),
+    );
+    let mut root = Root::builder().appender("stderr");
+    if let Some(log_file) = log_file {
+        let roller = FixedWindowRoller::builder()
+            .base(1)
+            .build(&format!("{}.{{}}", log_file.path.display()), log_file.keep)?;
+        let policy = CompoundPolicy::new(
+            Box::new(SizeTrigger::new(log_file.max_size)),
+            Box::new(roller),
+        );
+        let file = RollingFileAppender::builder()
+            .encoder(format.encoder())
+            .build(&log_file.path, Box::new(policy))?;
+        config = config.appender(Appender::builder().build("file", Box::new(file)));
+        root = root.appender("file");
+    }
+    let level = if log_file.is_some() {
+        LevelFilter::Debug
+    } else {
+        threshold
+    };
+
+    Ok(init_config(config.build(root.build(level))?)?)
+}
+
+/// Hides the progress bar while writing log records, so they don't get mixed up.
+#[derive(Debug)]
+struct ProgressAwareAppender(ConsoleAppender, ActiveBar);
+
+impl Append for ProgressAwareAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        self.1.suspend(|| self.0.append(record))
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
@@ -0,0 +1,228 @@
+//! Prometheus metrics of a `generate` run, served on a listener during the run and/or pushed to
+//! a pushgateway when it completes.
+
+use crate::http_client::HttpContext;
+use log::{debug, info, warn};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_util::task::AbortOnDropHandle;
+
+const PREFIX: &str = "jetbrains_plugins";
+/// Job name used for pushgateway pushes.
+const PUSH_JOB: &str = "nix_jetbrains_plugins_generator";
+
+/// The metrics of a run, see [`RunContext::metrics`](crate::run_context::RunContext::metrics).
+pub struct Metrics {
+    pub requests: Counter,
+    pub not_found: Counter,
+    pub throttled: Counter,
+    pub retries: Counter,
+    pub processed: Counter,
+    pub transient_failures: Counter,
+    pub permanent_failures: Counter,
+    pub processing_seconds: Histogram,
+    pub download_bytes: Histogram,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            requests: Counter::new("requests_total", "Requests made, including retries."),
+            not_found: Counter::new("not_found_total", "Requests answered with HTTP 404."),
+            throttled: Counter::new("throttled_total", "Requests answered with HTTP 429."),
+            retries: Counter::new(
+                "plugin_retries_total",
+                "Plugin processing attempts that failed transiently and were retried.",
+            ),
+            processed: Counter::new("plugins_processed_total", "Plugins processed."),
+            transient_failures: Counter::new(
+                "plugin_transient_failures_total",
+                "Plugins that failed with a transient error after all retries.",
+            ),
+            permanent_failures: Counter::new(
+                "plugin_permanent_failures_total",
+                "Plugins that failed with a permanent error.",
+            ),
+            processing_seconds: Histogram::new(
+                "plugin_processing_seconds",
+                "Time to process one plugin, including retries.",
+                &[
+                    0.1, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0,
+                ],
+            ),
+            download_bytes: Histogram::new(
+                "download_size_bytes",
+                "Size of downloaded plugin artifacts.",
+                &[1e4, 1e5, 1e6, 5e6, 1e7, 5e7, 1e8, 5e8],
+            ),
+        }
+    }
+}
+
+impl Metrics {
+    /// All metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for counter in [
+            &self.requests,
+            &self.not_found,
+            &self.throttled,
+            &self.retries,
+            &self.processed,
+            &self.transient_failures,
+            &self.permanent_failures,
+        ] {
+            counter.render(&mut out);
+        }
+        for histogram in [&self.processing_seconds, &self.download_bytes] {
+            histogram.render(&mut out);
+        }
+        out
+    }
+
+    /// Pushes all metrics to the pushgateway at `url` with a client from `http`, replacing the
+    /// previous push of this job.
+    pub async fn push(&self, http: &HttpContext, url: &str) -> anyhow::Result<()> {
+        let resp = http
+            .client()?
+            .put(format!(
+                "{}/metrics/job/{PUSH_JOB}",
+                url.trim_end_matches('/')
+            ))
+            .timeout(Duration::from_secs(30))
+            .body(self.render())
+            .send()
+            .await?;
+        resp.error_for_status()?;
+        Ok(())
+    }
+}
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String) {
+        let name = format!("{PREFIX}_{}", self.name);
+        _ = writeln!(out, "# HELP {name} {}", self.help);
+        _ = writeln!(out, "# TYPE {name} counter");
+        _ = writeln!(out, "{name} {}", self.value.load(Ordering::Relaxed));
+    }
+}
+
+/// At most this many buckets per histogram, plus `+Inf`.
+const MAX_BUCKETS: usize = 16;
+
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    bounds: &'static [f64],
+    state: Mutex<HistogramState>,
+}
+
+struct HistogramState {
+    /// Non-cumulative, the last one is `+Inf`.
+    buckets: [u64; MAX_BUCKETS + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(name: &'static str, help: &'static str, bounds: &'static [f64]) -> Self {
+        assert!(bounds.len() <= MAX_BUCKETS);
+        Self {
+            name,
+            help,
+            bounds,
+            state: Mutex::new(HistogramState {
+                buckets: [0; MAX_BUCKETS + 1],
+                sum: 0.0,
+                count: 0,
+            }),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(MAX_BUCKETS);
+        let mut state = self.state.lock().unwrap();
+        state.buckets[bucket] += 1;
+        state.sum += value;
+        state.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let name = format!("{PREFIX}_{}", self.name);
+        let state = self.state.lock().unwrap();
+        _ = writeln!(out, "# HELP {name} {}", self.help);
+        _ = writeln!(out, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(state.buckets) {
+            cumulative += count;
+            _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", state.count);
+        _ = writeln!(out, "{name}_sum {}", state.sum);
+        _ = writeln!(out, "{name}_count {}", state.count);
+    }
+}
+
+/// Serves [`Metrics::render`] to every HTTP request on `addr` until the returned handle is
+/// dropped.
+pub async fn serve(
+    metrics: Arc<Metrics>,
+    addr: SocketAddr,
+) -> anyhow::Result<AbortOnDropHandle<()>> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving metrics on http://{addr}/metrics");
+    let task = tokio::spawn(async move {
+        loop {
+            let mut stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("metrics listener: {e}");
+                    continue;
+                }
+            };
+            let metrics = metrics.clone();
+            tokio::spawn(async move {
+                // The request itself doesn't matter, every path gets the metrics.
+                let mut request = [0; 1024];
+                _ = stream.read(&mut request).await;
+                let body = metrics.render();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                if let Err(e) = stream.write_all(response.as_bytes()).await {
+                    debug!("metrics listener: {e}");
+                }
+            });
+        }
+    });
+    Ok(AbortOnDropHandle::new(task))
+}
@@ -0,0 +1,84 @@
+use crate::plugins::{ArtifactKind, PluginVersion, SRI_PREFIX};
+use anyhow::{anyhow, bail};
+use log::info;
+use serde_json::{Value, json};
+
+/// Schema version of all_plugins.json written by this generator.
+pub const SCHEMA_VERSION: u64 = 3;
+
+type Migration = fn(Value) -> anyhow::Result<Value>;
+
+/// `MIGRATIONS[n]` upgrades schema version `n + 1` to `n + 2`.
+const MIGRATIONS: &[Migration] = &[v1_to_v2, v2_to_v3];
+
+/// Upgrades the contents of all_plugins.json to [`SCHEMA_VERSION`].
+///
+/// Files without a `schemaVersion` are version 1: a flat map of plugin versions to entries.
+pub fn migrate(mut db: Value) -> anyhow::Result<Value> {
+    let mut version = match db.get("schemaVersion") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| anyhow!("invalid schemaVersion: {version}"))?,
+    };
+    if version > SCHEMA_VERSION {
+        bail!(
+            "database has schema version {version}, but this generator only supports up to {SCHEMA_VERSION}"
+        );
+    }
+    while version < SCHEMA_VERSION {
+        info!(
+            "Migrating database from schema version {version} to {}...",
+            version + 1
+        );
+        db = MIGRATIONS[version as usize - 1](db)?;
+        version += 1;
+        db["schemaVersion"] = version.into();
+    }
+    Ok(db)
+}
+
+/// Moves the entries below `plugins`, prefixes hashes with the SRI algorithm and
+/// records the artifact kind.
+fn v1_to_v2(db: Value) -> anyhow::Result<Value> {
+    let Value::Object(mut plugins) = db else {
+        bail!("expected an object of plugin entries");
+    };
+    for (key, entry) in &mut plugins {
+        let entry = entry
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("{key}: expected an object"))?;
+        if let Some(Value::String(hash)) = entry.get_mut("h")
+            && !hash.starts_with(SRI_PREFIX)
+        {
+            hash.insert_str(0, SRI_PREFIX);
+        }
+        if !entry.contains_key("k") {
+            let path = entry
+                .get("p")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("{key}: missing path"))?;
+            let kind = serde_json::to_value(ArtifactKind::from_path(path))?;
+            entry.insert("k".to_string(), kind);
+        }
+    }
+    Ok(json!({ "plugins": plugins }))
+}
+
+/// Escapes `%` and `/` in the plugin names of the keys. Before, names were used verbatim, so
+/// the first separator is assumed to end the name.
+fn v2_to_v3(mut db: Value) -> anyhow::Result<Value> {
+    let Some(Value::Object(plugins)) = db.get_mut("plugins") else {
+        bail!("expected an object of plugin entries");
+    };
+    *plugins = std::mem::take(plugins)
+        .into_iter()
+        .map(|(key, entry)| {
+            let (name, version) = key
+                .split_once(PluginVersion::SEPARATOR)
+                .ok_or_else(|| anyhow!("invalid plugin version key: {key}"))?;
+            Ok((PluginVersion::new(name, version).to_string(), entry))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(db)
+}
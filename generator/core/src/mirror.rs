@@ -0,0 +1,116 @@
+//! Copies downloaded plugin artifacts to a self-hosted mirror, for builds that can't fetch
+//! from JetBrains.
+
+use anyhow::{Context, anyhow};
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::Seek;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+use tokio::fs::{create_dir_all, rename};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use which::which;
+
+/// Where artifacts are mirrored to: `s3://bucket/prefix` (uploaded with the `aws` CLI) or a
+/// local directory.
+#[derive(Debug, Clone)]
+pub enum Mirror {
+    Local(PathBuf),
+    S3 { bucket: String, prefix: String },
+}
+
+impl FromStr for Mirror {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = s.strip_prefix("s3://") else {
+            return Ok(Mirror::Local(PathBuf::from(s)));
+        };
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(format!("missing bucket in {s:?}"));
+        }
+        Ok(Mirror::S3 {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+}
+
+impl Display for Mirror {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Mirror::Local(dir) => write!(f, "{}", dir.display()),
+            Mirror::S3 { bucket, prefix } if prefix.is_empty() => write!(f, "s3://{bucket}"),
+            Mirror::S3 { bucket, prefix } => write!(f, "s3://{bucket}/{prefix}"),
+        }
+    }
+}
+
+impl Mirror {
+    /// Fails early if the mirror can't be written to at all.
+    pub async fn check(&self) -> anyhow::Result<()> {
+        match self {
+            Mirror::Local(dir) => create_dir_all(dir)
+                .await
+                .with_context(|| format!("cannot create mirror directory {}", dir.display())),
+            Mirror::S3 { .. } => which("aws")
+                .map(drop)
+                .map_err(|_| anyhow!("mirroring to S3 requires the aws CLI in PATH")),
+        }
+    }
+
+    /// The location of the artifact stored under `key`.
+    pub fn location(&self, key: &str) -> String {
+        match self {
+            Mirror::Local(dir) => dir.join(key).display().to_string(),
+            Mirror::S3 { .. } => format!("{self}/{key}"),
+        }
+    }
+
+    /// Copies `file` to the mirror as `key` and returns its [`location`](Self::location).
+    /// `file` is rewound afterwards.
+    pub async fn store(&self, file: &mut File, key: &str) -> anyhow::Result<String> {
+        let location = self.location(key);
+        file.rewind()?;
+        let mut source = tokio::fs::File::from_std(file.try_clone()?);
+        match self {
+            Mirror::Local(_) => {
+                let path = Path::new(&location);
+                if let Some(parent) = path.parent() {
+                    create_dir_all(parent).await?;
+                }
+                let tmp_path = path.with_extension("part");
+                let mut target = tokio::fs::File::create(&tmp_path).await?;
+                tokio::io::copy(&mut source, &mut target).await?;
+                target.sync_all().await?;
+                drop(target);
+                rename(&tmp_path, path).await?;
+            }
+            Mirror::S3 { .. } => {
+                let mut child = Command::new("aws")
+                    .args(["s3", "cp", "--only-show-errors", "-", &location])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()?;
+                let mut stdin = child.stdin.take().expect("stdin is piped");
+                tokio::io::copy(&mut source, &mut stdin).await?;
+                stdin.shutdown().await?;
+                drop(stdin);
+                let result = child.wait_with_output().await?;
+                if !result.status.success() {
+                    return Err(anyhow!(
+                        "aws s3 cp failed for {location}: {}",
+                        String::from_utf8_lossy(&result.stderr).trim()
+                    ));
+                }
+            }
+        }
+        file.rewind()?;
+        Ok(location)
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+const NAR_VERSION_MAGIC: &str = "nix-archive-1";
+
+/// A file system tree as it will be serialized into a NAR.
+/// Regular files reference their contents through `C`, so that big artifacts never
+/// need to be held in memory.
+pub enum NarNode<C> {
+    Regular {
+        executable: bool,
+        size: u64,
+        contents: C,
+    },
+    Directory(BTreeMap<String, NarNode<C>>),
+    Symlink(String),
+}
+
+impl<C> NarNode<C> {
+    pub fn empty_directory() -> Self {
+        NarNode::Directory(BTreeMap::new())
+    }
+}
+
+/// Minimal NAR (Nix ARchive) serializer, just enough to compute the same hashes as
+/// `nix-prefetch-url` does for plugin artifacts.
+pub struct NarWriter<W> {
+    out: W,
+}
+
+impl<W: Write> NarWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Serializes `root`. `copy` is called for every regular file to copy its contents into
+    /// the given writer and must return the number of bytes written.
+    pub fn write_nar<C>(
+        &mut self,
+        root: &NarNode<C>,
+        copy: &mut impl FnMut(&C, &mut W) -> io::Result<u64>,
+    ) -> io::Result<()> {
+        self.write_str(NAR_VERSION_MAGIC)?;
+        self.write_node(root, copy)
+    }
+
+    fn write_node<C>(
+        &mut self,
+        node: &NarNode<C>,
+        copy: &mut impl FnMut(&C, &mut W) -> io::Result<u64>,
+    ) -> io::Result<()> {
+        self.write_str("(")?;
+        self.write_str("type")?;
+        match node {
+            NarNode::Regular {
+                executable,
+                size,
+                contents,
+            } => {
+                self.write_str("regular")?;
+                if *executable {
+                    self.write_str("executable")?;
+                    self.write_str("")?;
+                }
+                self.write_str("contents")?;
+                self.write_contents(*size, contents, copy)?;
+            }
+            NarNode::Directory(entries) => {
+                self.write_str("directory")?;
+                // BTreeMap iterates in byte order of the UTF-8 names, which is what Nix expects.
+                for (name, child) in entries {
+                    self.write_str("entry")?;
+                    self.write_str("(")?;
+                    self.write_str("name")?;
+                    self.write_str(name)?;
+                    self.write_str("node")?;
+                    self.write_node(child, copy)?;
+                    self.write_str(")")?;
+                }
+            }
+            NarNode::Symlink(target) => {
+                self.write_str("symlink")?;
+                self.write_str("target")?;
+                self.write_str(target)?;
+            }
+        }
+        self.write_str(")")
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.write_bytes(s.as_bytes())
+    }
+
+    fn write_bytes(&mut self, b: &[u8]) -> io::Result<()> {
+        self.out.write_all(&(b.len() as u64).to_le_bytes())?;
+        self.out.write_all(b)?;
+        self.write_padding(b.len() as u64)
+    }
+
+    fn write_contents<C>(
+        &mut self,
+        size: u64,
+        contents: &C,
+        copy: &mut impl FnMut(&C, &mut W) -> io::Result<u64>,
+    ) -> io::Result<()> {
+        self.out.write_all(&size.to_le_bytes())?;
+        let copied = copy(contents, &mut self.out)?;
+        if copied != size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("expected {size} bytes of file contents, got {copied}"),
+            ));
+        }
+        self.write_padding(size)
+    }
+
+    fn write_padding(&mut self, len: u64) -> io::Result<()> {
+        let padding = (8 - (len % 8)) % 8;
+        self.out.write_all(&[0; 8][..padding as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(root: &NarNode<&'static [u8]>) -> io::Result<Vec<u8>> {
+        let mut writer = NarWriter::new(Vec::new());
+        writer.write_nar(root, &mut |contents, out| {
+            out.write_all(contents)?;
+            Ok(contents.len() as u64)
+        })?;
+        Ok(writer.into_inner())
+    }
+
+    /// The NAR framing of `s`: its length, then `s` padded to 8 bytes.
+    fn str(s: &str) -> Vec<u8> {
+        let mut out = (s.len() as u64).to_le_bytes().to_vec();
+        out.extend(s.as_bytes());
+        out.resize(out.len().next_multiple_of(8), 0);
+        out
+    }
+
+    fn strs(strs: &[&str]) -> Vec<u8> {
+        strs.iter().flat_map(|s| str(s)).collect()
+    }
+
+    #[test]
+    fn executable_file() {
+        let nar = serialize(&NarNode::Regular {
+            executable: true,
+            size: 3,
+            contents: b"abc",
+        })
+        .unwrap();
+        let expected = strs(&[
+            "nix-archive-1",
+            "(",
+            "type",
+            "regular",
+            "executable",
+            "",
+            "contents",
+            "abc",
+            ")",
+        ]);
+        assert_eq!(nar, expected);
+    }
+
+    #[test]
+    fn directory_entries_are_sorted() {
+        let nar = serialize(&NarNode::Directory(BTreeMap::from([
+            ("b".to_string(), NarNode::Symlink("a".to_string())),
+            ("a".to_string(), NarNode::empty_directory()),
+        ])))
+        .unwrap();
+        let expected = strs(&[
+            "nix-archive-1",
+            "(",
+            "type",
+            "directory",
+            "entry",
+            "(",
+            "name",
+            "a",
+            "node",
+            "(",
+            "type",
+            "directory",
+            ")",
+            ")",
+            "entry",
+            "(",
+            "name",
+            "b",
+            "node",
+            "(",
+            "type",
+            "symlink",
+            "target",
+            "a",
+            ")",
+            ")",
+            ")",
+        ]);
+        assert_eq!(nar, expected);
+    }
+
+    #[test]
+    fn short_contents_fail() {
+        let error = serialize(&NarNode::Regular {
+            executable: false,
+            size: 4,
+            contents: b"abc",
+        })
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
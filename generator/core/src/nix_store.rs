@@ -0,0 +1,129 @@
+//! The store paths that `nix-prefetch-url` adds while hashing (`--hasher nix`). They are
+//! recorded in a sidecar file in the output directory and deleted in batches at the end of the
+//! run, or by the `gc` command after runs that didn't get there.
+
+use crate::fs::write_atomic;
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use std::collections::BTreeSet;
+use std::fs::exists;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::fs::{OpenOptions, read_to_string, remove_file};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use which::which;
+
+const STORE_PATHS_FILE: &str = "nix-store-paths.journal";
+/// Store paths per `nix-store --delete`, to stay below the limit of the command line length.
+const DELETE_BATCH: usize = 256;
+
+lazy_static! {
+    static ref NIX_STORE: PathBuf = which("nix-store").expect("nix-store not in PATH");
+}
+
+/// Append-only record of store paths to delete, one per line.
+pub struct StorePaths {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl StorePaths {
+    /// The record in `out_dir`, including the paths left by earlier runs.
+    pub fn new(out_dir: &Path) -> Self {
+        Self {
+            path: out_dir.join(STORE_PATHS_FILE),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Records `store_path` for deletion. Written right away, so that it isn't lost if the run
+    /// crashes.
+    pub async fn record(&self, store_path: &str) -> anyhow::Result<()> {
+        let _lock = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(format!("{store_path}\n").as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Deletes all recorded store paths that still exist. Paths that can't be deleted (e.g.
+    /// because something still references them) are logged and stay recorded.
+    pub async fn delete_all(&self) -> anyhow::Result<()> {
+        let _lock = self.lock.lock().await;
+        if !exists(&self.path)? {
+            return Ok(());
+        }
+        let recorded: BTreeSet<_> = read_to_string(&self.path)
+            .await?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+        let mut present = Vec::new();
+        for store_path in recorded {
+            if exists(&store_path)? {
+                present.push(store_path);
+            }
+        }
+        let mut deleted = 0;
+        let mut failed = Vec::new();
+        for batch in present.chunks(DELETE_BATCH) {
+            match delete(batch).await {
+                Ok(()) => deleted += batch.len(),
+                Err(e) => {
+                    debug!(
+                        "Deleting {} store paths failed ({e}), one by one.",
+                        batch.len()
+                    );
+                    for store_path in batch {
+                        match delete(std::slice::from_ref(store_path)).await {
+                            Ok(()) => deleted += 1,
+                            Err(e) => {
+                                warn!("Failed deleting {store_path}: {e}");
+                                failed.push(store_path.as_str());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if failed.is_empty() {
+            remove_file(&self.path).await?;
+            info!("Deleted {deleted} Nix store paths added while hashing.");
+        } else {
+            write_atomic(&self.path, format!("{}\n", failed.join("\n"))).await?;
+            warn!(
+                "Deleted {deleted} Nix store paths added while hashing, {} could not be deleted \
+                 and are left for `gc`.",
+                failed.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Runs `nix-store --delete` on `store_paths`.
+async fn delete(store_paths: &[String]) -> anyhow::Result<()> {
+    let output = Command::new(&*NIX_STORE)
+        .arg("--delete")
+        .args(store_paths)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(anyhow!("{}", stderr.trim()));
+    }
+    // E.g. "3 store paths deleted, 12.34 MiB freed".
+    if let Some(summary) = stderr.lines().last() {
+        info!("nix-store --delete: {summary}");
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use crate::http_client::HttpContext;
+use crate::run_summary::RunSummary;
+use log::{info, warn};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Posted to `--notify-webhook` when a command finishes.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Notification<'a> {
+    /// One-line description, so that Slack (and compatible) incoming webhooks can show it as is.
+    text: String,
+    command: &'a str,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    duration_seconds: f64,
+    /// Only for `generate` runs that got as far as processing plugins.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<&'a RunSummary>,
+}
+
+/// POSTs the outcome of `command` to `url` with a client from `http`. Failures are only logged.
+pub async fn send(
+    http: &HttpContext,
+    url: &str,
+    command: &str,
+    result: &anyhow::Result<()>,
+    duration: Duration,
+    summary: Option<&RunSummary>,
+) {
+    let text = match result {
+        Ok(()) => format!(
+            "nix-jetbrains-plugins: {command} succeeded after {}",
+            humantime::format_duration(Duration::from_secs(duration.as_secs()))
+        ),
+        Err(e) => format!("nix-jetbrains-plugins: {command} failed: {e:#}"),
+    };
+    let notification = Notification {
+        text,
+        command,
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|e| format!("{e:#}")),
+        duration_seconds: duration.as_secs_f64(),
+        summary,
+    };
+    let client = match http.client() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("failed sending notification to {url}: {e}");
+            return;
+        }
+    };
+    let resp = client
+        .post(url)
+        .timeout(Duration::from_secs(30))
+        .json(&notification)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status());
+    match resp {
+        Ok(_) => info!("Sent notification to {url}."),
+        Err(e) => warn!("failed sending notification to {url}: {e}"),
+    }
+}
@@ -0,0 +1,907 @@
+//! The `generate`, `cleanup`, `repair` and `gc` commands of the generator, usable without the CLI.
+
+use crate::artifact_cache::ArtifactCache;
+use crate::compression::Compression;
+use crate::config::Config;
+use crate::events::LogEvents;
+use crate::fetch::{Fetcher, FixtureFetcher, HttpFetcher, RewritingFetcher};
+use crate::hashing::HasherKind;
+use crate::http_client::HttpContext;
+use crate::ides::nixpkgs::{NIXPKGS_VERSIONS, NixpkgsCheck};
+use crate::ides::{self, IdeChannel, IdeVersion, ReleaseRange};
+use crate::journal::Journal;
+use crate::mirror::Mirror;
+use crate::nix_store::StorePaths;
+use crate::plugins::{
+    self, DownloadUrls, FailureThreshold, MARKETPLACE_DOWNLOADS, PluginDb, PluginsLayout, Storage,
+};
+use crate::run_context::RunContext;
+use crate::run_summary::{self, RunSummary, Timings};
+use crate::{metrics, schema};
+use anyhow::anyhow;
+use futures::future::try_join_all;
+use log::{debug, error, info, warn};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::spawn_blocking;
+use tokio::try_join;
+
+/// Options of [`generate`], also the arguments of the `generate` command.
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct GenerateOptions {
+    /// How to compute the hashes of plugin artifacts.
+    #[cfg_attr(feature = "clap", arg(long, value_enum, default_value_t))]
+    pub hasher: HasherKind,
+    /// Continue an interrupted run, skipping plugins that were already processed.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub resume: bool,
+    /// Save the database every N processed plugins, so a crash doesn't lose all work. 0 disables.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = 500))]
+    pub flush_every: usize,
+    /// Also record newer EAP channel versions of plugins next to the stable ones.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub eap_plugins: bool,
+    /// JetBrains IDE release channels to generate plugin mappings for.
+    #[cfg_attr(
+        feature = "clap",
+        arg(long, value_enum, value_delimiter = ',', default_value = "stable")
+    )]
+    pub channels: Vec<IdeChannel>,
+    /// Also process the IDE versions in this inclusive range of release lines (e.g.
+    /// `2023.1..2024.2`), in addition to the current ones. For one-off runs generating mappings
+    /// for older IDEs.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub backfill: Option<ReleaseRange>,
+    /// Cross-check IDE versions against the JetBrains versions packaged in nixpkgs.
+    #[cfg_attr(feature = "clap", arg(long, value_enum, default_value_t))]
+    pub nixpkgs_check: NixpkgsCheck,
+    /// The nixpkgs JetBrains versions.json to cross-check against.
+    #[cfg_attr(feature = "clap", arg(long, default_value = NIXPKGS_VERSIONS))]
+    pub nixpkgs_versions_url: String,
+    /// Fetch compatible plugin versions per IDE build in bulk instead of the details of every
+    /// plugin. Much fewer requests, but no EAP versions and no plugin metadata updates.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub bulk: bool,
+    /// Process one IDE build after another with the requests of `--bulk`, only looking at the
+    /// plugins compatible with it, and save each IDE mapping once it is complete. Cheapest
+    /// with `--only-ide` to add a single new IDE release.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub per_ide: bool,
+    /// Only process these IDE versions (e.g. `idea-2025.3`) or all versions of these IDEs
+    /// (e.g. `idea`), by nix key. The mappings of other IDEs are left as they are. Can be given
+    /// multiple times.
+    #[cfg_attr(feature = "clap", arg(long, value_delimiter = ','))]
+    pub only_ide: Vec<String>,
+    /// Average number of marketplace requests per second. 0 disables the limit.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = 10.0))]
+    pub requests_per_second: f64,
+    /// Number of marketplace requests that may be made at once before the limit applies.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = 20))]
+    pub burst: u32,
+    /// Limit artifact downloads to this many MB/s (10^6 bytes) in total. Not supported by
+    /// `--hasher nix`.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub max_bandwidth: Option<f64>,
+    /// Cache metadata responses (IDE lists, plugin indices and details) in this directory and
+    /// revalidate them with their ETag. Disabled if not given.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub http_cache: Option<PathBuf>,
+    /// How long cached responses are used without asking the server whether they changed.
+    #[cfg_attr(feature = "clap", arg(long, value_parser = humantime::parse_duration, default_value = "1h"))]
+    pub http_cache_max_age: Duration,
+    /// Serve everything from the HTTP cache and fail on any request it can't answer.
+    #[cfg_attr(feature = "clap", arg(long, requires = "http_cache"))]
+    pub offline: bool,
+    /// Abort at the first plugin that fails instead of processing all others first.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub fail_fast: bool,
+    /// Check the marketplace artifacts already in the database against the size and ETag of
+    /// their download and hash them again if they were republished. Makes a HEAD request per
+    /// plugin version.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub verify_artifacts: bool,
+    /// Archive a copy of every upstream feed (plugin indices, IDE version lists) used in this
+    /// run to this directory. Their hashes are in the run summary either way.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub archive_feeds: Option<PathBuf>,
+    /// Copy every artifact downloaded for hashing to this directory or S3 location
+    /// (`s3://bucket/prefix`, uploaded with the `aws` CLI) and record where in the database.
+    /// Artifacts are stored under their download path. Requires `--hasher native`.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub mirror: Option<Mirror>,
+    /// Keep downloaded artifacts in this directory by URL, so that a run after a failure
+    /// doesn't download them again. Requires `--hasher native`.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub artifact_cache: Option<PathBuf>,
+    /// Size limit of `--artifact-cache` in MB (10^6 bytes). The least recently used artifacts
+    /// are removed beyond it.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = 10_000))]
+    pub artifact_cache_max_size: u64,
+    /// Exit with an error if any plugin failed. By default, failures are only reported in the
+    /// logs and the run summary. The database is saved either way.
+    #[cfg_attr(feature = "clap", arg(long, conflicts_with = "max_failures"))]
+    pub strict: bool,
+    /// Exit with an error if more plugins failed than this number (`10`) or percentage of the
+    /// attempted plugins (`5%`).
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub max_failures: Option<FailureThreshold>,
+    /// Append a Markdown summary of the run to the file in `GITHUB_STEP_SUMMARY`.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub github_summary: bool,
+    /// Report products in updates.xml with unknown product codes (e.g. a newly released IDE)
+    /// as warnings and in the run summary, instead of silently skipping them.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub discover_products: bool,
+    /// Serve Prometheus metrics on this address (e.g. `127.0.0.1:9184`) during the run.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub metrics_listen: Option<SocketAddr>,
+    /// Index of the plugin IDs to process: a URL or `file://` URL of a JSON array of IDs like
+    /// the marketplace ones, or `-` to read it from stdin, e.g. for air-gapped runs. Replaces
+    /// the marketplace indices. Can be given multiple times.
+    #[cfg_attr(feature = "clap", arg(long = "plugin-index", default_values = PLUGIN_INDICES))]
+    pub plugin_indices: Vec<String>,
+    /// URL of an `updatePlugins.xml` of a custom plugin repository, whose plugins are
+    /// processed alongside the marketplace ones. Can be given multiple times.
+    #[cfg_attr(feature = "clap", arg(long = "plugin-repository"))]
+    pub plugin_repositories: Vec<String>,
+    /// Only process the plugins listed in this file (one ID per line) instead of all plugins in
+    /// the marketplace indices. The indices are still used to resolve dependencies.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub plugins_file: Option<PathBuf>,
+    /// Process recently updated and frequently downloaded plugins first, so that runs cut
+    /// short (e.g. by CI time limits) refresh the most relevant ones. Costs a few marketplace
+    /// search requests.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub prioritize: bool,
+    /// Process the plugins that weren't processed successfully for longer than this (e.g.
+    /// `7d`, see plugin_status.json) first, the least recently processed first. Useful with
+    /// runs that are likely to be interrupted.
+    #[cfg_attr(feature = "clap", arg(long, value_parser = humantime::parse_duration))]
+    pub refresh_stale: Option<Duration>,
+    /// Plugins only distributed as direct downloads, see the README. Ignored if missing.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "custom_plugins.toml"))]
+    pub custom_plugins: PathBuf,
+    /// Directory of curated plugin sets (`<name>.toml`) to render per IDE version, see the
+    /// README. Ignored if missing.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "profiles"))]
+    pub profiles: PathBuf,
+    /// Accepted prefixes of resolved marketplace download URLs. URLs starting with the
+    /// marketplace CDN are stored without it, others in full. Can be given multiple times.
+    #[cfg_attr(feature = "clap", arg(long = "download-prefix", default_value = MARKETPLACE_DOWNLOADS))]
+    pub download_prefixes: Vec<String>,
+    /// `FROM=TO`: download artifacts whose URL starts with FROM from TO instead (e.g. an
+    /// internal mirror). The stored URL is unchanged. Can be given multiple times.
+    #[cfg_attr(feature = "clap", arg(long = "download-rewrite", value_parser = plugins::parse_rewrite))]
+    pub download_rewrites: Vec<(String, String)>,
+    /// Push Prometheus metrics to this pushgateway when the run completes.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub metrics_pushgateway: Option<String>,
+    /// Check the output against the JSON Schemas in `core/schemas` before saving it, and fail
+    /// the run without saving if it violates them.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub validate_schema: bool,
+    #[doc(hidden)]
+    #[cfg_attr(feature = "clap", command(flatten))]
+    pub test_hooks: TestHooks,
+}
+
+impl Default for GenerateOptions {
+    /// The defaults of the `generate` command.
+    fn default() -> Self {
+        Self {
+            hasher: HasherKind::default(),
+            resume: false,
+            flush_every: 500,
+            eap_plugins: false,
+            channels: vec![IdeChannel::Stable],
+            backfill: None,
+            nixpkgs_check: NixpkgsCheck::default(),
+            nixpkgs_versions_url: NIXPKGS_VERSIONS.to_string(),
+            bulk: false,
+            per_ide: false,
+            only_ide: Vec::new(),
+            requests_per_second: 10.0,
+            burst: 20,
+            max_bandwidth: None,
+            http_cache: None,
+            http_cache_max_age: Duration::from_secs(60 * 60),
+            offline: false,
+            fail_fast: false,
+            verify_artifacts: false,
+            archive_feeds: None,
+            mirror: None,
+            artifact_cache: None,
+            artifact_cache_max_size: 10_000,
+            strict: false,
+            max_failures: None,
+            github_summary: false,
+            discover_products: false,
+            metrics_listen: None,
+            plugin_indices: PLUGIN_INDICES.iter().map(|url| url.to_string()).collect(),
+            plugin_repositories: Vec::new(),
+            plugins_file: None,
+            prioritize: false,
+            refresh_stale: None,
+            custom_plugins: PathBuf::from("custom_plugins.toml"),
+            profiles: PathBuf::from("profiles"),
+            download_prefixes: vec![MARKETPLACE_DOWNLOADS.to_string()],
+            download_rewrites: Vec::new(),
+            metrics_pushgateway: None,
+            validate_schema: false,
+            test_hooks: TestHooks::default(),
+        }
+    }
+}
+
+/// Hidden options for the tests of the `generate` and `repair` commands. Library users pass
+/// their own [`Fetcher`] to the functions of [`ides`] and [`plugins`] instead.
+#[doc(hidden)]
+#[derive(Default)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct TestHooks {
+    /// Test only: answer all metadata requests with the canned responses in this directory
+    /// instead of the network, see [`FixtureFetcher`]. Artifacts are still downloaded.
+    #[cfg_attr(feature = "clap", arg(long, hide = true))]
+    pub fixtures: Option<PathBuf>,
+    /// Test only: `FROM=TO`, send metadata requests (IDE feeds, marketplace API, download HEAD
+    /// requests) whose URL starts with FROM to TO instead, e.g. the mock marketplace of the
+    /// end-to-end tests. Can be given multiple times.
+    #[cfg_attr(feature = "clap", arg(long = "request-rewrite", value_parser = plugins::parse_rewrite, hide = true))]
+    pub request_rewrites: Vec<(String, String)>,
+}
+
+impl TestHooks {
+    /// The fetcher of metadata requests: the fixtures if given, else the network through `http`,
+    /// with the request rewrites applied.
+    fn fetcher(&self, http: &HttpContext) -> anyhow::Result<Arc<dyn Fetcher>> {
+        let fetcher: Arc<dyn Fetcher> = match &self.fixtures {
+            Some(dir) => Arc::new(FixtureFetcher::load(dir)?),
+            None => Arc::new(HttpFetcher::new(http)?),
+        };
+        if self.request_rewrites.is_empty() {
+            return Ok(fetcher);
+        }
+        Ok(Arc::new(RewritingFetcher::new(
+            fetcher,
+            self.request_rewrites.clone(),
+        )))
+    }
+}
+
+/// The marketplace indices listing the IDs of all plugins.
+pub const PLUGIN_INDICES: &[&str] = &[
+    "https://downloads.marketplace.jetbrains.com/files/pluginsXMLIds.json",
+    "https://downloads.marketplace.jetbrains.com/files/jbPluginsXMLIds.json",
+];
+
+/// How the database is saved, overriding how it was loaded.
+#[derive(Clone, Copy)]
+pub struct OutputOptions {
+    pub layout: Option<PluginsLayout>,
+    pub nix_output: Option<bool>,
+    pub compact: Option<bool>,
+    pub compression: Option<Compression>,
+    pub deterministic: Option<bool>,
+}
+
+impl OutputOptions {
+    pub fn apply(self, db: &mut PluginDb) {
+        if let Some(layout) = self.layout {
+            db.layout = layout;
+        }
+        if let Some(nix_output) = self.nix_output {
+            db.nix_output = nix_output;
+        }
+        if let Some(compact) = self.compact {
+            db.compact = compact;
+        }
+        if let Some(compression) = self.compression {
+            db.compression = compression;
+        }
+        if let Some(deterministic) = self.deterministic {
+            db.deterministic = deterministic;
+        }
+    }
+}
+
+/// Requests go through `http` with the rate limits and HTTP cache of `args` added. The run is
+/// recorded in `run`, which gets the [`RunSummary`] once the run got as far as processing
+/// plugins.
+pub async fn generate(
+    output_path: &Path,
+    config: &Config,
+    http: &HttpContext,
+    run: &RunContext,
+    storage: &dyn Storage,
+    output: OutputOptions,
+    args: GenerateOptions,
+) -> anyhow::Result<()> {
+    info!("running generate.");
+    let started = Instant::now();
+    let mut http = http
+        .clone()
+        .with_run(run)
+        .with_rate_limit(args.requests_per_second, args.burst);
+    if let Some(max_bandwidth) = args.max_bandwidth {
+        if args.hasher == HasherKind::Nix {
+            return Err(anyhow!("--max-bandwidth is not supported by --hasher nix"));
+        }
+        http = http.with_bandwidth_limit(max_bandwidth * 1_000_000.0);
+    }
+    if let Some(dir) = args.http_cache {
+        http = http.with_cache(dir, args.http_cache_max_age, args.offline);
+    }
+    run.set_feed_archive(args.archive_feeds.clone());
+    let _metrics_listener = match args.metrics_listen {
+        Some(addr) => Some(metrics::serve(run.metrics().clone(), addr).await?),
+        None => None,
+    };
+    if let Some(mirror) = &args.mirror {
+        mirror.check().await?;
+        info!("Mirroring downloaded artifacts to {mirror}.");
+    }
+    let cache = match &args.artifact_cache {
+        Some(dir) => Some(ArtifactCache::open(
+            dir.clone(),
+            args.artifact_cache_max_size * 1_000_000,
+        )?),
+        None => None,
+    };
+    let hasher = args.hasher.build(&http, output_path, args.mirror, cache)?;
+    let fetcher = args.test_hooks.fetcher(&http)?;
+    let ((mut ides, mut unknown_products), indices) = try_join!(
+        ides::collect_ids(&*fetcher, run, &args.channels, config, args.backfill),
+        try_join_all(
+            args.plugin_indices
+                .iter()
+                .map(|source| plugins::index(&*fetcher, run, source))
+        )
+    )?;
+    for product in &unknown_products {
+        if args.discover_products {
+            warn!(phase = "index"; "Unknown product in updates.xml: {product}");
+        } else {
+            debug!(phase = "index"; "Skipping unknown product {product}");
+        }
+    }
+    if !args.discover_products {
+        unknown_products.clear();
+    }
+
+    info!(
+        "Indexing {} IDE versions and {} plugins from {} indices.",
+        ides.len(),
+        indices.iter().map(Vec::len).sum::<usize>(),
+        indices.len()
+    );
+    let mut plugins = plugins::merge_indices(&indices);
+    let mut repositories = HashMap::new();
+    plugins::load_custom_plugins(&args.custom_plugins, &mut repositories).await?;
+    plugins::fetch_repositories(&*fetcher, &args.plugin_repositories, &mut repositories).await?;
+    let mut repository_plugins: Vec<_> = repositories
+        .keys()
+        .filter(|pluginkey| !plugins.contains(pluginkey))
+        .cloned()
+        .collect();
+    repository_plugins.sort();
+    plugins.extend(repository_plugins);
+    let mut known_plugins: HashSet<_> = plugins.iter().cloned().collect();
+    if let Some(path) = &args.plugins_file {
+        plugins = plugins::read_plugins_file(path).await?;
+        info!(
+            "Only processing the {} plugins in {}.",
+            plugins.len(),
+            path.display()
+        );
+        known_plugins.extend(plugins.iter().cloned());
+    }
+    let count = plugins.len();
+    plugins.retain(|pluginkey| !config.exclude.excludes_id(pluginkey));
+    if plugins.len() < count {
+        info!("Excluded {} plugins by ID.", count - plugins.len());
+    }
+    let total_plugins = plugins.len();
+
+    if !args.only_ide.is_empty() {
+        for only in &args.only_ide {
+            if !ides.iter().any(|ide| matches_ide(ide, only)) {
+                return Err(anyhow!(
+                    "--only-ide {only} matches none of the IDE versions to process, \
+                    use --backfill for older ones"
+                ));
+            }
+        }
+        ides.retain(|ide| args.only_ide.iter().any(|only| matches_ide(ide, only)));
+        info!("Only processing {} IDE versions.", ides.len());
+    }
+
+    if args.nixpkgs_check != NixpkgsCheck::Off {
+        info!("Cross-checking IDE versions with nixpkgs.");
+        let known = ides::nixpkgs::fetch_versions(&*fetcher, &args.nixpkgs_versions_url).await?;
+        ides = ides::nixpkgs::cross_check(ides, &known, args.nixpkgs_check);
+    }
+
+    let (journal, done) = Journal::open(output_path, args.resume).await?;
+    let mut db = if args.resume {
+        info!("Loading old database and IDE mappings to resume.");
+        let mut db = storage.load_full().await?;
+        db.adopt_build_numbers(&ides);
+        plugins.retain(|plugin| !done.contains(plugin));
+        info!(
+            "Resuming: {} plugins already processed, {} left.",
+            done.len(),
+            plugins.len()
+        );
+        db
+    } else {
+        info!("Loading old database.");
+        storage.load().await?
+    };
+    output.apply(&mut db);
+    if args.prioritize {
+        match plugins::api::fetch_popularity(&*fetcher).await {
+            Ok(popularity) => {
+                plugins::prioritize_popular(&mut plugins, &popularity);
+                info!("Processing recently updated and popular plugins first.");
+            }
+            Err(e) => warn!("Failed fetching plugin popularity, not prioritizing: {e:#}"),
+        }
+    }
+    if let Some(max_age) = args.refresh_stale {
+        let stale = db.prioritize_stale(&mut plugins, max_age)?;
+        info!(
+            "Processing {stale} plugins not processed successfully in the last {} first.",
+            humantime::format_duration(max_age)
+        );
+    }
+    let indexed = Instant::now();
+    info!(
+        phase = "index", duration_ms = (indexed - started).as_millis() as u64;
+        "Beginning plugin download..."
+    );
+    let ctx = plugins::UpdateContext {
+        fetcher,
+        hasher,
+        events: &LogEvents,
+        run,
+        journal: &journal,
+        storage,
+        flush_every: args.flush_every,
+        eap: args.eap_plugins,
+        known_plugins: &known_plugins,
+        bulk: args.bulk,
+        per_ide: args.per_ide,
+        fail_fast: args.fail_fast,
+        repositories: &repositories,
+        download_urls: &DownloadUrls {
+            prefixes: args.download_prefixes.clone(),
+            rewrites: args.download_rewrites.clone(),
+        },
+        exclude: &config.exclude,
+        release_channels: &config.release_channels,
+        verify_artifacts: args.verify_artifacts,
+    };
+    let mut outcome = plugins::db_update(&mut db, &ides, &plugins, &ctx).await?;
+    db.record_status(&outcome);
+    db.log_persistent_failures();
+    db.mark_delisted(&known_plugins);
+    db.update_bundled(&*ctx.fetcher, &ides, &known_plugins)
+        .await;
+    plugins::guard_regressions(
+        &mut db,
+        output_path,
+        &ides,
+        &known_plugins,
+        &config.exclude,
+        &mut outcome,
+    )
+    .await?;
+    let updated = Instant::now();
+    let new_ides = ides
+        .iter()
+        .filter(|ide| {
+            !output_path
+                .join("ides")
+                .join(ide.to_json_filename())
+                .exists()
+        })
+        .map(|ide| ide.to_string())
+        .collect();
+    info!(
+        phase = "update", duration_ms = (updated - indexed).as_millis() as u64;
+        "Saving DB..."
+    );
+    if args.validate_schema {
+        fail_on_violations(schema::validate_db(&db).await?)?;
+    }
+    storage.save(&db, &LogEvents).await?;
+    journal.commit().await?;
+    plugins::save_skips(output_path, &outcome.skips, args.resume).await?;
+    let profiles = plugins::load_profiles(&args.profiles).await?;
+    plugins::write_profiles(output_path, &db, &profiles).await?;
+    info!(phase = "save", duration_ms = updated.elapsed().as_millis() as u64; "Saved.");
+    if let Err(e) = ctx.hasher.finish().await {
+        warn!("Failed cleaning up after hashing: {e:#}");
+    }
+    outcome.log_summary();
+    let timings = Timings::new(
+        indexed - started,
+        updated - indexed,
+        updated.elapsed(),
+        started.elapsed(),
+    );
+    let summary = RunSummary::new(
+        total_plugins,
+        &outcome,
+        new_ides,
+        unknown_products,
+        db.recovered().to_vec(),
+        timings,
+        run,
+    );
+    summary.write(output_path).await?;
+    if args.github_summary {
+        summary.write_github_summary(&outcome)?;
+    }
+    if let Some(url) = &args.metrics_pushgateway
+        && let Err(e) = run.metrics().push(&http, url).await
+    {
+        warn!("failed pushing metrics to {url}: {e}");
+    }
+    run.set_summary(summary);
+
+    if run.shutdown().is_cancelled() {
+        return Err(anyhow!(
+            "interrupted: the saved IDE mappings only contain the plugins processed so far, \
+            run again with --resume to continue"
+        ));
+    }
+    let threshold = if args.strict {
+        Some(FailureThreshold::Count(0))
+    } else {
+        args.max_failures
+    };
+    if threshold.is_some_and(|threshold| outcome.exceeds(threshold)) {
+        return Err(anyhow!(
+            "{} of {} plugins failed, run again with --resume to retry them",
+            outcome.failed.len(),
+            outcome.processed + outcome.failed.len()
+        ));
+    }
+    journal.finish().await
+}
+
+/// Whether `ide` is selected by an `--only-ide` value, its nix key with or without version.
+fn matches_ide(ide: &IdeVersion, only: &str) -> bool {
+    ide.to_string() == only || ide.ide.nix_key() == only
+}
+
+/// Options of [`cleanup`], also the arguments of the `cleanup` command.
+#[derive(Default)]
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct CleanupOptions {
+    /// Also remove plugin versions that disappeared from the marketplace (see tombstones.json)
+    /// longer than this ago, e.g. `30d`, from the IDE mappings and the database.
+    #[cfg_attr(feature = "clap", arg(long, value_parser = humantime::parse_duration))]
+    pub purge_tombstones_after: Option<Duration>,
+    /// Also remove the files in `ides/` that aren't mappings of a supported IDE, e.g. of
+    /// products that were dropped.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub remove_unknown: bool,
+    /// Move the files removed by `--remove-unknown` to this directory instead of deleting them.
+    #[cfg_attr(feature = "clap", arg(long, requires = "remove_unknown"))]
+    pub quarantine: Option<PathBuf>,
+    /// Keep entries that no IDE mapping references for this long (e.g. `14d`) before removing
+    /// them, see unreferenced.json.
+    #[cfg_attr(feature = "clap", arg(long, value_parser = humantime::parse_duration))]
+    pub grace_period: Option<Duration>,
+    /// Keep entries that no IDE mapping references for this many cleanups in a row before
+    /// removing them. With `--grace-period`, both must have passed.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub grace_runs: Option<u32>,
+    /// Only print the plugin versions (`<id>/--/<version>`) and files that would be removed,
+    /// one per line, without changing anything.
+    #[cfg_attr(feature = "clap", arg(long))]
+    pub dry_run: bool,
+    /// Ask for confirmation, with what would be removed and kept, before changing anything.
+    #[cfg_attr(feature = "clap", arg(long, conflicts_with = "dry_run"))]
+    pub interactive: bool,
+}
+
+pub async fn cleanup(
+    output_path: &Path,
+    storage: &dyn Storage,
+    output: OutputOptions,
+    options: CleanupOptions,
+) -> anyhow::Result<()> {
+    let unknown_files = if options.remove_unknown {
+        plugins::unknown_ide_files(output_path).await?
+    } else {
+        Vec::new()
+    };
+    info!("Loading database and IDE mappings.");
+    let mut db = storage.load_full().await?;
+    output.apply(&mut db);
+
+    let mut purged = BTreeSet::new();
+    if let Some(grace) = options.purge_tombstones_after {
+        purged = db.purge_tombstones(grace)?;
+        info!("Purged {} tombstoned plugin versions.", purged.len());
+    }
+    info!("Running cleanup...");
+    let grace = plugins::Grace {
+        period: options.grace_period,
+        runs: options.grace_runs,
+    };
+    let report = plugins::db_cleanup(&mut db, grace).await?;
+    info!(
+        "Removed {} unused plugin versions, freeing {}{}.",
+        report.removed_entries,
+        run_summary::format_bytes(report.freed_bytes),
+        if report.unknown_size > 0 {
+            format!(" plus {} of unknown size", report.unknown_size)
+        } else {
+            String::new()
+        }
+    );
+    if report.kept_unreferenced > 0 {
+        info!(
+            "Keeping {} unreferenced plugin versions for their grace period.",
+            report.kept_unreferenced
+        );
+    }
+    if !report.removed_plugins.is_empty() {
+        info!(
+            "{} plugins have no versions left: {}",
+            report.removed_plugins.len(),
+            report.removed_plugins.join(", ")
+        );
+    }
+
+    if options.dry_run {
+        for path in &unknown_files {
+            println!("{}", path.display());
+        }
+        for key in purged.iter().chain(&report.removed) {
+            println!("{key}");
+        }
+        info!("Dry run, nothing was changed.");
+        return Ok(());
+    }
+    if options.interactive {
+        let removed = purged.len() + report.removed.len();
+        let question = format!(
+            "Remove {removed} of {} plugin versions ({} of them tombstoned) and {} unknown IDE \
+             files?",
+            removed + report.remaining_entries,
+            purged.len(),
+            unknown_files.len(),
+        );
+        if !confirm(&question).await? {
+            info!("Aborted, nothing was changed.");
+            return Ok(());
+        }
+    }
+    if options.remove_unknown {
+        let removed =
+            plugins::remove_unknown_ide_files(output_path, options.quarantine.as_deref()).await?;
+        info!("Removed {removed} unknown IDE files.");
+    }
+    info!("Saving DB...");
+    storage.save(&db, &LogEvents).await?;
+    run_summary::record_cleanup(output_path, &report).await?;
+
+    Ok(())
+}
+
+/// Asks `question` on the terminal. Only an answer starting with `y` confirms.
+async fn confirm(question: &str) -> anyhow::Result<bool> {
+    let question = question.to_string();
+    spawn_blocking(move || {
+        eprint!("{question} [y/N] ");
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        Ok(answer.trim_start().to_lowercase().starts_with('y'))
+    })
+    .await?
+}
+
+/// Options of [`repair`], also the arguments of the `repair` command.
+#[cfg_attr(feature = "clap", derive(clap::Args))]
+pub struct RepairOptions {
+    /// How to compute the hashes of plugin artifacts.
+    #[cfg_attr(feature = "clap", arg(long, value_enum, default_value_t))]
+    pub hasher: HasherKind,
+    /// Average number of marketplace requests per second. 0 disables the limit.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = 10.0))]
+    pub requests_per_second: f64,
+    /// Number of marketplace requests that may be made at once before the limit applies.
+    #[cfg_attr(feature = "clap", arg(long, default_value_t = 20))]
+    pub burst: u32,
+    /// Index of the plugin IDs that dependencies can be on, see `generate`.
+    #[cfg_attr(feature = "clap", arg(long = "plugin-index", default_values = PLUGIN_INDICES))]
+    pub plugin_indices: Vec<String>,
+    /// URL of an `updatePlugins.xml` of a custom plugin repository, see `generate`.
+    #[cfg_attr(feature = "clap", arg(long = "plugin-repository"))]
+    pub plugin_repositories: Vec<String>,
+    /// Plugins only distributed as direct downloads, see the README. Ignored if missing.
+    #[cfg_attr(feature = "clap", arg(long, default_value = "custom_plugins.toml"))]
+    pub custom_plugins: PathBuf,
+    /// Accepted prefixes of resolved marketplace download URLs, see `generate`.
+    #[cfg_attr(feature = "clap", arg(long = "download-prefix", default_value = MARKETPLACE_DOWNLOADS))]
+    pub download_prefixes: Vec<String>,
+    /// `FROM=TO`: download artifacts whose URL starts with FROM from TO instead, see `generate`.
+    #[cfg_attr(feature = "clap", arg(long = "download-rewrite", value_parser = plugins::parse_rewrite))]
+    pub download_rewrites: Vec<(String, String)>,
+    #[doc(hidden)]
+    #[cfg_attr(feature = "clap", command(flatten))]
+    pub test_hooks: TestHooks,
+}
+
+impl Default for RepairOptions {
+    /// The defaults of the `repair` command.
+    fn default() -> Self {
+        Self {
+            hasher: HasherKind::default(),
+            requests_per_second: 10.0,
+            burst: 20,
+            plugin_indices: PLUGIN_INDICES.iter().map(|url| url.to_string()).collect(),
+            plugin_repositories: Vec::new(),
+            custom_plugins: PathBuf::from("custom_plugins.toml"),
+            download_prefixes: vec![MARKETPLACE_DOWNLOADS.to_string()],
+            download_rewrites: Vec::new(),
+            test_hooks: TestHooks::default(),
+        }
+    }
+}
+
+/// Rebuilds the entries of all_plugins that the IDE mappings in `output_path` reference, e.g.
+/// after all_plugins.json was lost, by hashing only those plugin versions again. See
+/// [`plugins::db_repair`]. Requests go through `http` with the rate limit of `options` added.
+pub async fn repair(
+    output_path: &Path,
+    config: &Config,
+    http: &HttpContext,
+    storage: &dyn Storage,
+    output: OutputOptions,
+    options: RepairOptions,
+) -> anyhow::Result<()> {
+    info!("running repair.");
+    let run = RunContext::default();
+    let http = http
+        .clone()
+        .with_run(&run)
+        .with_rate_limit(options.requests_per_second, options.burst);
+    let hasher = options.hasher.build(&http, output_path, None, None)?;
+    let fetcher = options.test_hooks.fetcher(&http)?;
+    let indices = try_join_all(
+        options
+            .plugin_indices
+            .iter()
+            .map(|source| plugins::index(&*fetcher, &run, source)),
+    )
+    .await?;
+    let mut repositories = HashMap::new();
+    plugins::load_custom_plugins(&options.custom_plugins, &mut repositories).await?;
+    plugins::fetch_repositories(&*fetcher, &options.plugin_repositories, &mut repositories).await?;
+    let mut known_plugins: HashSet<_> = indices.into_iter().flatten().collect();
+    known_plugins.extend(repositories.keys().cloned());
+
+    info!("Loading database and IDE mappings.");
+    let mut db = plugins::load_for_repair(output_path).await?;
+    output.apply(&mut db);
+    let ctx = plugins::RepairContext {
+        fetcher,
+        hasher,
+        known_plugins: &known_plugins,
+        repositories: &repositories,
+        download_urls: &DownloadUrls {
+            prefixes: options.download_prefixes,
+            rewrites: options.download_rewrites,
+        },
+        release_channels: &config.release_channels,
+        run: &run,
+    };
+    let outcome = plugins::db_repair(&mut db, &ctx).await?;
+    info!(
+        "Restored {} plugin versions, removed {} that can't be restored from the IDE mappings.",
+        outcome.restored,
+        outcome.dropped.len()
+    );
+    info!("Saving DB...");
+    storage.save(&db, &LogEvents).await?;
+    if let Err(e) = ctx.hasher.finish().await {
+        warn!("Failed cleaning up after hashing: {e:#}");
+    }
+    Ok(())
+}
+
+/// Deletes the Nix store paths that `--hasher nix` added and that runs didn't get to delete,
+/// e.g. because they crashed. See [`StorePaths`].
+pub async fn gc(output_path: &Path) -> anyhow::Result<()> {
+    info!("running gc.");
+    StorePaths::new(output_path).delete_all().await
+}
+
+/// Rewrites all files of the database in `storage` in their canonical form (in the format
+/// chosen by `output`), without changing their contents.
+pub async fn normalize(storage: &dyn Storage, output: OutputOptions) -> anyhow::Result<()> {
+    info!("running normalize.");
+    let mut db = storage.load_full().await?;
+    output.apply(&mut db);
+    db.rewrite_all();
+    info!("Saving DB...");
+    storage.save(&db, &LogEvents).await?;
+    Ok(())
+}
+
+/// Fails with the violations of the JSON Schemas by the output in `output_path`, see
+/// [`schema::validate_output`].
+pub async fn validate_schema(output_path: &Path) -> anyhow::Result<()> {
+    fail_on_violations(schema::validate_output(output_path).await?)
+}
+
+fn fail_on_violations(violations: Vec<String>) -> anyhow::Result<()> {
+    for violation in &violations {
+        error!("{violation}");
+    }
+    if !violations.is_empty() {
+        return Err(anyhow!(
+            "the output violates its JSON Schemas in {} places",
+            violations.len()
+        ));
+    }
+    info!("The output matches its JSON Schemas.");
+    Ok(())
+}
+
+#[cfg(all(test, feature = "clap"))]
+mod tests {
+    use super::*;
+    use clap::{Args, Parser};
+
+    #[derive(Parser)]
+    struct Command<T: Args> {
+        #[command(flatten)]
+        options: T,
+    }
+
+    #[test]
+    fn generate_defaults_match_clap() {
+        let parsed = Command::<GenerateOptions>::parse_from(["generate"]).options;
+        let default = GenerateOptions::default();
+        assert_eq!(parsed.hasher, default.hasher);
+        assert_eq!(parsed.flush_every, default.flush_every);
+        assert_eq!(parsed.channels, default.channels);
+        assert_eq!(parsed.nixpkgs_check, default.nixpkgs_check);
+        assert_eq!(parsed.nixpkgs_versions_url, default.nixpkgs_versions_url);
+        assert_eq!(parsed.requests_per_second, default.requests_per_second);
+        assert_eq!(parsed.burst, default.burst);
+        assert_eq!(parsed.http_cache_max_age, default.http_cache_max_age);
+        assert_eq!(
+            parsed.artifact_cache_max_size,
+            default.artifact_cache_max_size
+        );
+        assert_eq!(parsed.plugin_indices, default.plugin_indices);
+        assert_eq!(parsed.custom_plugins, default.custom_plugins);
+        assert_eq!(parsed.profiles, default.profiles);
+        assert_eq!(parsed.download_prefixes, default.download_prefixes);
+    }
+
+    #[test]
+    fn repair_defaults_match_clap() {
+        let parsed = Command::<RepairOptions>::parse_from(["repair"]).options;
+        let default = RepairOptions::default();
+        assert_eq!(parsed.hasher, default.hasher);
+        assert_eq!(parsed.requests_per_second, default.requests_per_second);
+        assert_eq!(parsed.burst, default.burst);
+        assert_eq!(parsed.plugin_indices, default.plugin_indices);
+        assert_eq!(parsed.custom_plugins, default.custom_plugins);
+        assert_eq!(parsed.download_prefixes, default.download_prefixes);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::fetch::Fetcher;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Maximum length of [`PluginMeta::description`] in characters.
+const MAX_DESCRIPTION_LEN: usize = 200;
+
+/// Human-readable information about a plugin, stored in plugins_meta.json keyed by plugin ID.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginMeta {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    /// First sentence of the description, as plain text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// `None` if the marketplace did not tell.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<PricingModel>,
+}
+
+/// Whether a plugin needs a license.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PricingModel {
+    Free,
+    /// Usable without a license, with some features requiring one.
+    Freemium,
+    /// Requires a license (possibly after a trial period).
+    Paid,
+}
+
+impl PricingModel {
+    /// Parses the `pricingModel` of the marketplace API.
+    pub fn from_api(pricing_model: &str) -> Option<Self> {
+        match pricing_model {
+            "FREE" => Some(PricingModel::Free),
+            "FREEMIUM" => Some(PricingModel::Freemium),
+            "PAID" => Some(PricingModel::Paid),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MarketplacePlugin {
+    pricing_model: Option<String>,
+}
+
+/// The numeric marketplace ID of a plugin, derived from the path of one of its artifacts,
+/// which starts with `files/<numeric plugin ID>/`.
+pub fn numeric_plugin_id(artifact_path: &str) -> Option<&str> {
+    let numeric_id = artifact_path.strip_prefix("files/")?.split('/').next()?;
+    (!numeric_id.is_empty() && numeric_id.chars().all(|c| c.is_ascii_digit())).then_some(numeric_id)
+}
+
+/// Marketplace page of a plugin, see [`numeric_plugin_id`].
+pub fn marketplace_url(artifact_path: &str) -> Option<String> {
+    numeric_plugin_id(artifact_path)
+        .map(|numeric_id| format!("https://plugins.jetbrains.com/plugin/{numeric_id}"))
+}
+
+/// Fetches the pricing model of a plugin from the marketplace API.
+pub async fn fetch_pricing(
+    fetcher: &dyn Fetcher,
+    numeric_id: &str,
+) -> anyhow::Result<Option<PricingModel>> {
+    let plugin: MarketplacePlugin = fetcher
+        .get(&format!(
+            "https://plugins.jetbrains.com/api/plugins/{numeric_id}"
+        ))
+        .await?
+        .success(&format!("plugin {numeric_id}: failed API request"))?
+        .json()?;
+    Ok(plugin
+        .pricing_model
+        .as_deref()
+        .and_then(PricingModel::from_api))
+}
+
+/// Turns an HTML plugin description into a short plain text summary.
+pub fn short_description(html: &str) -> Option<String> {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                // Tags usually separate words (<br>, <p>, <li>).
+                text.push(' ');
+            }
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let sentence = match text.find(". ") {
+        Some(end) => &text[..=end],
+        None => &text,
+    };
+    let mut summary: String = sentence.chars().take(MAX_DESCRIPTION_LEN).collect();
+    if summary.len() < sentence.len() {
+        summary.push('…');
+    }
+    (!summary.is_empty()).then_some(summary)
+}
+
+/// Normalized plugin names mapped to plugin IDs, written to aliases.json.
+#[derive(Debug, Default, Serialize)]
+pub struct Aliases {
+    /// Names of exactly one plugin.
+    pub aliases: BTreeMap<String, String>,
+    /// Names shared by several plugins, with all of their IDs. They are not in `aliases`.
+    pub ambiguous: BTreeMap<String, Vec<String>>,
+}
+
+impl Aliases {
+    pub fn new(meta: &BTreeMap<String, PluginMeta>) -> Self {
+        let mut by_name: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (pluginkey, meta) in meta {
+            if let Some(alias) = normalize_name(&meta.name) {
+                by_name.entry(alias).or_default().push(pluginkey.clone());
+            }
+        }
+        let mut aliases = Self::default();
+        for (alias, mut pluginkeys) in by_name {
+            if pluginkeys.len() == 1 {
+                aliases.aliases.insert(alias, pluginkeys.remove(0));
+            } else {
+                aliases.ambiguous.insert(alias, pluginkeys);
+            }
+        }
+        aliases
+    }
+}
+
+/// Lowercases a plugin name and joins its alphanumeric words with dashes, e.g.
+/// `GitHub Copilot` becomes `github-copilot`.
+pub fn normalize_name(name: &str) -> Option<String> {
+    let alias = name
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    (!alias.is_empty()).then_some(alias)
+}
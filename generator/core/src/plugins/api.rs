@@ -0,0 +1,238 @@
+//! Client for the JSON API of the JetBrains Marketplace, the primary source of plugin details.
+
+use super::{PluginDetailsIdeaPlugin, PluginDetailsIdeaVersion, PluginDetailsVendor};
+use crate::fetch::Fetcher;
+use crate::plugin_meta::PricingModel;
+use anyhow::anyhow;
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const API_URL_ROOT: &str = "https://plugins.jetbrains.com/api";
+const API_URL: &str = "https://plugins.jetbrains.com/api/plugins";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiPlugin {
+    id: u64,
+    xml_id: String,
+    name: Option<String>,
+    vendor: Option<ApiVendor>,
+    description: Option<String>,
+    pricing_model: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiVendor {
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiUpdate {
+    version: String,
+    /// Empty for the stable channel.
+    #[serde(default)]
+    channel: String,
+    since: Option<String>,
+    until: Option<String>,
+    /// Plugin IDs of (non-optional) dependencies.
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+/// Fetches all versions of a plugin in the given release channel (`None` for stable). Returns
+/// `None` if the marketplace does not know the plugin.
+pub async fn fetch_versions(
+    fetcher: &dyn Fetcher,
+    pluginkey: &str,
+    channel: Option<&str>,
+) -> anyhow::Result<Option<Vec<PluginDetailsIdeaPlugin>>> {
+    let mut url = Url::parse(API_URL)?;
+    url.path_segments_mut()
+        .map_err(|()| anyhow!("invalid API URL"))?
+        .extend(["intellij", pluginkey]);
+    let Some(plugin) = get::<ApiPlugin>(fetcher, url, pluginkey).await? else {
+        return Ok(None);
+    };
+    // Lookups are case-insensitive, like the XML endpoint.
+    if !plugin.xml_id.eq_ignore_ascii_case(pluginkey) {
+        return Ok(None);
+    }
+
+    let channel_name = channel.unwrap_or_default();
+    let mut url = Url::parse(&format!("{API_URL}/{}/updates", plugin.id))?;
+    url.query_pairs_mut().append_pair("channel", channel_name);
+    let updates = get::<Vec<ApiUpdate>>(fetcher, url, pluginkey)
+        .await?
+        .unwrap_or_default();
+
+    let pricing = plugin
+        .pricing_model
+        .as_deref()
+        .and_then(PricingModel::from_api);
+    Ok(Some(
+        updates
+            .into_iter()
+            .filter(|update| update.channel == channel_name)
+            .map(|update| PluginDetailsIdeaPlugin {
+                id: plugin.xml_id.clone(),
+                version: update.version,
+                idea_version: PluginDetailsIdeaVersion {
+                    since_build: update.since.filter(|since| !since.is_empty()),
+                    until_build: update.until.filter(|until| !until.is_empty()),
+                },
+                depends: update.dependencies,
+                name: plugin.name.clone(),
+                vendor: plugin.vendor.as_ref().map(|vendor| PluginDetailsVendor {
+                    name: vendor.name.clone().unwrap_or_default(),
+                }),
+                description: plugin.description.clone(),
+                pricing,
+                channel: channel.map(str::to_string),
+            })
+            .collect(),
+    ))
+}
+
+async fn get<T: for<'de> Deserialize<'de>>(
+    fetcher: &dyn Fetcher,
+    url: Url,
+    pluginkey: &str,
+) -> anyhow::Result<Option<T>> {
+    let resp = fetcher.get(url.as_str()).await?;
+    if resp.status == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(
+        resp.success(&format!("{pluginkey} failed API request"))?
+            .json()?,
+    ))
+}
+
+/// Number of plugins per search request.
+const SEARCH_PAGE: usize = 1000;
+
+#[derive(Deserialize)]
+struct SearchResult {
+    plugins: Vec<SearchPlugin>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchPlugin {
+    xml_id: String,
+    #[serde(default)]
+    downloads: u64,
+    /// Date of the last update, in milliseconds since the epoch.
+    cdate: Option<i64>,
+}
+
+/// How relevant a plugin is to process early, see [`super::prioritize_popular`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Popularity {
+    pub downloads: u64,
+    /// Date of the last update, in milliseconds since the epoch. 0 if unknown.
+    pub updated: i64,
+}
+
+/// The download count and last update of all marketplace plugins, by plugin ID, fetched in
+/// pages of the plugin search.
+pub async fn fetch_popularity(
+    fetcher: &dyn Fetcher,
+) -> anyhow::Result<HashMap<String, Popularity>> {
+    let mut popularity = HashMap::new();
+    for offset in (0..).step_by(SEARCH_PAGE) {
+        let mut url = Url::parse(&format!("{API_URL_ROOT}/searchPlugins"))?;
+        url.query_pairs_mut()
+            .append_pair("max", &SEARCH_PAGE.to_string())
+            .append_pair("offset", &offset.to_string())
+            .append_pair("orderBy", "update date");
+        let page: SearchResult = fetcher
+            .get(url.as_str())
+            .await?
+            .success("failed plugin search request")?
+            .json()?;
+        let len = page.plugins.len();
+        popularity.extend(page.plugins.into_iter().map(|plugin| {
+            let popularity = Popularity {
+                downloads: plugin.downloads,
+                updated: plugin.cdate.unwrap_or_default(),
+            };
+            (plugin.xml_id, popularity)
+        }));
+        if len < SEARCH_PAGE {
+            break;
+        }
+    }
+    Ok(popularity)
+}
+
+/// Number of plugin IDs per compatible updates request.
+const COMPATIBLE_UPDATES_PAGE: usize = 500;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CompatibleUpdatesRequest<'a> {
+    build: &'a str,
+    #[serde(rename = "pluginXMLIds")]
+    plugin_xml_ids: &'a [String],
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CompatibleUpdate {
+    plugin_xml_id: String,
+    version: String,
+}
+
+/// The newest stable version of each of `pluginkeys` compatible with `build` (including the
+/// product code, e.g. `IU-251.23774.435`), fetched in pages. Plugins without a compatible
+/// version are missing from the result.
+pub async fn compatible_updates(
+    fetcher: &dyn Fetcher,
+    build: &str,
+    pluginkeys: &[String],
+) -> anyhow::Result<HashMap<String, String>> {
+    let url = format!("{API_URL_ROOT}/search/compatibleUpdates");
+    let mut versions = HashMap::new();
+    for page in pluginkeys.chunks(COMPATIBLE_UPDATES_PAGE) {
+        let request = CompatibleUpdatesRequest {
+            build,
+            plugin_xml_ids: page,
+        };
+        let updates: Vec<CompatibleUpdate> = fetcher
+            .post_json(&url, &serde_json::to_value(&request)?)
+            .await?
+            .success(&format!("{build}: failed compatible updates request"))?
+            .json()?;
+        versions.extend(
+            updates
+                .into_iter()
+                .map(|update| (update.plugin_xml_id, update.version)),
+        );
+    }
+    Ok(versions)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BundledPlugin {
+    plugin_xml_id: String,
+}
+
+/// IDs of the plugins bundled with the IDE `build` (including the product code, e.g.
+/// `IU-251.23774.435`). Returns `None` if the marketplace has no manifest for the build.
+pub async fn bundled_plugins(
+    fetcher: &dyn Fetcher,
+    build: &str,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let mut url = Url::parse(&format!("{API_URL_ROOT}/search/bundledPlugins"))?;
+    url.query_pairs_mut().append_pair("build", build);
+    let bundled = get::<Vec<BundledPlugin>>(fetcher, url, build).await?;
+    Ok(bundled.map(|bundled| {
+        bundled
+            .into_iter()
+            .map(|plugin| plugin.plugin_xml_id)
+            .collect()
+    }))
+}
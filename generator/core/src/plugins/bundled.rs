@@ -0,0 +1,84 @@
+//! Marketplace plugins that IDE distributions already bundle (e.g. Docker, Terminal), in
+//! bundled.json, so that users listing them redundantly can be warned.
+
+use super::{PluginDb, api};
+use crate::fetch::Fetcher;
+use crate::fs::write_if_changed;
+use crate::ides::IdeVersion;
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+use tokio::fs::{read_to_string, remove_file, try_exists};
+
+const BUNDLED_JSON: &str = "bundled.json";
+
+/// IDE (`<nix key>-<version>`) -> IDs of the marketplace plugins bundled with it.
+pub type Bundled = BTreeMap<String, BTreeSet<String>>;
+
+impl PluginDb {
+    /// Fetches the bundled plugins of each of `ides` and records those that are also
+    /// marketplace plugins (in `indexed`). IDEs whose manifest can't be fetched keep their
+    /// previous record.
+    pub async fn update_bundled(
+        &mut self,
+        fetcher: &dyn Fetcher,
+        ides: &[IdeVersion],
+        indexed: &HashSet<String>,
+    ) {
+        for ide in ides {
+            let build = format!("{}-{}", ide.ide.product_code(), ide.build_number);
+            let bundled = match api::bundled_plugins(fetcher, &build).await {
+                Ok(Some(bundled)) => bundled,
+                Ok(None) => {
+                    debug!("{build}: no bundled plugins manifest.");
+                    continue;
+                }
+                Err(e) => {
+                    warn!("{build}: failed fetching bundled plugins, keeping previous: {e:#}");
+                    continue;
+                }
+            };
+            let bundled: BTreeSet<_> = bundled
+                .into_iter()
+                .filter(|pluginkey| indexed.contains(pluginkey))
+                .collect();
+            if bundled.is_empty() {
+                self.bundled.remove(&ide.to_string());
+                continue;
+            }
+            info!(
+                "{ide} bundles {} marketplace plugins: {}",
+                bundled.len(),
+                bundled.iter().cloned().collect::<Vec<_>>().join(", ")
+            );
+            self.bundled.insert(ide.to_string(), bundled);
+        }
+    }
+
+    pub fn bundled(&self) -> &Bundled {
+        &self.bundled
+    }
+}
+
+/// Reads bundled.json in `out_dir`, if there is one.
+pub async fn load(out_dir: &Path) -> anyhow::Result<Bundled> {
+    let path = out_dir.join(BUNDLED_JSON);
+    if !try_exists(&path).await? {
+        return Ok(Bundled::new());
+    }
+    Ok(serde_json::from_str(&read_to_string(path).await?)?)
+}
+
+/// Writes the bundled plugins of `db` to bundled.json in `out_dir`, or removes it if no IDE
+/// bundles any marketplace plugin.
+pub async fn save(out_dir: &Path, db: &PluginDb) -> anyhow::Result<()> {
+    let path = out_dir.join(BUNDLED_JSON);
+    if db.bundled.is_empty() {
+        if try_exists(&path).await? {
+            remove_file(&path).await?;
+        }
+        return Ok(());
+    }
+    write_if_changed(&path, serde_json::to_string_pretty(&db.bundled)?).await?;
+    Ok(())
+}
@@ -0,0 +1,38 @@
+//! Validation of plugin IDs (XML IDs) from the indices and plugin lists, before they end up in
+//! request URLs.
+//!
+//! Real IDs may contain spaces (`String Manipulation`), `+` or non-ASCII characters, so those
+//! are only percent-encoded in URLs, not rejected.
+
+use std::fmt::{self, Display, Formatter};
+
+/// Why a plugin ID can't be requested from the marketplace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidId {
+    /// Empty, or only whitespace.
+    Empty,
+    /// Contains control characters like line breaks.
+    Control,
+}
+
+impl Display for InvalidId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InvalidId::Empty => "empty",
+            InvalidId::Control => "contains control characters",
+        })
+    }
+}
+
+/// Trims surrounding whitespace from `pluginkey` and checks that the marketplace could serve
+/// it.
+pub fn normalize_plugin_id(pluginkey: &str) -> Result<&str, InvalidId> {
+    let pluginkey = pluginkey.trim();
+    if pluginkey.is_empty() {
+        Err(InvalidId::Empty)
+    } else if pluginkey.chars().any(char::is_control) {
+        Err(InvalidId::Control)
+    } else {
+        Ok(pluginkey)
+    }
+}
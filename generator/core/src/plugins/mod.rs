@@ -0,0 +1,2747 @@
+use crate::build_number::BuildNumber;
+use crate::compat::{CompatibilityInfo, compatible_version};
+use crate::compression::Compression;
+use crate::config::{Exclude, ReleaseChannels};
+use crate::error::{self, ErrorKind, StatusError};
+use crate::events::{GeneratorEvents, SkipReason};
+use crate::fetch::Fetcher;
+use crate::fs::{compress_file, write_atomic, write_if_changed, write_json_if_changed};
+use crate::hashing::{Hasher, UnpackError};
+use crate::ides::IdeVersion;
+use crate::journal::Journal;
+use crate::migrations;
+use crate::plugin_meta::{
+    Aliases, PluginMeta, PricingModel, fetch_pricing, marketplace_url, numeric_plugin_id,
+    short_description,
+};
+use crate::progress::Progress;
+use crate::run_context::RunContext;
+use crate::version_order::compare_versions;
+use anyhow::anyhow;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use futures::stream::iter;
+use futures::{StreamExt, TryStreamExt};
+use log::{debug, info, warn};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, btree_map};
+use std::fmt::{Display, Formatter};
+use std::fs::exists;
+use std::io::Read;
+use std::mem::take;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::fs::{
+    create_dir_all, read, read_dir, read_to_string, remove_dir_all, remove_file, rename,
+};
+use tokio::sync::{OnceCell, RwLock};
+use tokio::task::{block_in_place, spawn_blocking};
+use tokio::time::timeout;
+use tokio_retry2::strategy::{ExponentialBackoff, jitter};
+use tokio_retry2::{Retry, RetryError};
+use tokio_stream::wrappers::ReadDirStream;
+
+pub mod api;
+mod bundled;
+mod ids;
+mod nix;
+mod profiles;
+mod recovery;
+mod regressions;
+mod repair;
+mod repository;
+mod skips;
+mod sqlite;
+mod status;
+mod storage;
+mod tombstones;
+mod unreferenced;
+mod urls;
+
+pub use bundled::Bundled;
+pub use ids::{InvalidId, normalize_plugin_id};
+pub use profiles::{Profile, load_profiles, write_profiles};
+pub use recovery::RecoveredFile;
+pub use regressions::{DropReason, DroppedVersion, Regressions, guard_regressions};
+pub use repair::{RepairContext, RepairOutcome, db_repair, load_for_repair};
+pub use repository::{RepositoryPlugin, fetch_repositories, load_custom_plugins};
+pub use skips::{Skip, SkipKind, Skips, save as save_skips};
+pub use status::{PluginFailure, PluginStatus, PluginStatuses};
+pub use storage::{DbBackend, MemoryStorage, Storage};
+pub use tombstones::{Tombstone, TombstoneReason, Tombstones};
+pub use unreferenced::{Grace, Unreferenced, UnreferencedSince};
+pub use urls::{DownloadUrls, MARKETPLACE_DOWNLOADS, parse_rewrite};
+
+const ALL_PLUGINS_JSON: &str = "all_plugins.json";
+const ALL_PLUGINS_DIR: &str = "all_plugins";
+const PLUGINS_META_JSON: &str = "plugins_meta.json";
+const INDEX_JSON: &str = "index.json";
+const ALIASES_JSON: &str = "aliases.json";
+pub const SRI_PREFIX: &str = "sha256-";
+
+/// Key of a plugin version in all_plugins.
+///
+/// Serialized as `<name>/--/<version>`, with `%` and `/` in the name percent-encoded so that
+/// the first separator always ends the name. Must match `findPlugin` in plugins.nix.
+#[derive(Clone, Debug, PartialOrd, PartialEq, Ord, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct PluginVersion {
+    pub name: String,
+    pub version: String,
+}
+
+impl PluginVersion {
+    pub const SEPARATOR: &'static str = "/--/";
+
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+        }
+    }
+
+    fn escape_name(name: &str) -> String {
+        name.replace('%', "%25").replace('/', "%2F")
+    }
+
+    fn unescape_name(name: &str) -> anyhow::Result<String> {
+        let mut unescaped = String::with_capacity(name.len());
+        let mut rest = name;
+        while let Some(i) = rest.find('%') {
+            unescaped.push_str(&rest[..i]);
+            match rest.get(i + 1..i + 3) {
+                Some("25") => unescaped.push('%'),
+                Some("2F") => unescaped.push('/'),
+                _ => return Err(anyhow!("invalid escape in plugin name: {name}")),
+            }
+            rest = &rest[i + 3..];
+        }
+        unescaped.push_str(rest);
+        Ok(unescaped)
+    }
+}
+
+impl Display for PluginVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            Self::escape_name(&self.name),
+            Self::SEPARATOR,
+            self.version
+        )
+    }
+}
+
+impl FromStr for PluginVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, version) = s
+            .split_once(Self::SEPARATOR)
+            .ok_or_else(|| anyhow!("invalid plugin version key: {s}"))?;
+        Ok(Self {
+            name: Self::unescape_name(name)?,
+            version: version.to_string(),
+        })
+    }
+}
+
+impl From<PluginVersion> for String {
+    fn from(value: PluginVersion) -> Self {
+        value.to_string()
+    }
+}
+
+impl TryFrom<String> for PluginVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// The slot of a plugin version in an IDE mapping. `Eap` holds versions of all pre-release
+/// channels, see [`PluginDbEntry::channel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Stable,
+    Eap,
+}
+
+/// The plugin versions recorded for one plugin in an IDE mapping.
+/// Serialized as a plain version string if there only is a stable version, otherwise as
+/// `{ "stable": "1.2.3", "eap": "1.3.0-eap1" }`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "PluginChannelsRepr", into = "PluginChannelsRepr")]
+pub struct PluginChannels {
+    pub stable: Option<String>,
+    pub eap: Option<String>,
+}
+
+impl PluginChannels {
+    /// Records `version` for `channel`. Returns whether it was a different one before.
+    pub fn set(&mut self, channel: Channel, version: &str) -> bool {
+        let slot = match channel {
+            Channel::Stable => &mut self.stable,
+            Channel::Eap => &mut self.eap,
+        };
+        if slot.as_deref() == Some(version) {
+            return false;
+        }
+        *slot = Some(version.to_string());
+        true
+    }
+
+    pub fn versions(&self) -> impl Iterator<Item = &str> {
+        self.stable.iter().chain(&self.eap).map(String::as_str)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum PluginChannelsRepr {
+    Stable(String),
+    Channels {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stable: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        eap: Option<String>,
+    },
+}
+
+impl From<PluginChannelsRepr> for PluginChannels {
+    fn from(value: PluginChannelsRepr) -> Self {
+        match value {
+            PluginChannelsRepr::Stable(stable) => Self {
+                stable: Some(stable),
+                eap: None,
+            },
+            PluginChannelsRepr::Channels { stable, eap } => Self { stable, eap },
+        }
+    }
+}
+
+impl From<PluginChannels> for PluginChannelsRepr {
+    fn from(value: PluginChannels) -> Self {
+        match value {
+            PluginChannels {
+                stable: Some(stable),
+                eap: None,
+            } => PluginChannelsRepr::Stable(stable),
+            PluginChannels { stable, eap } => PluginChannelsRepr::Channels { stable, eap },
+        }
+    }
+}
+
+/// The plugins available for one IDE version.
+#[derive(Debug, Default, Clone)]
+pub struct IdeMapping {
+    pub plugins: BTreeMap<String, PluginChannels>,
+    /// When the mapping was last generated. `None` if it changed during this run, or if its
+    /// file was written without the time (see [`PluginDb::deterministic`]).
+    generated_at: Option<String>,
+}
+
+/// Metadata header of the IDE JSON files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct IdeFileMeta {
+    build_number: String,
+    product_code: String,
+    /// Left out with [`PluginDb::deterministic`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    generated_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IdeFile<P> {
+    meta: IdeFileMeta,
+    plugins: P,
+}
+
+/// IDE JSON files are read in both the current format and the old flat plugin map format
+/// without metadata.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IdeFileCompat {
+    WithMeta(IdeFile<BTreeMap<String, PluginChannels>>),
+    Flat(BTreeMap<String, PluginChannels>),
+}
+
+/// Layout of all_plugins.json, see [`crate::migrations`] for older ones.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct AllPluginsFile<P> {
+    schema_version: u64,
+    /// Missing in files written by older generators.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    meta: Option<GeneratorMeta>,
+    plugins: P,
+}
+
+/// Which generator build wrote a file, and when.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct GeneratorMeta {
+    generator_version: String,
+    /// Git revision the generator was built from, if known at build time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    git_revision: Option<String>,
+    /// Left out with [`PluginDb::deterministic`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    generated_at: Option<String>,
+}
+
+impl GeneratorMeta {
+    fn current(deterministic: bool) -> Self {
+        Self {
+            generator_version: env!("CARGO_PKG_VERSION").to_string(),
+            git_revision: option_env!("GENERATOR_GIT_REV").map(str::to_string),
+            generated_at: (!deterministic).then(now_rfc3339),
+        }
+    }
+}
+
+fn now_rfc3339() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+}
+
+/// The entry of each plugin version resolved in a run, `None` if it is not available.
+type EntryMemo = HashMap<PluginVersion, Arc<OnceCell<Option<Arc<PluginDbEntry>>>>>;
+
+#[derive(Default, Clone)]
+pub struct PluginDb {
+    // all_plugins caches all entries, ides contains references to them.
+    all_plugins: BTreeMap<PluginVersion, Arc<PluginDbEntry>>,
+    ides: HashMap<IdeVersion, IdeMapping>,
+    // IDE mappings changed since the last flush.
+    dirty_ides: HashSet<IdeVersion>,
+    meta: BTreeMap<String, PluginMeta>,
+    /// Plugin versions that disappeared from the marketplace, see [`PluginDb::tombstone`].
+    tombstones: Tombstones,
+    /// When plugins were last processed successfully, see [`PluginDb::record_status`].
+    status: PluginStatuses,
+    /// Entries kept by cleanup although unreferenced, see [`PluginDb::mark_unreferenced`].
+    unreferenced: Unreferenced,
+    /// Plugins whose entries were added, changed or removed since loading. Files of
+    /// all_plugins without any of them are not rewritten.
+    changed_plugins: HashSet<String>,
+    /// Metadata of the all_plugins files as loaded, by path relative to the output directory.
+    /// Kept in files whose entries didn't change.
+    entries_meta: HashMap<String, GeneratorMeta>,
+    /// Marketplace plugins bundled with each IDE, see [`PluginDb::update_bundled`].
+    bundled: Bundled,
+    /// all_plugins files that were corrupted when loading, see [`PluginDb::recovered`].
+    recovered: Vec<RecoveredFile>,
+    /// How all_plugins is stored. Defaults to the layout it was loaded from.
+    pub layout: PluginsLayout,
+    /// Also save the JSON files as Nix expressions (`.nix` next to each `.json`). Defaults to
+    /// whether the output directory already has them.
+    pub nix_output: bool,
+    /// Write all_plugins without indentation and line breaks. Defaults to whether it was
+    /// loaded from such a file.
+    pub compact: bool,
+    /// Also save all_plugins and the IDE mappings compressed next to the JSON files. Defaults
+    /// to the compression already in the output directory.
+    pub compression: Compression,
+    /// Leave the generation times out of the written files, so that the same database always
+    /// gives byte-identical output. Defaults to whether the loaded index.json has none.
+    pub deterministic: bool,
+    /// Rewrite every file when saving, even if its contents didn't change, see
+    /// [`PluginDb::rewrite_all`].
+    rewrite: bool,
+}
+
+/// How the plugin entries (all_plugins) are stored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum PluginsLayout {
+    /// A single all_plugins.json.
+    #[default]
+    Single,
+    /// `all_plugins/<shard>.json`, sharded by the first character of the plugin ID.
+    Sharded,
+}
+
+/// Shard of a plugin version key in [`PluginsLayout::Sharded`]. Must match `shardOf` in
+/// plugins.nix.
+fn shard_of(name: &str) -> String {
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphanumeric() => c.to_ascii_lowercase().to_string(),
+        _ => "_".to_string(),
+    }
+}
+
+impl PluginDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn init(init: impl IntoIterator<Item = (PluginVersion, PluginDbEntry)>) -> PluginDb {
+        Self {
+            all_plugins: init.into_iter().map(|(k, v)| (k, Arc::new(v))).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The all_plugins files that were corrupted when loading the database, and that only
+    /// their salvageable entries were loaded from.
+    pub fn recovered(&self) -> &[RecoveredFile] {
+        &self.recovered
+    }
+
+    /// Makes the next save rewrite every file in its canonical form, e.g. after they were
+    /// edited by hand or written by an older generator. Contents and generation times are kept.
+    pub fn rewrite_all(&mut self) {
+        self.rewrite = true;
+    }
+
+    /// Records `version` of plugin `name` as compatible with `ideversion`. Returns whether the
+    /// plugin entry is new or changed.
+    pub fn insert(
+        &mut self,
+        ideversion: &IdeVersion,
+        channel: Channel,
+        name: &str,
+        version: &str,
+        entry: PluginDbEntry,
+    ) -> bool {
+        let mapping = self.ides.entry(ideversion.clone()).or_default();
+        let changed = match self.all_plugins.entry(PluginVersion::new(name, version)) {
+            btree_map::Entry::Occupied(existing) if **existing.get() == entry => false,
+            btree_map::Entry::Occupied(mut existing) => {
+                existing.insert(Arc::new(entry));
+                true
+            }
+            btree_map::Entry::Vacant(vacant) => {
+                vacant.insert(Arc::new(entry));
+                true
+            }
+        };
+        if changed {
+            self.changed_plugins.insert(name.to_string());
+        }
+        let mapping_changed = mapping
+            .plugins
+            .entry(name.to_string())
+            .or_default()
+            .set(channel, version);
+        if mapping_changed {
+            mapping.generated_at = None;
+            self.dirty_ides.insert(ideversion.clone());
+        }
+        changed
+    }
+
+    /// Number of known versions of each plugin.
+    fn version_counts(&self) -> HashMap<&str, usize> {
+        let mut counts = HashMap::new();
+        for key in self.all_plugins.keys() {
+            *counts.entry(key.name.as_str()).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Plugin versions in the IDE mappings without an entry in all_plugins. Nix can't build
+    /// these, so the database is never saved with any.
+    pub fn dangling(&self) -> BTreeSet<PluginVersion> {
+        self.ides
+            .values()
+            .flat_map(|mapping| {
+                mapping.plugins.iter().flat_map(|(name, channels)| {
+                    channels
+                        .versions()
+                        .map(|version| PluginVersion::new(name, version))
+                })
+            })
+            .filter(|key| !self.all_plugins.contains_key(key))
+            .collect()
+    }
+
+    /// IDE versions loaded from JSON filenames have no build number. Replace them with the
+    /// matching entries of `ides`, so that new inserts end up in the same mapping.
+    pub fn adopt_build_numbers(&mut self, ides: &[IdeVersion]) {
+        self.ides = take(&mut self.ides)
+            .into_iter()
+            .map(|(loaded, mut mapping)| {
+                let ide = ides
+                    .iter()
+                    .find(|ide| ide.ide == loaded.ide && ide.version == loaded.version)
+                    .cloned()
+                    .unwrap_or(loaded.clone());
+                if ide.build_number != loaded.build_number {
+                    mapping.generated_at = None;
+                }
+                (ide, mapping)
+            })
+            .collect();
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct PluginDetails {
+    // Somehow sometimes the plugin list returns other unrelated plugins along with
+    // the response...
+    category: Vec<PluginDetailsCategory>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct PluginDetailsCategory {
+    #[serde(rename = "idea-plugin")]
+    idea_plugin: Vec<PluginDetailsIdeaPlugin>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct PluginDetailsIdeaPlugin {
+    id: String,
+    version: String,
+    #[serde(rename = "idea-version")]
+    idea_version: PluginDetailsIdeaVersion,
+    #[serde(default)]
+    depends: Vec<String>,
+    name: Option<String>,
+    vendor: Option<PluginDetailsVendor>,
+    description: Option<String>,
+    /// Only known from the JSON API.
+    #[serde(skip)]
+    pricing: Option<PricingModel>,
+    /// The marketplace release channel the version was fetched from, `None` for stable.
+    #[serde(skip)]
+    channel: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct PluginDetailsVendor {
+    #[serde(rename = "#text", default)]
+    name: String,
+}
+
+impl PluginDetailsIdeaPlugin {
+    fn meta(&self, artifact_path: Option<&str>) -> PluginMeta {
+        PluginMeta {
+            name: self.name.clone().unwrap_or_else(|| self.id.clone()),
+            vendor: self
+                .vendor
+                .as_ref()
+                .map(|vendor| vendor.name.trim().to_string())
+                .filter(|vendor| !vendor.is_empty()),
+            description: self.description.as_deref().and_then(short_description),
+            url: artifact_path.and_then(marketplace_url),
+            pricing: self.pricing,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+pub struct PluginDetailsIdeaVersion {
+    #[serde(rename = "@since-build")]
+    since_build: Option<String>,
+    #[serde(rename = "@until-build")]
+    until_build: Option<String>,
+}
+
+impl CompatibilityInfo for PluginDetailsIdeaPlugin {
+    fn version(&self) -> &str {
+        &self.version
+    }
+
+    fn since_build(&self) -> Option<&str> {
+        self.idea_version.since_build.as_deref()
+    }
+
+    fn until_build(&self) -> Option<&str> {
+        self.idea_version.until_build.as_deref()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct PluginDbEntry {
+    #[serde(rename = "p")]
+    pub path: String,
+    /// SRI hash (`sha256-<base64>`), usable as `hash` of `fetchurl`/`fetchzip`.
+    #[serde(rename = "h")]
+    pub hash: String,
+    /// IDs of marketplace plugins this plugin depends on.
+    #[serde(rename = "d", default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    #[serde(rename = "k")]
+    pub kind: ArtifactKind,
+    /// `false` for ZIPs Nix can't unpack (e.g. because of invalid file names). The hash is
+    /// then the flat hash of the ZIP itself.
+    #[serde(
+        rename = "u",
+        default = "unpackable_default",
+        skip_serializing_if = "is_true"
+    )]
+    pub unpackable: bool,
+    /// Size of the artifact in bytes, from the `Content-Length` of its download.
+    #[serde(rename = "s", default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// `ETag` of the download, to notice when an artifact is republished with other contents.
+    #[serde(rename = "e", default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// Where the artifact was copied to by `--mirror` when it was hashed.
+    #[serde(rename = "m", default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<String>,
+    /// Marketplace release channel (e.g. `eap`) of pre-release versions, `None` for stable.
+    #[serde(rename = "c", default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+}
+
+fn unpackable_default() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+/// How a plugin artifact is fetched and hashed.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactKind {
+    /// A single JAR, fetched as an executable file.
+    Jar,
+    /// A ZIP, fetched unpacked.
+    Zip,
+}
+
+impl ArtifactKind {
+    /// Marketplace artifacts are ZIPs unless they are plain JARs.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".jar") {
+            ArtifactKind::Jar
+        } else {
+            ArtifactKind::Zip
+        }
+    }
+}
+
+/// Reads the plugin IDs (a JSON array) of the index at `source`: a URL, a `file://` URL or
+/// `-` for stdin, and records it in `run`.
+pub async fn index(
+    fetcher: &dyn Fetcher,
+    run: &RunContext,
+    source: &str,
+) -> anyhow::Result<Vec<String>> {
+    let body = if source == "-" {
+        spawn_blocking(|| std::io::read_to_string(std::io::stdin()))
+            .await?
+            .map_err(|e| anyhow!("failed reading the plugin index from stdin: {e}"))?
+    } else if source.starts_with("file://") {
+        let path = Url::parse(source)?
+            .to_file_path()
+            .map_err(|()| anyhow!("invalid file URL {source}"))?;
+        read_to_string(&path)
+            .await
+            .map_err(|e| anyhow!("failed reading the plugin index {}: {e}", path.display()))?
+    } else {
+        fetcher.get(source).await?.success(source)?.body
+    };
+    run.record_feed(source, &body).await?;
+    serde_json::from_str(&body).map_err(|e| anyhow!("invalid plugin index {source}: {e}"))
+}
+
+/// Merges the plugin IDs of several indices, in the order they first appear. IDs listed by
+/// more than one index are only kept once, see [`normalize_plugin_id`] for invalid ones.
+pub fn merge_indices(indices: &[Vec<String>]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut merged = Vec::new();
+    let mut duplicates = 0;
+    let mut invalid = Vec::new();
+    for pluginkey in indices.iter().flatten() {
+        match normalize_plugin_id(pluginkey) {
+            Err(e) => invalid.push(format!("{pluginkey:?} ({e})")),
+            Ok(pluginkey) if seen.insert(pluginkey) => merged.push(pluginkey.to_string()),
+            Ok(_) => duplicates += 1,
+        }
+    }
+    if duplicates > 0 {
+        info!("{duplicates} plugin IDs are in more than one index, processing them once.");
+    }
+    if !invalid.is_empty() {
+        warn!(
+            "Skipping {} invalid plugin IDs in the indices: {}",
+            invalid.len(),
+            invalid.join(", ")
+        );
+    }
+    merged
+}
+
+/// Orders `pluginkeys` by relevance: the sum of their rank by last update and their rank by
+/// downloads, so that recently updated and popular plugins come first. Plugins missing from
+/// `popularity` keep their order at the end.
+pub fn prioritize_popular(
+    pluginkeys: &mut [String],
+    popularity: &HashMap<String, api::Popularity>,
+) {
+    let mut by_update: Vec<_> = popularity.iter().collect();
+    by_update.sort_by(|(a, a_pop), (b, b_pop)| b_pop.updated.cmp(&a_pop.updated).then(a.cmp(b)));
+    let mut by_downloads = by_update.clone();
+    by_downloads
+        .sort_by(|(a, a_pop), (b, b_pop)| b_pop.downloads.cmp(&a_pop.downloads).then(a.cmp(b)));
+    let mut ranks: HashMap<&str, usize> = HashMap::new();
+    for ranking in [by_update, by_downloads] {
+        for (rank, (pluginkey, _)) in ranking.into_iter().enumerate() {
+            *ranks.entry(pluginkey.as_str()).or_default() += rank;
+        }
+    }
+    pluginkeys.sort_by_cached_key(|pluginkey| {
+        ranks
+            .get(pluginkey.as_str())
+            .map_or(usize::MAX, |rank| *rank)
+    });
+}
+
+/// Reads a list of plugin IDs, one per line. Empty lines and `#` comments are ignored.
+pub async fn read_plugins_file(path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut pluginkeys = Vec::new();
+    for line in read_to_string(path).await?.lines() {
+        let pluginkey = match normalize_plugin_id(line.split('#').next().unwrap_or_default()) {
+            Ok(pluginkey) => pluginkey,
+            Err(InvalidId::Empty) => continue,
+            Err(e) => return Err(anyhow!("{}: plugin ID {line:?} {e}", path.display())),
+        };
+        if !pluginkeys.iter().any(|known| known == pluginkey) {
+            pluginkeys.push(pluginkey.to_string());
+        }
+    }
+    Ok(pluginkeys)
+}
+
+/// The layout of all_plugins in `out_dir`.
+fn current_layout(out_dir: &Path) -> std::io::Result<PluginsLayout> {
+    Ok(if exists(out_dir.join(ALL_PLUGINS_DIR))? {
+        PluginsLayout::Sharded
+    } else {
+        PluginsLayout::Single
+    })
+}
+
+/// Whether `out_dir` has Nix expressions of the JSON files, see [`PluginDb::nix_output`].
+fn current_nix_output(out_dir: &Path) -> std::io::Result<bool> {
+    Ok(
+        exists(out_dir.join(ALL_PLUGINS_JSON).with_extension("nix"))?
+            || std::fs::read_dir(out_dir.join(ALL_PLUGINS_DIR)).is_ok_and(|mut shards| {
+                shards.any(|shard| {
+                    shard.is_ok_and(|shard| shard.path().extension() == Some("nix".as_ref()))
+                })
+            }),
+    )
+}
+
+/// Whether all_plugins in `out_dir` is written compactly, see [`PluginDb::compact`].
+fn current_compact(out_dir: &Path) -> std::io::Result<bool> {
+    let file = match current_layout(out_dir)? {
+        PluginsLayout::Single => Some(out_dir.join(ALL_PLUGINS_JSON)),
+        PluginsLayout::Sharded => std::fs::read_dir(out_dir.join(ALL_PLUGINS_DIR))?
+            .filter_map(Result::ok)
+            .map(|shard| shard.path())
+            .find(|path| path.extension() == Some("json".as_ref())),
+    };
+    let Some(file) = file.filter(|file| file.exists()) else {
+        return Ok(false);
+    };
+    is_compact(&file)
+}
+
+/// Whether the JSON file `path` is written without indentation and line breaks.
+fn is_compact(path: &Path) -> std::io::Result<bool> {
+    // Pretty files start with `{` and a line break.
+    let mut start = [0; 2];
+    std::fs::File::open(path)?.read_exact(&mut start)?;
+    Ok(start != *b"{\n")
+}
+
+/// Whether `out_dir` has deterministic output, see [`PluginDb::deterministic`]. Decided by
+/// index.json, which is small and always written.
+fn current_deterministic(out_dir: &Path) -> anyhow::Result<bool> {
+    let path = out_dir.join(INDEX_JSON);
+    if !exists(&path)? {
+        return Ok(false);
+    }
+    Ok(is_deterministic(&serde_json::from_str(
+        &std::fs::read_to_string(path)?,
+    )?))
+}
+
+/// Whether the metadata of a file written by the generator has no generation time.
+fn is_deterministic(contents: &serde_json::Value) -> bool {
+    contents
+        .get("meta")
+        .is_some_and(|meta| meta.get("generatedAt").is_none())
+}
+
+/// The compressed copies of all_plugins in `out_dir`, see [`PluginDb::compression`].
+fn current_compression(out_dir: &Path) -> std::io::Result<Compression> {
+    let all_plugins = out_dir.join(ALL_PLUGINS_JSON);
+    let shards: Vec<_> = match std::fs::read_dir(out_dir.join(ALL_PLUGINS_DIR)) {
+        Ok(shards) => shards.filter_map(Result::ok).map(|e| e.path()).collect(),
+        Err(_) => Vec::new(),
+    };
+    for compression in Compression::ENABLED {
+        if compression
+            .path_of(&all_plugins)
+            .is_some_and(|path| path.exists())
+            || shards
+                .iter()
+                .any(|shard| Compression::of_path(shard).is_some_and(|(c, _)| c == compression))
+        {
+            return Ok(compression);
+        }
+    }
+    Ok(Compression::None)
+}
+
+/// Whether the JSON file `path` or a compressed copy of it exists.
+fn json_exists(path: &Path) -> std::io::Result<bool> {
+    for compression in Compression::ENABLED {
+        if let Some(compressed) = compression.path_of(path)
+            && exists(&compressed)?
+        {
+            return Ok(true);
+        }
+    }
+    exists(path)
+}
+
+/// Reads the JSON file `path`, or if it doesn't exist, one of its compressed copies.
+async fn read_json(path: &Path) -> anyhow::Result<String> {
+    if !exists(path)? {
+        for compression in Compression::ENABLED {
+            if let Some(compressed) = compression.path_of(path)
+                && exists(&compressed)?
+            {
+                let contents = read(&compressed).await?;
+                return Ok(spawn_blocking(move || compression.decode(&contents)).await??);
+            }
+        }
+    }
+    Ok(read_to_string(path).await?)
+}
+
+/// Load the plugin database, all_plugins.json (or its shards) only!
+async fn db_load(out_dir: &Path) -> anyhow::Result<PluginDb> {
+    let file = out_dir.join(ALL_PLUGINS_JSON);
+    let shard_dir = out_dir.join(ALL_PLUGINS_DIR);
+    let mut loaded_meta = HashMap::new();
+    let mut recovered = Vec::new();
+    let mut db = if current_layout(out_dir)? == PluginsLayout::Sharded {
+        let mut all_plugins = HashMap::new();
+        let mut shards = BTreeSet::new();
+        let mut files = read_dir(&shard_dir).await?;
+        while let Some(file) = files.next_entry().await? {
+            // Shards may only be there compressed.
+            let path = Compression::of_path(&file.path()).map_or(file.path(), |(_, path)| path);
+            if path.extension() == Some("json".as_ref()) {
+                shards.insert(path);
+            }
+        }
+        for shard in shards {
+            let (meta, plugins) = read_all_plugins(out_dir, &shard, &mut recovered).await?;
+            all_plugins.extend(plugins);
+            loaded_meta.extend(meta.map(|meta| (relative_path(out_dir, &shard), meta)));
+        }
+        let mut db = PluginDb::init(all_plugins);
+        db.layout = PluginsLayout::Sharded;
+        db
+    } else if json_exists(&file)? {
+        let (meta, plugins) = read_all_plugins(out_dir, &file, &mut recovered).await?;
+        loaded_meta.extend(meta.map(|meta| (relative_path(out_dir, &file), meta)));
+        PluginDb::init(plugins)
+    } else {
+        PluginDb::new()
+    };
+    db.entries_meta = loaded_meta;
+    db.recovered = recovered;
+    db.compact = current_compact(out_dir)?;
+    load_state(out_dir, &mut db).await?;
+    Ok(db)
+}
+
+/// Loads the output settings and everything but the entries and IDE mappings into `db`.
+async fn load_state(out_dir: &Path, db: &mut PluginDb) -> anyhow::Result<()> {
+    db.nix_output = current_nix_output(out_dir)?;
+    db.compression = current_compression(out_dir)?;
+    db.deterministic = current_deterministic(out_dir)?;
+    let meta_file = out_dir.join(PLUGINS_META_JSON);
+    if exists(&meta_file)? {
+        db.meta = serde_json::from_str(&read_to_string(meta_file).await?)?;
+    }
+    db.tombstones = tombstones::load(out_dir).await?;
+    db.status = status::load(out_dir).await?;
+    db.bundled = bundled::load(out_dir).await?;
+    db.unreferenced = unreferenced::load(out_dir).await?;
+    Ok(())
+}
+
+/// Reads the all_plugins file `file` in `out_dir`, and its metadata unless it had to be
+/// migrated or recovered. Corrupted files are recovered as far as possible and recorded in
+/// `recovered`, see [`recovery::quarantine`].
+async fn read_all_plugins(
+    out_dir: &Path,
+    file: &Path,
+    recovered: &mut Vec<RecoveredFile>,
+) -> anyhow::Result<(Option<GeneratorMeta>, HashMap<PluginVersion, PluginDbEntry>)> {
+    let text = read_json(file).await?;
+    let contents: serde_json::Value = match serde_json::from_str(&text) {
+        Ok(contents) => contents,
+        Err(e) if e.is_syntax() || e.is_eof() => {
+            let (contents, file) = recovery::quarantine(out_dir, file, &text, &e).await?;
+            recovered.push(file);
+            // Without metadata, so that the file is rewritten.
+            contents
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let current =
+        contents.get("schemaVersion").and_then(|v| v.as_u64()) == Some(migrations::SCHEMA_VERSION);
+    let contents = migrations::migrate(contents)
+        .map_err(|e| e.context(format!("failed migrating {}", file.display())))?;
+    let contents = serde_json::from_value::<AllPluginsFile<_>>(contents)?;
+    Ok((contents.meta.filter(|_| current), contents.plugins))
+}
+
+/// `path` relative to `out_dir`, as a key of [`PluginDb::entries_meta`].
+fn relative_path(out_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(out_dir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Whether the JSON file `out_path` and the copies of it that are configured exist, so that
+/// it doesn't need to be written if its contents didn't change.
+fn outputs_exist(out_path: &Path, nix: bool, compression: Compression) -> std::io::Result<bool> {
+    Ok(exists(out_path)?
+        && (!nix || exists(out_path.with_extension("nix"))?)
+        && match compression.path_of(out_path) {
+            Some(compressed) => exists(compressed)?,
+            None => true,
+        })
+}
+
+/// How many IDE mapping files are read or written at once.
+const IDE_FILE_CONCURRENCY: usize = 16;
+
+/// Load the plugin database, including the IDE mappings.
+/// WARNING: Does not populate build numbers for IDE files in the old format without metadata!
+async fn db_load_full(out_dir: &Path) -> anyhow::Result<PluginDb> {
+    let mut db = db_load(out_dir).await?;
+    load_ide_mappings(out_dir, &mut db).await?;
+    Ok(db)
+}
+
+/// Reads the IDE mapping files in `out_dir` into `db`.
+async fn load_ide_mappings(out_dir: &Path, db: &mut PluginDb) -> anyhow::Result<()> {
+    let mut ides = ReadDirStream::new(read_dir(out_dir.join("ides")).await?)
+        .map_err(anyhow::Error::from)
+        .map_ok(|file| async move {
+            let path = match Compression::of_path(&file.path()) {
+                // Read the plain JSON file instead.
+                Some((_, path)) if exists(&path)? => return Ok(None),
+                Some((_, path)) => path,
+                None => file.path(),
+            };
+            let Some(mut ideversion) = path
+                .file_name()
+                .and_then(|name| IdeVersion::from_json_filename(&name.to_string_lossy()))
+            else {
+                warn!(
+                    "Invalid JSON file in ide directory skipped: {}",
+                    file.path().display()
+                );
+                return Ok(None);
+            };
+            let (build_number, ide_mapping) = read_ide_file(&path).await?;
+            if let Some(build_number) = build_number {
+                ideversion.build_number = build_number;
+            }
+            Ok(Some((ideversion, ide_mapping)))
+        })
+        .try_buffer_unordered(IDE_FILE_CONCURRENCY);
+    while let Some(ide) = ides.try_next().await? {
+        if let Some((ideversion, ide_mapping)) = ide {
+            db.ides.insert(ideversion, ide_mapping);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the IDE mapping file at `path` and the build number in its metadata, if it has any.
+async fn read_ide_file(path: &Path) -> anyhow::Result<(Option<String>, IdeMapping)> {
+    let contents = read_json(path).await?;
+    // Parsing the bigger files takes a while, do it on the blocking pool so that the files
+    // are parsed in parallel.
+    Ok(
+        match spawn_blocking(move || serde_json::from_str(&contents)).await?? {
+            IdeFileCompat::WithMeta(IdeFile { meta, plugins }) => (
+                Some(meta.build_number),
+                IdeMapping {
+                    plugins,
+                    generated_at: meta.generated_at,
+                },
+            ),
+            IdeFileCompat::Flat(plugins) => (
+                None,
+                IdeMapping {
+                    plugins,
+                    generated_at: None,
+                },
+            ),
+        },
+    )
+}
+
+/// Settings and shared state for [`db_update`].
+pub struct UpdateContext<'a> {
+    pub fetcher: Arc<dyn Fetcher>,
+    pub hasher: Arc<dyn Hasher>,
+    pub events: &'a dyn GeneratorEvents,
+    /// Once its shutdown is cancelled, no new plugins are started, but plugins already being
+    /// processed are finished, so that the database can be saved in a consistent (if
+    /// incomplete) state. Also gets the metrics, downloaded bytes and progress bar.
+    pub run: &'a RunContext,
+    pub journal: &'a Journal,
+    pub storage: &'a dyn Storage,
+    /// Flush the database to disk every this many processed plugins. 0 disables this.
+    pub flush_every: usize,
+    /// Also record EAP channel versions that are newer than the stable version.
+    pub eap: bool,
+    /// All plugin IDs in the indices, to tell plugin dependencies from platform modules.
+    pub known_plugins: &'a HashSet<String>,
+    /// Fetch the compatible versions of all plugins per IDE build in bulk, instead of the
+    /// details of every plugin. Only stable versions are supported, plugin metadata is kept.
+    pub bulk: bool,
+    /// Process one IDE after another instead of all at once, each with the queries of
+    /// [`bulk`](Self::bulk) mode and only the plugins compatible with it. Each IDE mapping is
+    /// saved as soon as it is complete.
+    pub per_ide: bool,
+    /// Stop at the first plugin that fails (after retries) instead of processing the rest.
+    pub fail_fast: bool,
+    /// Plugins from custom repositories, see [`fetch_repositories`]. They take precedence
+    /// over marketplace plugins with the same ID.
+    pub repositories: &'a HashMap<String, RepositoryPlugin>,
+    pub download_urls: &'a DownloadUrls,
+    pub exclude: &'a Exclude,
+    /// Release channels considered besides stable, per plugin. Ignored in bulk mode.
+    pub release_channels: &'a ReleaseChannels,
+    /// Check the marketplace artifacts already in the database against the `Content-Length`
+    /// and `ETag` of their download, and hash them again if they changed.
+    pub verify_artifacts: bool,
+}
+
+/// Outcome of a [`db_update`] run.
+#[derive(Default)]
+pub struct UpdateOutcome {
+    /// Number of plugins processed successfully.
+    pub processed: usize,
+    /// Plugins that failed after all retries, with their last error.
+    pub failed: Vec<(String, anyhow::Error)>,
+    /// Plugins whose versions for all IDEs are known from this run, i.e. a plugin missing from
+    /// an IDE is really incompatible with it.
+    pub succeeded: HashSet<String>,
+    /// IDE mapping entries that this run would have dropped, see [`guard_regressions`].
+    pub regressions: Regressions,
+    /// Plugins that were not in the database before.
+    pub added: usize,
+    /// Plugins already in the database that got new versions.
+    pub updated: usize,
+    /// Number of plugins available for each processed IDE version.
+    pub ide_plugins: BTreeMap<String, usize>,
+    /// Plugin versions (`<id>@<version>`) whose artifact changed upstream and was hashed again.
+    pub republished: Vec<String>,
+    /// Plugins left out of the database, see [`save_skips`].
+    pub skips: Skips,
+    /// Wall-clock time spent on each processed plugin, including retries (and all IDE batches
+    /// in per-IDE mode).
+    pub durations: HashMap<String, Duration>,
+}
+
+/// How many failed plugins a run tolerates before exiting with an error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureThreshold {
+    /// At most this many plugins.
+    Count(usize),
+    /// At most this percentage of the attempted plugins.
+    Percent(f64),
+}
+
+impl FromStr for FailureThreshold {
+    type Err = anyhow::Error;
+
+    /// Parses `10` or `5%`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix('%') {
+            Some(percent) => {
+                let percent: f64 = percent
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("invalid percentage: {s:?}"))?;
+                if !(0.0..=100.0).contains(&percent) {
+                    return Err(anyhow!("percentage out of range: {s:?}"));
+                }
+                Ok(FailureThreshold::Percent(percent))
+            }
+            None => Ok(FailureThreshold::Count(s.trim().parse().map_err(|_| {
+                anyhow!("invalid failure threshold {s:?}, expected e.g. 10 or 5%")
+            })?)),
+        }
+    }
+}
+
+impl UpdateOutcome {
+    /// Share of failed plugins among all plugins attempted.
+    pub fn failure_ratio(&self) -> f64 {
+        let attempted = self.processed + self.failed.len();
+        if attempted == 0 {
+            0.0
+        } else {
+            self.failed.len() as f64 / attempted as f64
+        }
+    }
+
+    pub fn exceeds(&self, threshold: FailureThreshold) -> bool {
+        match threshold {
+            FailureThreshold::Count(count) => self.failed.len() > count,
+            FailureThreshold::Percent(percent) => self.failure_ratio() * 100.0 > percent,
+        }
+    }
+
+    pub fn log_summary(&self) {
+        info!(
+            "Processed {} plugins, {} failed.",
+            self.processed + self.failed.len(),
+            self.failed.len()
+        );
+        if self.failed.is_empty() {
+            return;
+        }
+        let mut by_kind = BTreeMap::new();
+        for (_, e) in &self.failed {
+            *by_kind
+                .entry(format!("{:?}", error::classify(e)))
+                .or_insert(0) += 1;
+        }
+        for (kind, count) in by_kind {
+            warn!("{kind} errors: {count}");
+        }
+        for (pluginkey, e) in &self.failed {
+            warn!(
+                plugin:% = pluginkey, kind:? = error::classify(e);
+                "failed: {pluginkey}: {e:#}"
+            );
+        }
+    }
+}
+
+/// State shared by all plugins processed in a [`db_update`] run.
+struct RunState<'a> {
+    db: RwLock<&'a mut PluginDb>,
+    fetcher: Arc<dyn Fetcher>,
+    hasher: Arc<dyn Hasher>,
+    events: &'a dyn GeneratorEvents,
+    run: &'a RunContext,
+    ides: &'a [IdeVersion],
+    eap: bool,
+    known_plugins: &'a HashSet<String>,
+    /// In bulk mode, the compatible version of each plugin per IDE.
+    bulk: Option<BulkVersions<'a>>,
+    repositories: &'a HashMap<String, RepositoryPlugin>,
+    download_urls: &'a DownloadUrls,
+    exclude: &'a Exclude,
+    release_channels: &'a ReleaseChannels,
+    verify_artifacts: bool,
+    /// Each plugin version is looked up (and downloaded or verified) only once per run, no
+    /// matter with how many IDEs it is compatible.
+    entries: Mutex<EntryMemo>,
+    republished: RwLock<BTreeSet<String>>,
+    skips: Mutex<Skips>,
+    durations: Mutex<HashMap<String, Duration>>,
+}
+
+/// Plugin ID -> (IDE, newest compatible stable version).
+type BulkVersions<'a> = HashMap<String, Vec<(&'a IdeVersion, String)>>;
+
+/// Processes all plugins and updates the database. Failing plugins are collected in the
+/// outcome, unless [`UpdateContext::fail_fast`] is set.
+pub async fn db_update(
+    db: &mut PluginDb,
+    ides: &[IdeVersion],
+    pluginkeys: &[String],
+    ctx: &UpdateContext<'_>,
+) -> anyhow::Result<UpdateOutcome> {
+    let UpdateContext {
+        fetcher,
+        hasher,
+        events,
+        run,
+        journal,
+        storage,
+        eap,
+        known_plugins,
+        bulk,
+        per_ide,
+        repositories,
+        download_urls,
+        exclude,
+        release_channels,
+        verify_artifacts,
+        ..
+    } = ctx;
+    if (*bulk || *per_ide) && *eap {
+        warn!("EAP plugin versions are not supported in bulk mode, only recording stable ones.");
+    }
+    let versions_before: HashMap<String, usize> = db
+        .version_counts()
+        .into_iter()
+        .map(|(name, count)| (name.to_string(), count))
+        .collect();
+    // In per-IDE mode, every IDE is a batch of its own and saved once it is complete.
+    let batches: Vec<&[IdeVersion]> = if *per_ide {
+        ides.chunks(1).collect()
+    } else {
+        vec![ides]
+    };
+
+    let mut entries = Mutex::default();
+    let mut republished = RwLock::default();
+    let mut skips = Mutex::default();
+    let mut durations = Mutex::default();
+    let mut processed = HashSet::new();
+    let mut failed = Vec::new();
+    let shutdown = run.shutdown();
+    for batch in batches {
+        if shutdown.is_cancelled() {
+            break;
+        }
+        let bulk = if *bulk || *per_ide {
+            Some(fetch_bulk_versions(&**fetcher, batch, pluginkeys).await?)
+        } else {
+            None
+        };
+        let batch_keys: Vec<&String> = pluginkeys
+            .iter()
+            .filter(|pluginkey| {
+                // Plugins without a version for this IDE would not change anything.
+                !*per_ide
+                    || repositories.contains_key(*pluginkey)
+                    || bulk
+                        .as_ref()
+                        .is_some_and(|bulk| bulk.contains_key(*pluginkey))
+            })
+            .collect();
+        if *per_ide {
+            info!(
+                "Processing {} plugins compatible with {}...",
+                batch_keys.len(),
+                batch[0]
+            );
+        }
+        let state = RunState {
+            db: RwLock::new(&mut *db),
+            fetcher: fetcher.clone(),
+            hasher: hasher.clone(),
+            events: *events,
+            run,
+            ides: batch,
+            eap: *eap,
+            known_plugins,
+            bulk,
+            repositories,
+            download_urls,
+            exclude,
+            release_channels,
+            verify_artifacts: *verify_artifacts,
+            entries,
+            republished,
+            skips,
+            durations,
+        };
+        let results = process_plugins(&state, &batch_keys, ctx).await?;
+        // Carry the state of this batch over to the next one.
+        RunState {
+            entries,
+            republished,
+            skips,
+            durations,
+            ..
+        } = state;
+        for (pluginkey, result) in results {
+            match result {
+                Ok(()) => {
+                    processed.insert(pluginkey);
+                }
+                Err(e) if failed.iter().all(|(failed, _)| failed != pluginkey) => {
+                    events.on_failure(pluginkey, &e);
+                    failed.push((pluginkey.clone(), e));
+                }
+                Err(_) => {}
+            }
+        }
+        if *per_ide {
+            storage.flush(db, *events).await?;
+        }
+    }
+
+    let mut outcome = UpdateOutcome::default();
+    for pluginkey in processed {
+        if failed.iter().all(|(failed, _)| failed != pluginkey) {
+            outcome.processed += 1;
+            outcome.succeeded.insert(pluginkey.clone());
+            // In per-IDE mode, a plugin is only done once all IDEs are.
+            if *per_ide && !shutdown.is_cancelled() {
+                journal.record(pluginkey);
+            }
+        }
+    }
+    if *per_ide && !shutdown.is_cancelled() {
+        // Plugins never processed have no compatible version for any IDE.
+        outcome.succeeded.extend(
+            pluginkeys
+                .iter()
+                .filter(|pluginkey| failed.iter().all(|(failed, _)| failed != *pluginkey))
+                .cloned(),
+        );
+    }
+    outcome.failed = failed;
+    for (name, count) in db.version_counts() {
+        match versions_before.get(name) {
+            None => outcome.added += 1,
+            Some(before) if count > *before => outcome.updated += 1,
+            Some(_) => {}
+        }
+    }
+    outcome.ide_plugins = ides
+        .iter()
+        .map(|ide| {
+            let count = db.ides.get(ide).map_or(0, |mapping| mapping.plugins.len());
+            (ide.to_string(), count)
+        })
+        .collect();
+    outcome.republished = republished.into_inner().into_iter().collect();
+    outcome.skips = skips.into_inner().unwrap();
+    outcome.durations = durations.into_inner().unwrap();
+    Ok(outcome)
+}
+
+/// Processes `pluginkeys` with the IDEs of `state` and returns the result of each plugin that
+/// was started before a shutdown. Only returns an error if saving fails, or a plugin fails
+/// with [`UpdateContext::fail_fast`] set.
+async fn process_plugins<'k>(
+    state: &RunState<'_>,
+    pluginkeys: &[&'k String],
+    ctx: &UpdateContext<'_>,
+) -> anyhow::Result<Vec<(&'k String, anyhow::Result<()>)>> {
+    let UpdateContext {
+        events,
+        run,
+        journal,
+        storage,
+        flush_every,
+        per_ide,
+        fail_fast,
+        ..
+    } = ctx;
+    let metrics = run.metrics();
+    let mut futures = Vec::new();
+
+    for &pluginkey in pluginkeys {
+        // Create a future that will be retried 3 times, has a timeout of 1200 seconds per try
+        // and polls process_plugin to process this plugin for this IDE version. process_plugin
+        // will update the database.
+        futures.push(async move {
+            let started = Instant::now();
+            // 1s, 4s and 16s, jittered by ±50% so that plugins failing at the same moment
+            // don't all retry at the same moment either.
+            let result = Retry::spawn(
+                ExponentialBackoff::from_millis(4)
+                    .factor(250)
+                    .max_delay(Duration::from_secs(60))
+                    .map(jitter)
+                    .take(3)
+                    // Only asked for a delay when another attempt follows.
+                    .inspect(|_| metrics.retries.inc()),
+                move || async move {
+                    let res =
+                        timeout(Duration::from_secs(1200), process_plugin(state, pluginkey)).await;
+                    match res {
+                        Ok(Ok(v)) => Ok(v),
+                        Ok(Err(e)) if error::classify(&e) == ErrorKind::Permanent => {
+                            warn!(
+                                plugin:% = pluginkey, phase = "process", kind = "permanent";
+                                "failed plugin processing {pluginkey}: {e}. Not retrying."
+                            );
+                            Err(RetryError::permanent(e))
+                        }
+                        Ok(Err(e)) => {
+                            warn!(
+                                plugin:% = pluginkey, phase = "process", kind = "transient";
+                                "failed plugin processing {pluginkey}: {e}. Might retry."
+                            );
+                            Err(RetryError::transient(e))
+                        }
+                        Err(e) => {
+                            warn!(
+                                plugin:% = pluginkey, phase = "process", kind = "timeout";
+                                "failed plugin processing {pluginkey} due to timeout. Might retry."
+                            );
+                            Err(RetryError::transient(anyhow!("timeout").context(e)))
+                        }
+                    }
+                },
+            )
+            .await;
+            metrics
+                .processing_seconds
+                .observe(started.elapsed().as_secs_f64());
+            *state
+                .durations
+                .lock()
+                .unwrap()
+                .entry(pluginkey.clone())
+                .or_default() += started.elapsed();
+            let duration_ms = started.elapsed().as_millis() as u64;
+            debug!(
+                plugin:% = pluginkey, phase = "process", duration_ms, success = result.is_ok();
+                "{pluginkey}: done after {duration_ms}ms"
+            );
+            if result.is_ok() && !per_ide {
+                journal.record(pluginkey);
+            }
+            (pluginkey, result)
+        });
+    }
+
+    let progress = Progress::new(futures.len() as u64, run.active_bar());
+    let mut results = pin!(
+        iter(futures)
+            .take_until(run.shutdown().cancelled())
+            .buffered(16)
+    );
+    let mut finished = Vec::new();
+    let mut succeeded = 0;
+    while let Some((pluginkey, plugin_result)) = results.next().await {
+        progress.inc(plugin_result.is_err());
+        match plugin_result {
+            Err(e) if *fail_fast => {
+                progress.finish();
+                return Err(e.context(format!("failed processing {pluginkey}")));
+            }
+            Err(e) => {
+                match error::classify(&e) {
+                    ErrorKind::Transient => metrics.transient_failures.inc(),
+                    ErrorKind::Permanent => metrics.permanent_failures.inc(),
+                }
+                finished.push((pluginkey, Err(e)));
+                continue;
+            }
+            Ok(()) => finished.push((pluginkey, Ok(()))),
+        }
+        metrics.processed.inc();
+        succeeded += 1;
+        if *flush_every != 0 && succeeded % flush_every == 0 {
+            debug!("Flushing DB after {succeeded} plugins...");
+            let mut lck = state.db.write().await;
+            storage.flush(&mut lck, *events).await?;
+            journal.commit().await?;
+        }
+    }
+    progress.finish();
+    Ok(finished)
+}
+
+/// Various hacks to support (or skip) some very odd cases
+fn hacks_for_details_key(pluginkey: &str) -> Option<&str> {
+    match pluginkey {
+        // The former is the real ID, but it trips up the plugin endpoint...
+        "23.bytecode-disassembler" => Some("bytecode-disassembler"),
+        // Has invalid version numbers
+        "com.valord577.mybatis-navigator" => None,
+        v => Some(v),
+    }
+}
+
+async fn fetch_bulk_versions<'a>(
+    fetcher: &dyn Fetcher,
+    ides: &'a [IdeVersion],
+    pluginkeys: &[String],
+) -> anyhow::Result<BulkVersions<'a>> {
+    let mut bulk = BulkVersions::new();
+    for ide in ides {
+        let build = format!("{}-{}", ide.ide.product_code(), ide.build_number);
+        info!("Fetching compatible plugin versions for {build}...");
+        for (pluginkey, version) in api::compatible_updates(fetcher, &build, pluginkeys).await? {
+            bulk.entry(pluginkey).or_default().push((ide, version));
+        }
+    }
+    Ok(bulk)
+}
+
+async fn process_plugin(state: &RunState<'_>, pluginkey: &str) -> anyhow::Result<()> {
+    state.events.on_plugin_started(pluginkey);
+
+    if let Some(repository) = state.repositories.get(pluginkey) {
+        return process_versions(state, pluginkey, &repository.versions, &[]).await;
+    }
+    let Some(pluginkey_for_details) = hacks_for_details_key(pluginkey) else {
+        state.skip(pluginkey, SkipReason::Broken, state.ides);
+        return Ok(());
+    };
+    if let Some(bulk) = &state.bulk {
+        return process_plugin_bulk(state, pluginkey, pluginkey_for_details, bulk).await;
+    }
+
+    let Some(versions) =
+        fetch_versions(&*state.fetcher, pluginkey, pluginkey_for_details, None).await?
+    else {
+        state.skip(pluginkey, SkipReason::NoDetails, state.ides);
+        return Ok(());
+    };
+    let mut channels = state.release_channels.of(pluginkey);
+    if state.eap && !channels.contains(&"eap") {
+        channels.insert(0, "eap");
+    }
+    let mut eap_versions = Vec::new();
+    for channel in channels {
+        eap_versions.extend(
+            fetch_versions(
+                &*state.fetcher,
+                pluginkey,
+                pluginkey_for_details,
+                Some(channel),
+            )
+            .await?
+            .unwrap_or_default(),
+        );
+    }
+    process_versions(state, pluginkey, &versions, &eap_versions).await
+}
+
+/// Records the newest versions of a plugin compatible with each IDE, and its metadata.
+async fn process_versions(
+    state: &RunState<'_>,
+    pluginkey: &str,
+    versions: &[PluginDetailsIdeaPlugin],
+    eap_versions: &[PluginDetailsIdeaPlugin],
+) -> anyhow::Result<()> {
+    let vendor = versions
+        .iter()
+        .max_by(|a, b| compare_versions(&a.version, &b.version))
+        .and_then(|newest| newest.vendor.as_ref());
+    if let Some(vendor) = vendor
+        && state.exclude.excludes_vendor(&vendor.name)
+    {
+        state.skip(
+            pluginkey,
+            SkipReason::ExcludedVendor(&vendor.name),
+            state.ides,
+        );
+        return Ok(());
+    }
+
+    let mut artifact_path = None;
+    let mut compatible = false;
+    for ide in state.ides {
+        let build: BuildNumber = ide.build_number.parse()?;
+        let stable = compatible_version(&build, versions);
+        // EAP versions are only interesting if they are newer than the stable one.
+        let eap = compatible_version(&build, eap_versions).filter(|eap| {
+            stable.is_none_or(|stable| compare_versions(&eap.version, &stable.version).is_gt())
+        });
+        if stable.is_none() && eap.is_none() {
+            debug!(plugin = pluginkey, ide:% = ide; "{pluginkey}: IDE {ide} not supported.");
+            continue;
+        }
+        compatible = true;
+        for (channel, version) in [(Channel::Stable, stable), (Channel::Eap, eap)] {
+            let Some(version) = version else {
+                continue;
+            };
+            let entry = resolve_entry(
+                state,
+                pluginkey,
+                &version.version,
+                version.channel.as_deref(),
+            )
+            .await?;
+            if entry.is_none() {
+                state.skipped_for(pluginkey, ide);
+            }
+            if let Some(entry) = entry {
+                let mut entry = Arc::unwrap_or_clone(entry);
+                entry.dependencies = resolve_dependencies(state, pluginkey, version);
+                entry.channel = version.channel.clone();
+                artifact_path.get_or_insert_with(|| entry.path.clone());
+                let key = PluginVersion::new(pluginkey, &version.version);
+                let mut lck = state.db.write().await;
+                let db_mut = &mut *lck;
+                if db_mut.insert(ide, channel, pluginkey, &version.version, entry) {
+                    state.events.on_entry_added(&key, &db_mut.all_plugins[&key]);
+                }
+            }
+        }
+    }
+    if !compatible {
+        state.skip(pluginkey, SkipReason::Incompatible, state.ides);
+    }
+
+    if let Some(newest) = versions
+        .iter()
+        .max_by(|a, b| compare_versions(&a.version, &b.version))
+    {
+        let previous = state.db.read().await.meta.get(pluginkey).cloned();
+        let artifact_path = artifact_path.or_else(|| {
+            // Keep the URL we found in a previous run.
+            let url = previous.as_ref()?.url.as_ref()?;
+            Some(format!("files/{}/", url.rsplit('/').next()?))
+        });
+        let mut meta = newest.meta(artifact_path.as_deref());
+        meta.pricing = meta
+            .pricing
+            .or(previous.and_then(|previous| previous.pricing));
+        if newest.pricing.is_none()
+            && let Some(numeric_id) = artifact_path.as_deref().and_then(numeric_plugin_id)
+        {
+            match fetch_pricing(&*state.fetcher, numeric_id).await {
+                Ok(pricing) => meta.pricing = pricing.or(meta.pricing),
+                Err(e) => warn!("{pluginkey}: failed fetching pricing model: {e}"),
+            }
+        }
+        state
+            .db
+            .write()
+            .await
+            .meta
+            .insert(pluginkey.to_string(), meta);
+    }
+    Ok(())
+}
+
+/// Records the versions found by [`fetch_bulk_versions`]. Plugin details are only fetched for
+/// versions not in the database yet, to resolve their dependencies.
+async fn process_plugin_bulk(
+    state: &RunState<'_>,
+    pluginkey: &str,
+    pluginkey_for_details: &str,
+    bulk: &BulkVersions<'_>,
+) -> anyhow::Result<()> {
+    let Some(compatible) = bulk.get(pluginkey) else {
+        state.skip(pluginkey, SkipReason::Incompatible, state.ides);
+        return Ok(());
+    };
+    // Plugin details aren't fetched in bulk mode, so rely on the vendor seen in earlier runs.
+    let vendor = state
+        .db
+        .read()
+        .await
+        .meta
+        .get(pluginkey)
+        .and_then(|meta| meta.vendor.clone());
+    if let Some(vendor) = vendor
+        && state.exclude.excludes_vendor(&vendor)
+    {
+        state.skip(pluginkey, SkipReason::ExcludedVendor(&vendor), state.ides);
+        return Ok(());
+    }
+    let mut details = None;
+    for (ide, version) in compatible {
+        let known = state
+            .db
+            .read()
+            .await
+            .all_plugins
+            .contains_key(&PluginVersion::new(pluginkey, version));
+        let Some(entry) = resolve_entry(state, pluginkey, version, None).await? else {
+            state.skipped_for(pluginkey, ide);
+            continue;
+        };
+        let mut entry = Arc::unwrap_or_clone(entry);
+        if !known {
+            if details.is_none() {
+                details = Some(
+                    fetch_versions(&*state.fetcher, pluginkey, pluginkey_for_details, None)
+                        .await?
+                        .unwrap_or_default(),
+                );
+            }
+            if let Some(details) = details.iter().flatten().find(|d| d.version == *version) {
+                entry.dependencies = resolve_dependencies(state, pluginkey, details);
+            }
+        }
+        let key = PluginVersion::new(pluginkey, version);
+        let mut lck = state.db.write().await;
+        let db_mut = &mut *lck;
+        if db_mut.insert(ide, Channel::Stable, pluginkey, version, entry) {
+            state.events.on_entry_added(&key, &db_mut.all_plugins[&key]);
+        }
+    }
+    Ok(())
+}
+
+/// Fetches all versions of a plugin in the given release channel (`None` for stable) from the
+/// JSON API, falling back to the XML plugin list. Returns `None` if the marketplace has no
+/// details for the plugin.
+async fn fetch_versions(
+    fetcher: &dyn Fetcher,
+    pluginkey: &str,
+    pluginkey_for_details: &str,
+    channel: Option<&str>,
+) -> anyhow::Result<Option<Vec<PluginDetailsIdeaPlugin>>> {
+    match api::fetch_versions(fetcher, pluginkey, channel).await {
+        Ok(Some(versions)) => return Ok(Some(versions)),
+        Ok(None) => debug!("{pluginkey}: not found in the JSON API, trying the XML list."),
+        Err(e) => debug!("{pluginkey}: JSON API failed ({e}), trying the XML list."),
+    }
+    fetch_versions_xml(fetcher, pluginkey, pluginkey_for_details, channel).await
+}
+
+/// The XML plugin list of `pluginkey` in `channel` (`None` for stable).
+fn plugin_list_url(pluginkey: &str, channel: Option<&str>) -> anyhow::Result<Url> {
+    let mut url = Url::parse("https://plugins.jetbrains.com/plugins/list")?;
+    if let Some(channel) = channel {
+        url.path_segments_mut()
+            .map_err(|()| anyhow!("invalid plugin list URL"))?
+            .clear()
+            .extend(["plugins", channel, "list"]);
+    }
+    url.query_pairs_mut().append_pair("pluginId", pluginkey);
+    Ok(url)
+}
+
+/// The marketplace download of `version` of `pluginkey` in `channel` (`None` for stable).
+fn marketplace_download_url(
+    pluginkey: &str,
+    version: &str,
+    channel: Option<&str>,
+) -> anyhow::Result<Url> {
+    let mut url = Url::parse_with_params(
+        "https://plugins.jetbrains.com/plugin/download",
+        [("pluginId", pluginkey), ("version", version)],
+    )?;
+    if let Some(channel) = channel {
+        url.query_pairs_mut().append_pair("channel", channel);
+    }
+    Ok(url)
+}
+
+async fn fetch_versions_xml(
+    fetcher: &dyn Fetcher,
+    pluginkey: &str,
+    pluginkey_for_details: &str,
+    channel: Option<&str>,
+) -> anyhow::Result<Option<Vec<PluginDetailsIdeaPlugin>>> {
+    let url = plugin_list_url(pluginkey_for_details, channel)?;
+    let request_text = fetcher
+        .get(url.as_str())
+        .await?
+        .success(&format!("{pluginkey} failed details request"))?
+        .body;
+    let all_details: PluginDetails = match serde_xml_rs::from_str(&request_text) {
+        Ok(all_details) => all_details,
+        Err(error) => {
+            let empty_response: Result<(), _> = serde_xml_rs::from_str(&request_text);
+            return if empty_response.is_ok() {
+                Ok(None)
+            } else {
+                Err(error.into())
+            };
+        }
+    };
+
+    // Somehow sometimes the plugin list returns other unrelated plugins along with
+    // the response...
+    // This means we have to check which result is actually correct.
+    for candidate in all_details.category {
+        if let Some(first_version) = candidate.idea_plugin.first()
+            && first_version.id.to_lowercase() == pluginkey.to_lowercase()
+        {
+            let mut versions = candidate.idea_plugin;
+            for version in &mut versions {
+                version.channel = channel.map(str::to_string);
+            }
+            return Ok(Some(versions));
+        }
+    }
+    Ok(None)
+}
+
+/// Dependencies of a plugin version on other marketplace plugins. Dependencies on platform
+/// modules (`com.intellij.modules.*`) and bundled plugins not in the indices are dropped.
+fn resolve_dependencies(
+    state: &RunState<'_>,
+    pluginkey: &str,
+    version: &PluginDetailsIdeaPlugin,
+) -> Vec<String> {
+    let mut dependencies: Vec<_> = version
+        .depends
+        .iter()
+        .map(|dependency| dependency.trim())
+        .filter(|dependency| *dependency != pluginkey && state.known_plugins.contains(*dependency))
+        .map(str::to_string)
+        .collect();
+    dependencies.sort();
+    dependencies.dedup();
+    dependencies
+}
+
+/// [`get_db_entry`], memoized for the run.
+async fn resolve_entry(
+    state: &RunState<'_>,
+    pluginkey: &str,
+    version: &str,
+    channel: Option<&str>,
+) -> anyhow::Result<Option<Arc<PluginDbEntry>>> {
+    let cell = state
+        .entries
+        .lock()
+        .unwrap()
+        .entry(PluginVersion::new(pluginkey, version))
+        .or_default()
+        .clone();
+    cell.get_or_try_init(|| get_db_entry(state, pluginkey, version, channel))
+        .await
+        .cloned()
+}
+
+/// `channel` is the release channel of the version, `None` for stable.
+async fn get_db_entry(
+    state: &RunState<'_>,
+    pluginkey: &str,
+    version: &str,
+    channel: Option<&str>,
+) -> anyhow::Result<Option<Arc<PluginDbEntry>>> {
+    let RunState {
+        fetcher,
+        hasher,
+        db: current_db,
+        ..
+    } = state;
+    let key = PluginVersion::new(pluginkey, version);
+    let repository_url = state
+        .repositories
+        .get(pluginkey)
+        .and_then(|repository| repository.urls.get(version));
+    // Look in current_db
+    let existing = current_db.read().await.all_plugins.get(&key).cloned();
+    if let Some(existing) = &existing
+        && (!state.verify_artifacts || repository_url.is_some())
+    {
+        return Ok(Some(existing.clone()));
+    }
+
+    if existing.is_none() {
+        info!(
+            plugin = pluginkey, version = version, phase = "hash";
+            "{pluginkey}@{version}: Plugin not yet cached, downloading for hash..."
+        );
+    }
+
+    let (url, size, etag) = if let Some(url) = repository_url {
+        fetcher.check_online(url)?;
+        (url.clone(), None, None)
+    } else {
+        let download_url = marketplace_download_url(pluginkey, version, channel)?;
+        let head = fetcher.head(download_url.as_str()).await?;
+
+        if head.status == StatusCode::NOT_FOUND {
+            return Ok(unavailable(state, &key, existing).await);
+        } else if !head.status.is_success() {
+            return Err(StatusError::new(
+                format!("{pluginkey}@{version}: failed download HEAD request"),
+                head.status,
+            )
+            .into());
+        }
+        revive(current_db, &key).await;
+
+        // Query parameters don't seem to result in different files, probably only for analytics.
+        // Remove them to save some space.
+        let mut url = Url::parse(&head.url)?;
+        url.set_query(None);
+        (url.to_string(), head.content_length, head.etag)
+    };
+    let mut dependencies = Vec::new();
+    if let Some(existing) = &existing {
+        let differs = existing.size.zip(size).is_some_and(|(a, b)| a != b)
+            || existing
+                .etag
+                .as_ref()
+                .zip(etag.as_ref())
+                .is_some_and(|(a, b)| a != b);
+        if !differs {
+            // Entries from before sizes and ETags were recorded get them now.
+            let mut entry = PluginDbEntry::clone(existing);
+            entry.size = entry.size.or(size);
+            entry.etag = entry.etag.or(etag);
+            return Ok(Some(Arc::new(entry)));
+        }
+        warn!(
+            plugin = pluginkey, version = version, phase = "hash";
+            "{pluginkey}@{version}: artifact changed upstream, hashing it again"
+        );
+        state
+            .republished
+            .write()
+            .await
+            .insert(format!("{pluginkey}@{version}"));
+        dependencies = existing.dependencies.clone();
+    }
+    // Custom repository URLs are stored as is.
+    let path = if repository_url.is_some() {
+        url.clone()
+    } else {
+        state.download_urls.store_path(&url)?
+    };
+    let download_url = state.download_urls.rewrite(&url);
+
+    let kind = ArtifactKind::from_path(&url);
+    let is_jar = kind == ArtifactKind::Jar;
+    let name = format!("{pluginkey}-{version}-source").replace(|c: char| !c.is_alphanumeric(), "-");
+    // Mirrored like the store path, but full URLs without their scheme.
+    let mirror_key = path
+        .split_once("://")
+        .map_or(path.as_str(), |(_, rest)| rest);
+    let mirror = hasher.mirror().map(|mirror| mirror.location(mirror_key));
+    let mut unpackable = true;
+    let started = Instant::now();
+    let digest = match hasher
+        .hash(&name, &download_url, !is_jar, is_jar, Some(mirror_key))
+        .await
+    {
+        Err(e) if is_not_found(&e) => {
+            debug!("{pluginkey}@{version}: {e:#}");
+            return Ok(unavailable(state, &key, existing).await);
+        }
+        Err(e) if !is_jar && e.downcast_ref::<UnpackError>().is_some() => {
+            warn!("{pluginkey}@{version}: {e}, using the hash of the packed ZIP instead");
+            unpackable = false;
+            // Already mirrored by the first attempt.
+            hasher
+                .hash(&name, &download_url, false, false, None)
+                .await?
+        }
+        digest => digest?,
+    };
+    let hash = format!("{SRI_PREFIX}{}", BASE64_STANDARD.encode(digest));
+    revive(current_db, &key).await;
+    let duration_ms = started.elapsed().as_millis() as u64;
+    debug!(
+        plugin = pluginkey, version = version, phase = "hash", duration_ms, bytes = size;
+        "{pluginkey}@{version}: hashed in {duration_ms}ms"
+    );
+    if let Some(size) = size {
+        state.run.add_downloaded(size);
+        state.run.metrics().download_bytes.observe(size as f64);
+    }
+
+    Ok(Some(Arc::new(PluginDbEntry {
+        path,
+        hash,
+        dependencies,
+        kind,
+        unpackable,
+        size,
+        etag,
+        mirror,
+        channel: channel.map(str::to_string),
+    })))
+}
+
+/// Records a tombstone for `key`, whose download returns 404. Keeps the `existing` entry (and
+/// returns it), otherwise the version is skipped.
+async fn unavailable(
+    state: &RunState<'_>,
+    key: &PluginVersion,
+    existing: Option<Arc<PluginDbEntry>>,
+) -> Option<Arc<PluginDbEntry>> {
+    let recorded = state
+        .db
+        .write()
+        .await
+        .tombstone(key, TombstoneReason::Unavailable);
+    if recorded {
+        warn!("{key}: download not available, recorded a tombstone");
+    }
+    if existing.is_none() {
+        state.skip(
+            &key.name,
+            SkipReason::Unavailable {
+                version: &key.version,
+            },
+            &[],
+        );
+    }
+    existing
+}
+
+/// Removes the tombstone of `key`, whose download is available (again).
+async fn revive(db: &RwLock<&mut PluginDb>, key: &PluginVersion) {
+    if db.read().await.tombstones().contains_key(key) {
+        db.write().await.revive(key);
+    }
+}
+
+/// Whether `e` is a download that returned 404.
+fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<StatusError>()
+        .is_some_and(|e| e.status == StatusCode::NOT_FOUND)
+}
+
+/// Save everything, including the Nix expressions if [`PluginDb::nix_output`] is set.
+async fn db_save(
+    output_folder: &Path,
+    db: &PluginDb,
+    events: &dyn GeneratorEvents,
+) -> anyhow::Result<()> {
+    check_integrity(db)?;
+    create_dir_all(output_folder.join("ides")).await?;
+    save_all_plugins(output_folder, db, db.nix_output, db.compression).await?;
+    save_ide_mappings(
+        output_folder,
+        db,
+        db.ides.keys(),
+        db.nix_output,
+        db.compression,
+        events,
+    )
+    .await?;
+    if !db.nix_output {
+        remove_nix_output(output_folder).await?;
+    }
+    remove_compressed_output(output_folder, db.compression).await?;
+    let out_path = output_folder.join(ALIASES_JSON);
+    debug!("Generating {out_path:?}...");
+    write_if_changed(
+        &out_path,
+        serde_json::to_string_pretty(&Aliases::new(&db.meta))?,
+    )
+    .await?;
+    tombstones::save(output_folder, db).await?;
+    status::save(output_folder, db).await?;
+    bundled::save(output_folder, db).await?;
+    unreferenced::save(output_folder, db).await?;
+    save_index(output_folder, db).await
+}
+
+/// Writes the JSON files of `db` to `dir` like saving writes them to the output directory, but
+/// without Nix expressions and compressed copies. For checking them before they replace the
+/// output, see [`crate::schema::validate_db`].
+pub async fn write_json_files(dir: &Path, db: &PluginDb) -> anyhow::Result<()> {
+    struct Quiet;
+    impl GeneratorEvents for Quiet {}
+
+    let mut db = db.clone();
+    db.nix_output = false;
+    db.compression = Compression::None;
+    db_save(dir, &db, &Quiet).await
+}
+
+/// Fails if an IDE mapping references a plugin version without an entry, see
+/// [`PluginDb::dangling`].
+fn check_integrity(db: &PluginDb) -> anyhow::Result<()> {
+    let dangling = db.dangling();
+    let Some(first) = dangling.first() else {
+        return Ok(());
+    };
+    Err(anyhow!(
+        "refusing to save: {} plugin versions in the IDE mappings have no entry, e.g. {first}",
+        dangling.len()
+    ))
+}
+
+/// Save all_plugins.json and the IDE mappings that changed since the last flush.
+async fn db_flush(
+    output_folder: &Path,
+    db: &mut PluginDb,
+    events: &dyn GeneratorEvents,
+) -> anyhow::Result<()> {
+    check_integrity(db)?;
+    create_dir_all(output_folder.join("ides")).await?;
+    save_all_plugins(output_folder, db, false, Compression::None).await?;
+    let dirty = take(&mut db.dirty_ides);
+    save_ide_mappings(output_folder, db, &dirty, false, Compression::None, events).await?;
+    Ok(())
+}
+
+async fn save_all_plugins(
+    output_folder: &Path,
+    db: &PluginDb,
+    nix: bool,
+    compression: Compression,
+) -> anyhow::Result<()> {
+    let out_path = output_folder.join(ALL_PLUGINS_JSON);
+    let shard_dir = output_folder.join(ALL_PLUGINS_DIR);
+    match db.layout {
+        PluginsLayout::Single => {
+            let previous = db
+                .changed_plugins
+                .is_empty()
+                .then(|| db.entries_meta.get(ALL_PLUGINS_JSON))
+                .flatten();
+            let file = AllPluginsOutput {
+                path: &out_path,
+                previous,
+                nix,
+                compact: db.compact,
+                compression,
+                deterministic: db.deterministic,
+                rewrite: db.rewrite,
+            };
+            write_all_plugins(file, &db.all_plugins).await?;
+            if exists(&shard_dir)? {
+                remove_dir_all(&shard_dir).await?;
+            }
+        }
+        PluginsLayout::Sharded => {
+            let mut shards: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::new();
+            for (key, entry) in &db.all_plugins {
+                shards
+                    .entry(shard_of(&key.name))
+                    .or_default()
+                    .insert(key, entry);
+            }
+            create_dir_all(&shard_dir).await?;
+            // Remove shards that no longer have any entries.
+            let mut existing = read_dir(&shard_dir).await?;
+            while let Some(shard) = existing.next_entry().await? {
+                let path = shard.path();
+                let plain = Compression::of_path(&path).map_or(path.clone(), |(_, path)| path);
+                if [Some("json".as_ref()), Some("nix".as_ref())].contains(&plain.extension())
+                    && plain
+                        .file_stem()
+                        .is_none_or(|stem| !shards.contains_key(&*stem.to_string_lossy()))
+                {
+                    remove_file(&path).await?;
+                }
+            }
+            let changed_shards: HashSet<_> = db
+                .changed_plugins
+                .iter()
+                .map(|name| shard_of(name))
+                .collect();
+            for (shard, plugins) in &shards {
+                let shard_path = shard_dir.join(format!("{shard}.json"));
+                let previous = (!changed_shards.contains(shard))
+                    .then(|| {
+                        db.entries_meta
+                            .get(&relative_path(output_folder, &shard_path))
+                    })
+                    .flatten();
+                let file = AllPluginsOutput {
+                    path: &shard_path,
+                    previous,
+                    nix,
+                    compact: db.compact,
+                    compression,
+                    deterministic: db.deterministic,
+                    rewrite: db.rewrite,
+                };
+                write_all_plugins(file, plugins).await?;
+            }
+            let compressed = Compression::ENABLED.map(|c| c.path_of(&out_path));
+            for path in [Some(out_path.clone()), Some(out_path.with_extension("nix"))]
+                .into_iter()
+                .chain(compressed)
+                .flatten()
+            {
+                if exists(&path)? {
+                    remove_file(&path).await?;
+                }
+            }
+        }
+    }
+
+    let out_path = output_folder.join(PLUGINS_META_JSON);
+    debug!("Generating {out_path:?}...");
+    write_if_changed(&out_path, serde_json::to_string_pretty(&db.meta)?).await?;
+    Ok(())
+}
+
+/// A file of all_plugins to write, see [`write_all_plugins`].
+struct AllPluginsOutput<'a> {
+    path: &'a Path,
+    /// The metadata of the file as loaded, if none of its entries changed since.
+    previous: Option<&'a GeneratorMeta>,
+    nix: bool,
+    compact: bool,
+    compression: Compression,
+    deterministic: bool,
+    /// Write the file even if `previous` is set, see [`PluginDb::rewrite_all`].
+    rewrite: bool,
+}
+
+/// Like [`write_json`], but streams the JSON to the file, as all_plugins is large. Files whose
+/// entries didn't change are only written if their format did.
+async fn write_all_plugins(
+    file: AllPluginsOutput<'_>,
+    plugins: &impl Serialize,
+) -> anyhow::Result<()> {
+    let AllPluginsOutput {
+        path: out_path,
+        previous,
+        nix,
+        compact,
+        compression,
+        deterministic,
+        rewrite,
+    } = file;
+    // Files switching to or from deterministic output get new metadata.
+    let previous = previous.filter(|meta| meta.generated_at.is_none() == deterministic);
+    if previous.is_some()
+        && !rewrite
+        && outputs_exist(out_path, nix, compression)?
+        && is_compact(out_path)? == compact
+    {
+        debug!("{out_path:?} is unchanged.");
+        return Ok(());
+    }
+    let contents = AllPluginsFile {
+        schema_version: migrations::SCHEMA_VERSION,
+        meta: Some(
+            previous
+                .cloned()
+                .unwrap_or_else(|| GeneratorMeta::current(deterministic)),
+        ),
+        plugins,
+    };
+    debug!("Generating {out_path:?}...");
+    let changed = block_in_place(|| write_json_if_changed(out_path, &contents, compact))?;
+    if !changed {
+        debug!("{out_path:?} is unchanged.");
+    }
+    if let Some(compressed) = compression.path_of(out_path)
+        && (changed || !exists(&compressed)?)
+    {
+        block_in_place(|| compress_file(out_path, &compressed, compression))?;
+    }
+    if nix {
+        write_if_changed(&out_path.with_extension("nix"), nix::render(&contents)?).await?;
+    }
+    Ok(())
+}
+
+/// Writes `contents` to the JSON file `out_path`, and with `nix` as a Nix expression and with
+/// `compression` compressed next to it. Files whose contents didn't change are not rewritten.
+/// Returns whether the JSON file changed.
+async fn write_json(
+    out_path: &Path,
+    contents: &impl Serialize,
+    nix: bool,
+    compression: Compression,
+) -> anyhow::Result<bool> {
+    debug!("Generating {out_path:?}...");
+    let json = serde_json::to_string_pretty(contents)?;
+    if let Some(compressed) = compression.path_of(out_path) {
+        write_if_changed(&compressed, compression.encode(json.as_bytes())?).await?;
+    }
+    let changed = write_if_changed(out_path, json).await?;
+    if !changed {
+        debug!("{out_path:?} is unchanged.");
+    }
+    if nix {
+        write_if_changed(&out_path.with_extension("nix"), nix::render(contents)?).await?;
+    }
+    Ok(changed)
+}
+
+/// The files in the `ides` directory of `out_dir` that aren't IDE mappings of a known product
+/// (or their Nix expressions and compressed copies), sorted.
+pub async fn unknown_ide_files(out_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let ides_dir = out_dir.join("ides");
+    let mut unknown = Vec::new();
+    if !exists(&ides_dir)? {
+        return Ok(unknown);
+    }
+    let mut files = read_dir(&ides_dir).await?;
+    while let Some(file) = files.next_entry().await? {
+        let path = file.path();
+        let plain = Compression::of_path(&path).map_or(path.clone(), |(_, path)| path);
+        let json = if plain.extension() == Some("nix".as_ref()) {
+            plain.with_extension("json")
+        } else {
+            plain
+        };
+        if json
+            .file_name()
+            .and_then(|name| IdeVersion::from_json_filename(&name.to_string_lossy()))
+            .is_none()
+        {
+            unknown.push(path);
+        }
+    }
+    unknown.sort();
+    Ok(unknown)
+}
+
+/// Removes the [`unknown_ide_files`] of `out_dir`, or moves them to `quarantine`. Returns how
+/// many there were.
+pub async fn remove_unknown_ide_files(
+    out_dir: &Path,
+    quarantine: Option<&Path>,
+) -> anyhow::Result<usize> {
+    let unknown = unknown_ide_files(out_dir).await?;
+    for path in &unknown {
+        match quarantine {
+            Some(quarantine) => {
+                create_dir_all(quarantine).await?;
+                let Some(name) = path.file_name() else {
+                    continue;
+                };
+                rename(path, quarantine.join(name)).await?;
+                info!(
+                    "Moved unknown IDE file {} to {}",
+                    path.display(),
+                    quarantine.display()
+                );
+            }
+            None => {
+                remove_file(path).await?;
+                info!("Removed unknown IDE file {}", path.display());
+            }
+        }
+    }
+    Ok(unknown.len())
+}
+
+/// Removes the compressed copies of the JSON files other than those of `keep`, see
+/// [`PluginDb::compression`].
+async fn remove_compressed_output(output_folder: &Path, keep: Compression) -> anyhow::Result<()> {
+    let stale = |path: &Path| Compression::of_path(path).is_some_and(|(c, _)| c != keep);
+    for dir in [
+        output_folder.to_path_buf(),
+        output_folder.join(ALL_PLUGINS_DIR),
+        output_folder.join("ides"),
+    ] {
+        if !exists(&dir)? {
+            continue;
+        }
+        let mut files = read_dir(&dir).await?;
+        while let Some(file) = files.next_entry().await? {
+            if stale(&file.path()) {
+                remove_file(file.path()).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Removes all Nix expressions of the JSON files, see [`PluginDb::nix_output`].
+async fn remove_nix_output(output_folder: &Path) -> anyhow::Result<()> {
+    let all_plugins = output_folder.join(ALL_PLUGINS_JSON).with_extension("nix");
+    if exists(&all_plugins)? {
+        remove_file(&all_plugins).await?;
+    }
+    for dir in [
+        output_folder.join(ALL_PLUGINS_DIR),
+        output_folder.join("ides"),
+    ] {
+        if !exists(&dir)? {
+            continue;
+        }
+        let mut files = read_dir(&dir).await?;
+        while let Some(file) = files.next_entry().await? {
+            if file.path().extension() == Some("nix".as_ref()) {
+                remove_file(file.path()).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn save_ide_mapping(
+    output_folder: &Path,
+    db: &PluginDb,
+    ide: &IdeVersion,
+    nix: bool,
+    compression: Compression,
+) -> anyhow::Result<Option<PathBuf>> {
+    let mapping = &db.ides[ide];
+    let out_path = output_folder.join("ides").join(ide.to_json_filename());
+    // Mappings rebuilt in this run keep the time of the file if they turn out the same. The
+    // outer `Option` is whether they did, the inner one the time, if the file has one.
+    let previous = match &mapping.generated_at {
+        Some(generated_at) => Some(Some(generated_at.clone())),
+        None if exists(&out_path)? => match read_ide_file(&out_path).await {
+            Ok((Some(build_number), previous))
+                if build_number == ide.build_number && previous.plugins == mapping.plugins =>
+            {
+                Some(previous.generated_at)
+            }
+            _ => None,
+        },
+        None => None,
+    }
+    .filter(|generated_at| generated_at.is_none() == db.deterministic);
+    if previous.is_some() && !db.rewrite && outputs_exist(&out_path, nix, compression)? {
+        return Ok(None);
+    }
+    let file = IdeFile {
+        meta: IdeFileMeta {
+            build_number: ide.build_number.clone(),
+            product_code: ide.ide.product_code().to_string(),
+            generated_at: match previous {
+                Some(generated_at) => generated_at,
+                None => (!db.deterministic).then(now_rfc3339),
+            },
+        },
+        plugins: &mapping.plugins,
+    };
+    let changed = write_json(&out_path, &file, nix, compression).await?;
+    Ok(changed.then_some(out_path))
+}
+
+/// Writes the mapping files of `ides` concurrently, see [`save_ide_mapping`].
+async fn save_ide_mappings(
+    output_folder: &Path,
+    db: &PluginDb,
+    ides: impl IntoIterator<Item = &IdeVersion>,
+    nix: bool,
+    compression: Compression,
+    events: &dyn GeneratorEvents,
+) -> anyhow::Result<()> {
+    let writes: Vec<_> = ides
+        .into_iter()
+        .map(|ide| async move {
+            if let Some(path) = save_ide_mapping(output_folder, db, ide, nix, compression).await? {
+                events.on_ide_written(ide, &path);
+            }
+            anyhow::Ok(())
+        })
+        .collect();
+    iter(writes)
+        .buffer_unordered(IDE_FILE_CONCURRENCY)
+        .try_collect()
+        .await
+}
+
+/// index.json, describing the available IDE JSON files.
+#[derive(Serialize)]
+struct Index<'a> {
+    meta: GeneratorMeta,
+    ides: &'a [IndexEntry],
+    /// Newest stable version per nix key. Versions of pre-release channels are labeled (like
+    /// `2025.3-eap`), except for Android Studio, whose versions don't name their channel.
+    latest: BTreeMap<&'static str, &'a str>,
+}
+
+/// An IDE JSON file in index.json.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexEntry {
+    nix_key: &'static str,
+    version: String,
+    /// `None` for files in the old format without metadata.
+    build_number: Option<String>,
+    product_name: &'static str,
+    plugin_count: usize,
+    /// Relative to the output directory.
+    file: String,
+}
+
+/// Regenerates index.json from all IDE JSON files in the output directory, including the ones
+/// of IDE versions not in `db`, which are read from disk.
+async fn save_index(output_folder: &Path, db: &PluginDb) -> anyhow::Result<()> {
+    let loaded: HashMap<String, (&IdeVersion, &IdeMapping)> = db
+        .ides
+        .iter()
+        .map(|(ide, mapping)| (ide.to_json_filename(), (ide, mapping)))
+        .collect();
+    let mut ides = Vec::new();
+    let mut files = read_dir(output_folder.join("ides")).await?;
+    while let Some(file) = files.next_entry().await? {
+        let filename = file.file_name().to_string_lossy().into_owned();
+        let Some(ideversion) = IdeVersion::from_json_filename(&filename) else {
+            continue;
+        };
+        let (build_number, plugin_count) = if let Some((ide, mapping)) = loaded.get(&filename) {
+            let build_number = Some(ide.build_number.clone()).filter(|b| !b.is_empty());
+            (build_number, mapping.plugins.len())
+        } else {
+            match serde_json::from_str(&read_to_string(file.path()).await?)? {
+                IdeFileCompat::WithMeta(IdeFile { meta, plugins }) => {
+                    (Some(meta.build_number), plugins.len())
+                }
+                IdeFileCompat::Flat(plugins) => (None, plugins.len()),
+            }
+        };
+        ides.push(IndexEntry {
+            nix_key: ideversion.ide.nix_key(),
+            version: ideversion.version,
+            build_number,
+            product_name: ideversion.ide.display_name(),
+            plugin_count,
+            file: format!("ides/{filename}"),
+        });
+    }
+    ides.sort_by(|a, b| a.file.cmp(&b.file));
+    let mut latest: BTreeMap<&str, &str> = BTreeMap::new();
+    for ide in ides.iter().filter(|ide| !ide.version.contains('-')) {
+        let newest = latest.entry(ide.nix_key).or_insert(&ide.version);
+        if compare_versions(&ide.version, newest).is_gt() {
+            *newest = &ide.version;
+        }
+    }
+
+    let out_path = output_folder.join(INDEX_JSON);
+    debug!("Generating {out_path:?}...");
+    let index = serde_json::to_value(Index {
+        meta: GeneratorMeta::current(db.deterministic),
+        ides: &ides,
+        latest,
+    })?;
+    // Only the metadata would change.
+    if !db.rewrite
+        && let Ok(previous) = read_to_string(&out_path).await
+        && let Ok(previous) = serde_json::from_str::<serde_json::Value>(&previous)
+        && is_deterministic(&previous) == db.deterministic
+        && previous.get("ides") == index.get("ides")
+        && previous.get("latest") == index.get("latest")
+    {
+        debug!("{out_path:?} is unchanged.");
+        return Ok(());
+    }
+    write_atomic(&out_path, serde_json::to_string_pretty(&index)?).await
+}
+
+/// Download sizes of the plugins in the database, see [`db_stats`].
+pub struct DbStats {
+    /// Sorted by IDE version.
+    pub ides: Vec<IdeStats>,
+    /// The largest artifacts as `<id>@<version>` and their size, largest first.
+    pub largest: Vec<(String, u64)>,
+}
+
+pub struct IdeStats {
+    /// `<nix key>-<version>`.
+    pub ide: String,
+    pub plugins: usize,
+    /// Total size of the stable (or else EAP) version of all plugins.
+    pub bytes: u64,
+    /// Plugins hashed before sizes were recorded, which aren't in `bytes`.
+    pub unknown_size: usize,
+}
+
+/// Computes the download sizes of all IDE versions in `db`, and its `largest` artifacts.
+pub fn db_stats(db: &PluginDb, largest: usize) -> DbStats {
+    let mut ides: Vec<IdeStats> = db
+        .ides
+        .iter()
+        .map(|(ide, mapping)| {
+            let mut stats = IdeStats {
+                ide: ide.to_string(),
+                plugins: mapping.plugins.len(),
+                bytes: 0,
+                unknown_size: 0,
+            };
+            for (name, channels) in &mapping.plugins {
+                let size = channels.versions().next().and_then(|version| {
+                    db.all_plugins.get(&PluginVersion::new(name, version))?.size
+                });
+                match size {
+                    Some(size) => stats.bytes += size,
+                    None => stats.unknown_size += 1,
+                }
+            }
+            stats
+        })
+        .collect();
+    ides.sort_by(|a, b| a.ide.cmp(&b.ide));
+
+    let mut sizes: Vec<(String, u64)> = db
+        .all_plugins
+        .iter()
+        .filter_map(|(key, entry)| Some((format!("{}@{}", key.name, key.version), entry.size?)))
+        .collect();
+    sizes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    sizes.truncate(largest);
+    DbStats {
+        ides,
+        largest: sizes,
+    }
+}
+
+/// What [`db_cleanup`] removed.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CleanupReport {
+    /// Plugin versions no IDE mapping references anymore.
+    pub removed_entries: usize,
+    /// Plugins of which no version is left.
+    pub removed_plugins: Vec<String>,
+    /// Total artifact size of the removed entries, as far as it is known.
+    pub freed_bytes: u64,
+    /// Removed entries hashed before sizes were recorded, which aren't in `freed_bytes`.
+    pub unknown_size: usize,
+    /// Unreferenced entries kept for their grace period, see unreferenced.json.
+    pub kept_unreferenced: usize,
+    /// Entries left in the database.
+    pub remaining_entries: usize,
+    /// The removed entries, sorted. Only counted in the run summary.
+    #[serde(skip)]
+    pub removed: Vec<PluginVersion>,
+}
+
+/// Removes the entries and metadata that no IDE mapping references anymore. With a `grace`
+/// limit, unreferenced entries are only marked until it is reached.
+pub async fn db_cleanup(db: &mut PluginDb, grace: Grace) -> anyhow::Result<CleanupReport> {
+    let used_keys: HashSet<_> = db
+        .ides
+        .values()
+        .flat_map(|mapping| {
+            mapping.plugins.iter().flat_map(|(name, channels)| {
+                channels
+                    .versions()
+                    .map(|version| PluginVersion::new(name, version))
+            })
+        })
+        .collect();
+
+    let unused: HashSet<_> = db
+        .all_plugins
+        .keys()
+        .filter(|key| !used_keys.contains(key))
+        .cloned()
+        .collect();
+    let expired = db.mark_unreferenced(&unused, grace)?;
+    let (kept, unused): (BTreeMap<_, _>, BTreeMap<_, _>) = take(&mut db.all_plugins)
+        .into_iter()
+        .partition(|(k, _)| !expired.contains(k));
+    db.all_plugins = kept;
+    let mut report = CleanupReport {
+        removed_entries: unused.len(),
+        kept_unreferenced: db.unreferenced.len(),
+        remaining_entries: db.all_plugins.len(),
+        removed: unused.keys().cloned().collect(),
+        ..CleanupReport::default()
+    };
+    for entry in unused.values() {
+        match entry.size {
+            Some(size) => report.freed_bytes += size,
+            None => report.unknown_size += 1,
+        }
+    }
+    let remaining: HashSet<_> = db.all_plugins.keys().map(|key| &key.name).collect();
+    let removed: BTreeSet<_> = unused
+        .keys()
+        .map(|key| &key.name)
+        .filter(|name| !remaining.contains(name))
+        .cloned()
+        .collect();
+    report.removed_plugins = removed.into_iter().collect();
+    db.changed_plugins
+        .extend(unused.into_keys().map(|key| key.name));
+
+    let used_plugins: HashSet<_> = db
+        .ides
+        .values()
+        .flat_map(|mapping| mapping.plugins.keys())
+        .collect();
+    db.meta.retain(|plugin, _| used_plugins.contains(plugin));
+    // Failing plugins may have no versions yet.
+    db.status
+        .retain(|plugin, status| used_plugins.contains(plugin) || status.failure.is_some());
+    let ides: HashSet<_> = db.ides.keys().map(IdeVersion::to_string).collect();
+    db.bundled.retain(|ide, _| ides.contains(ide));
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(name: &str, version: &str) -> String {
+        let key = PluginVersion::new(name, version);
+        let json = serde_json::to_string(&key).unwrap();
+        let parsed: PluginVersion = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, key, "{json}");
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn plugin_version_round_trips() {
+        assert_eq!(round_trip("Pythonid", "243.1"), "Pythonid/--/243.1");
+        assert_eq!(round_trip("a/--/b", "1.0"), "a%2F--%2Fb/--/1.0");
+        assert_eq!(round_trip("100%", "1.0"), "100%25/--/1.0");
+        assert_eq!(round_trip("a/b", "1.0"), "a%2Fb/--/1.0");
+        assert_eq!(round_trip("a%2Fb", "1.0"), "a%252Fb/--/1.0");
+        assert_eq!(round_trip("日本語 ü", "1.0"), "日本語 ü/--/1.0");
+        assert_eq!(round_trip("plugin", "1.0/--/2"), "plugin/--/1.0/--/2");
+        assert_eq!(round_trip("a/--/b", "c/--/d"), "a%2F--%2Fb/--/c/--/d");
+    }
+
+    #[test]
+    fn plugin_version_rejects_invalid_escapes() {
+        for key in [
+            "a%2/--/1.0",
+            "a%/--/1.0",
+            "a%2f/--/1.0",
+            "a%41/--/1.0",
+            "a%ü/--/1.0",
+            "no separator",
+        ] {
+            let json = serde_json::to_string(key).unwrap();
+            assert!(
+                serde_json::from_str::<PluginVersion>(&json).is_err(),
+                "{key}"
+            );
+        }
+    }
+
+    fn query(url: &Url, name: &str) -> String {
+        let query = url.query().unwrap_or_default();
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix(&format!("{name}=")))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn plugin_ids_are_percent_encoded_in_urls() {
+        for (id, encoded) in [
+            ("String Manipulation", "String+Manipulation"),
+            (
+                "org.jetbrains.plugins.go+tools",
+                "org.jetbrains.plugins.go%2Btools",
+            ),
+            (
+                "  com.example.ünïcödé ",
+                "com.example.%C3%BCn%C3%AFc%C3%B6d%C3%A9",
+            ),
+            ("a&version=1#x", "a%26version%3D1%23x"),
+        ] {
+            let id = normalize_plugin_id(id).unwrap();
+            let list = plugin_list_url(id, None).unwrap();
+            assert_eq!(query(&list, "pluginId"), encoded);
+            assert_eq!(list.path(), "/plugins/list");
+            let list = plugin_list_url(id, Some("eap")).unwrap();
+            assert_eq!(query(&list, "pluginId"), encoded);
+            assert_eq!(list.path(), "/plugins/eap/list");
+            let download = marketplace_download_url(id, "1.0+b", Some("eap")).unwrap();
+            assert_eq!(query(&download, "pluginId"), encoded);
+            assert_eq!(query(&download, "version"), "1.0%2Bb");
+            assert_eq!(query(&download, "channel"), "eap");
+            let pairs: HashMap<_, _> = download.query_pairs().collect();
+            assert_eq!(pairs["pluginId"], id);
+        }
+    }
+
+    #[test]
+    fn invalid_plugin_ids_are_rejected() {
+        assert_eq!(normalize_plugin_id(""), Err(InvalidId::Empty));
+        assert_eq!(normalize_plugin_id(" \t "), Err(InvalidId::Empty));
+        assert_eq!(normalize_plugin_id("a\nb"), Err(InvalidId::Control));
+        assert_eq!(normalize_plugin_id("a\u{0}"), Err(InvalidId::Control));
+        assert_eq!(normalize_plugin_id("a\u{7f}b"), Err(InvalidId::Control));
+        assert_eq!(normalize_plugin_id(" a b "), Ok("a b"));
+    }
+}
@@ -0,0 +1,71 @@
+//! Renders the JSON files of the output directory as equivalent Nix expressions, which can be
+//! imported without `builtins.fromJSON`.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt::Write;
+
+/// Renders `value` as a Nix expression with the same structure as its JSON serialization.
+/// Attributes of the outermost two levels are put on lines of their own.
+pub fn render(value: &impl Serialize) -> anyhow::Result<String> {
+    let mut out = String::new();
+    write_value(&mut out, &serde_json::to_value(value)?, 0);
+    out.push('\n');
+    Ok(out)
+}
+
+fn write_value(out: &mut String, value: &Value, depth: usize) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => _ = write!(out, "{b}"),
+        Value::Number(n) => _ = write!(out, "{n}"),
+        Value::String(s) => write_string(out, s),
+        Value::Array(items) => {
+            out.push('[');
+            for item in items {
+                out.push(' ');
+                write_value(out, item, depth + 1);
+            }
+            out.push_str(" ]");
+        }
+        Value::Object(attrs) => {
+            let (separator, indent) = if depth < 2 {
+                ("\n", "  ".repeat(depth + 1))
+            } else {
+                (" ", String::new())
+            };
+            out.push('{');
+            for (name, value) in attrs {
+                out.push_str(separator);
+                out.push_str(&indent);
+                write_string(out, name);
+                out.push_str(" = ");
+                write_value(out, value, depth + 1);
+                out.push(';');
+            }
+            out.push_str(separator);
+            if depth < 2 {
+                out.push_str(&"  ".repeat(depth));
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // `${` would start an interpolation.
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
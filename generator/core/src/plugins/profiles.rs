@@ -0,0 +1,151 @@
+//! Curated plugin sets ("profiles"), defined as `<name>.toml` in a directory and written to
+//! `profiles/<name>-<ide>.json` in the output directory with the plugins that are available for
+//! each IDE version.
+
+use super::{IdeMapping, PluginDb, PluginVersion};
+use crate::fs::write_if_changed;
+use anyhow::anyhow;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::exists;
+use std::path::Path;
+use tokio::fs::{create_dir_all, read_dir, read_to_string};
+
+const PROFILES_DIR: &str = "profiles";
+
+/// A profile definition, e.g. `profiles/rust-dev.toml`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Plugin IDs.
+    pub plugins: Vec<String>,
+}
+
+/// A profile rendered for one IDE version.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileFile<'a> {
+    profile: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+    /// `<nix key>-<version>`
+    ide: String,
+    /// Plugin ID -> version, for the plugins available for the IDE together with all their
+    /// dependencies.
+    plugins: BTreeMap<&'a str, &'a str>,
+    /// Plugins of the profile that are not.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unavailable: Vec<&'a str>,
+}
+
+/// Reads the profile definitions (`*.toml`) in `dir`, by name. None if `dir` doesn't exist.
+pub async fn load_profiles(dir: &Path) -> anyhow::Result<BTreeMap<String, Profile>> {
+    let mut profiles = BTreeMap::new();
+    if !exists(dir)? {
+        return Ok(profiles);
+    }
+    let mut entries = read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "toml") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let profile: Profile = toml::from_str(&read_to_string(&path).await?)
+            .map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        profiles.insert(name.to_string(), profile);
+    }
+    Ok(profiles)
+}
+
+/// Writes every profile for every IDE mapping of `db` to `out_dir/profiles`. Warns about
+/// plugin IDs of a profile that are in none of the mappings.
+pub async fn write_profiles(
+    out_dir: &Path,
+    db: &PluginDb,
+    profiles: &BTreeMap<String, Profile>,
+) -> anyhow::Result<()> {
+    if profiles.is_empty() {
+        return Ok(());
+    }
+    let dir = out_dir.join(PROFILES_DIR);
+    create_dir_all(&dir).await?;
+    for (name, profile) in profiles {
+        let unknown: Vec<_> = profile
+            .plugins
+            .iter()
+            .filter(|pluginkey| {
+                !db.ides
+                    .values()
+                    .any(|mapping| mapping.plugins.contains_key(*pluginkey))
+            })
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            warn!(
+                "Profile {name} lists plugins not available for any IDE: {}",
+                unknown.join(", ")
+            );
+        }
+        for (ide, mapping) in &db.ides {
+            let mut file = ProfileFile {
+                profile: name,
+                description: profile.description.as_deref(),
+                ide: ide.to_string(),
+                plugins: BTreeMap::new(),
+                unavailable: Vec::new(),
+            };
+            for pluginkey in &profile.plugins {
+                match mapping
+                    .plugins
+                    .get(pluginkey)
+                    .and_then(|c| c.versions().next())
+                {
+                    Some(version) if resolvable(db, mapping, pluginkey, &mut HashSet::new()) => {
+                        file.plugins.insert(pluginkey, version);
+                    }
+                    _ => file.unavailable.push(pluginkey),
+                }
+            }
+            let path = dir.join(format!("{name}-{ide}.json"));
+            if write_if_changed(&path, serde_json::to_string_pretty(&file)?).await? {
+                debug!("Wrote {}", path.display());
+            }
+        }
+    }
+    info!(
+        "Wrote {} profiles for {} IDE versions.",
+        profiles.len(),
+        db.ides.len()
+    );
+    Ok(())
+}
+
+/// Whether `pluginkey` and all of its dependencies, recursively, are in `mapping`.
+fn resolvable<'a>(
+    db: &'a PluginDb,
+    mapping: &'a IdeMapping,
+    pluginkey: &'a str,
+    visiting: &mut HashSet<&'a str>,
+) -> bool {
+    if !visiting.insert(pluginkey) {
+        // A dependency cycle, or already checked on another path.
+        return true;
+    }
+    let Some((pluginkey, channels)) = mapping.plugins.get_key_value(pluginkey) else {
+        return false;
+    };
+    let dependencies: BTreeSet<&str> = channels
+        .versions()
+        .filter_map(|version| db.all_plugins.get(&PluginVersion::new(pluginkey, version)))
+        .flat_map(|entry| entry.dependencies.iter().map(String::as_str))
+        .collect();
+    dependencies
+        .into_iter()
+        .all(|dependency| resolvable(db, mapping, dependency, visiting))
+}
@@ -0,0 +1,201 @@
+//! Recovery from all_plugins files that aren't valid JSON, e.g. after an interrupted write: the
+//! file is backed up next to itself and the entries before the error are salvaged, so that a
+//! run can continue without them instead of failing.
+
+use super::relative_path;
+use crate::compression::Compression;
+use crate::migrations;
+use log::warn;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::fs::exists;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs::copy;
+
+/// A corrupted all_plugins file that was loaded with only its salvageable entries.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveredFile {
+    /// Relative to the output directory.
+    pub path: String,
+    /// The copy of the corrupted file, relative to the output directory.
+    pub backup: String,
+    /// Number of entries salvaged from it.
+    pub salvaged: usize,
+    pub error: String,
+}
+
+/// Backs up the all_plugins file `file` in `out_dir`, whose contents `text` failed to parse
+/// with `error`, and salvages what it can from them. Returns the salvaged contents, without
+/// metadata, to be migrated like a valid file.
+pub(super) async fn quarantine(
+    out_dir: &Path,
+    file: &Path,
+    text: &str,
+    error: &serde_json::Error,
+) -> anyhow::Result<(Value, RecoveredFile)> {
+    let source = stored_path(file)?;
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut backup = source.clone().into_os_string();
+    backup.push(format!(".corrupt-{secs}"));
+    let backup = PathBuf::from(backup);
+    copy(&source, &backup).await?;
+
+    let contents = salvage(text);
+    let salvaged = contents["plugins"].as_object().map_or(0, Map::len);
+    let recovered = RecoveredFile {
+        path: relative_path(out_dir, file),
+        backup: relative_path(out_dir, &backup),
+        salvaged,
+        error: error.to_string(),
+    };
+    warn!(
+        "{} is corrupted ({error}), continuing with the {salvaged} entries before the error. \
+         The file was backed up to {}, run `repair` to restore the entries the IDE mappings \
+         still reference.",
+        recovered.path, recovered.backup
+    );
+    Ok((contents, recovered))
+}
+
+/// The file on disk that [`super::read_json`] read for `file`.
+fn stored_path(file: &Path) -> std::io::Result<PathBuf> {
+    if !exists(file)? {
+        for compression in Compression::ENABLED {
+            if let Some(compressed) = compression.path_of(file)
+                && exists(&compressed)?
+            {
+                return Ok(compressed);
+            }
+        }
+    }
+    Ok(file.to_path_buf())
+}
+
+/// The schema version and the complete entries of an all_plugins file up to the first error in
+/// `text`. Files without a schema version (it comes first) are taken to have the current one.
+fn salvage(text: &str) -> Value {
+    let mut contents = Map::new();
+    let mut plugins = Map::new();
+    let mut cursor = Cursor { text, pos: 0 };
+    if cursor.eat('{') {
+        while let Some(key) = cursor.value::<String>() {
+            if !cursor.eat(':') {
+                break;
+            }
+            if key == "plugins" {
+                if !cursor.eat('{') {
+                    break;
+                }
+                while let Some(key) = cursor.value::<String>() {
+                    let Some(entry) = cursor.eat(':').then(|| cursor.value()).flatten() else {
+                        break;
+                    };
+                    plugins.insert(key, entry);
+                    if !cursor.eat(',') {
+                        break;
+                    }
+                }
+                if !cursor.eat('}') {
+                    break;
+                }
+            } else if key == "schemaVersion" {
+                let Some(version) = cursor.value::<Value>() else {
+                    break;
+                };
+                contents.insert(key, version);
+            } else if cursor.value::<Value>().is_none() {
+                break;
+            }
+            if !cursor.eat(',') {
+                break;
+            }
+        }
+    }
+    contents
+        .entry("schemaVersion")
+        .or_insert(migrations::SCHEMA_VERSION.into());
+    contents.insert("plugins".to_string(), Value::Object(plugins));
+    Value::Object(contents)
+}
+
+/// Reads JSON values and punctuation one by one, to get as far as possible in broken JSON.
+struct Cursor<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    /// Skips whitespace and `c` if it comes next.
+    fn eat(&mut self, c: char) -> bool {
+        let rest = self.text[self.pos..].trim_start();
+        self.pos = self.text.len() - rest.len();
+        if rest.starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value<T: DeserializeOwned>(&mut self) -> Option<T> {
+        let mut values =
+            serde_json::Deserializer::from_str(&self.text[self.pos..]).into_iter::<T>();
+        let value = values.next()?.ok()?;
+        self.pos += values.byte_offset();
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn salvages_complete_entries() {
+        let text =
+            r#"{"schemaVersion": 2, "plugins": {"a/--/1": {"p": "a.zip"}, "b/--/1": {"p": "b"#;
+        assert_eq!(
+            salvage(text),
+            json!({"schemaVersion": 2, "plugins": {"a/--/1": {"p": "a.zip"}}})
+        );
+    }
+
+    #[test]
+    fn skips_other_keys() {
+        let text = r#"{"schemaVersion": 2, "meta": {"x": [1, 2]}, "plugins": {"a/--/1": 1}}"#;
+        assert_eq!(
+            salvage(text),
+            json!({"schemaVersion": 2, "plugins": {"a/--/1": 1}})
+        );
+    }
+
+    #[test]
+    fn defaults_to_current_schema_version() {
+        let expected = json!({"schemaVersion": migrations::SCHEMA_VERSION, "plugins": {}});
+        assert_eq!(salvage(""), expected);
+        assert_eq!(salvage("{\"plugins\": {\"a/--/1\""), expected);
+        assert_eq!(salvage("not json"), expected);
+    }
+
+    #[test]
+    fn cursor_skips_whitespace() {
+        let mut cursor = Cursor {
+            text: " \n{ \"key\" :\t1 ,",
+            pos: 0,
+        };
+        assert!(cursor.eat('{'));
+        assert!(!cursor.eat(':'));
+        assert_eq!(cursor.value::<String>().as_deref(), Some("key"));
+        assert!(cursor.eat(':'));
+        assert_eq!(cursor.value::<u32>(), Some(1));
+        assert!(cursor.eat(','));
+        assert_eq!(cursor.value::<Value>(), None);
+    }
+}
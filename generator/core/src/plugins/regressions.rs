@@ -0,0 +1,151 @@
+//! Guard against plugins vanishing from an IDE because of a hiccup (a failed details request,
+//! an interrupted run) rather than a real incompatibility.
+
+use super::{PluginDb, PluginVersion, UpdateOutcome, json_exists, read_ide_file};
+use crate::config::Exclude;
+use crate::ides::IdeVersion;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+
+/// The IDE mapping entries a run dropped or kept, compared to the previous mapping files.
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Regressions {
+    /// Dropped because the plugin is confirmed to be gone from the IDE.
+    pub dropped: Vec<DroppedVersion>,
+    /// Kept from the previous mapping, as this run couldn't confirm that the plugin is gone.
+    pub kept: Vec<DroppedVersion>,
+}
+
+/// A plugin that was in the previous mapping of an IDE, but not in the new one.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DroppedVersion {
+    /// `<nix key>-<version>`
+    pub ide: String,
+    pub plugin: String,
+    /// The stable (or EAP) version of the previous mapping.
+    pub version: String,
+    pub reason: DropReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DropReason {
+    /// Processed in this run without a compatible version for the IDE.
+    Incompatible,
+    /// No longer in the marketplace indices.
+    Delisted,
+    /// Excluded by ID or vendor in the configuration.
+    Excluded,
+    /// The plugin failed in this run, its last known good versions are kept.
+    Failed,
+    /// Not confirmed: the plugin was not processed in this run.
+    Unconfirmed,
+    /// Not confirmed, but the database has no entry for the previous version to keep.
+    NoEntry,
+}
+
+impl Display for DropReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DropReason::Incompatible => "no compatible version",
+            DropReason::Delisted => "removed from the marketplace",
+            DropReason::Excluded => "excluded",
+            DropReason::Failed => "failed, kept last known versions",
+            DropReason::Unconfirmed => "not confirmed",
+            DropReason::NoEntry => "previous version has no entry",
+        })
+    }
+}
+
+/// Compares the mappings of `ides` with their previous files in `out_dir`. Plugins that are
+/// missing now are only dropped if this run confirms it (see [`UpdateOutcome::succeeded`]),
+/// or they are delisted or excluded. Others keep their previous versions. Both are recorded
+/// in [`UpdateOutcome::regressions`].
+pub async fn guard_regressions(
+    db: &mut PluginDb,
+    out_dir: &Path,
+    ides: &[IdeVersion],
+    indexed: &HashSet<String>,
+    exclude: &Exclude,
+    outcome: &mut UpdateOutcome,
+) -> anyhow::Result<()> {
+    for ide in ides {
+        let path = out_dir.join("ides").join(ide.to_json_filename());
+        if !json_exists(&path)? {
+            continue;
+        }
+        let (_, previous) = read_ide_file(&path).await?;
+        for (plugin, channels) in previous.plugins {
+            if db
+                .ides
+                .get(ide)
+                .is_some_and(|mapping| mapping.plugins.contains_key(&plugin))
+            {
+                continue;
+            }
+            let Some(version) = channels.versions().next().map(str::to_string) else {
+                continue;
+            };
+            let excluded = exclude.excludes_id(&plugin)
+                || db
+                    .meta
+                    .get(&plugin)
+                    .and_then(|meta| meta.vendor.as_deref())
+                    .is_some_and(|vendor| exclude.excludes_vendor(vendor));
+            let reason = if excluded {
+                DropReason::Excluded
+            } else if !indexed.contains(&plugin) {
+                DropReason::Delisted
+            } else if outcome.succeeded.contains(&plugin) {
+                DropReason::Incompatible
+            } else if channels.versions().all(|version| {
+                db.all_plugins
+                    .contains_key(&PluginVersion::new(&plugin, version))
+            }) {
+                if outcome.failed.iter().any(|(failed, _)| *failed == plugin) {
+                    DropReason::Failed
+                } else {
+                    DropReason::Unconfirmed
+                }
+            } else {
+                DropReason::NoEntry
+            };
+            let dropped = DroppedVersion {
+                ide: ide.to_string(),
+                plugin,
+                version,
+                reason,
+            };
+            if matches!(reason, DropReason::Failed | DropReason::Unconfirmed) {
+                let mapping = db.ides.entry(ide.clone()).or_default();
+                mapping.generated_at = None;
+                mapping.plugins.insert(dropped.plugin.clone(), channels);
+                db.dirty_ides.insert(ide.clone());
+                *outcome.ide_plugins.entry(ide.to_string()).or_default() += 1;
+                outcome.regressions.kept.push(dropped);
+            } else {
+                outcome.regressions.dropped.push(dropped);
+            }
+        }
+    }
+    let regressions = &outcome.regressions;
+    if !regressions.kept.is_empty() {
+        warn!(
+            "Kept {} plugin versions in IDE mappings that this run would have dropped without \
+            confirmation, see the run summary.",
+            regressions.kept.len()
+        );
+    }
+    if !regressions.dropped.is_empty() {
+        info!(
+            "Dropped {} plugins from IDE mappings.",
+            regressions.dropped.len()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,228 @@
+//! Rebuilding the entries of all_plugins from the IDE mappings, e.g. after all_plugins.json was
+//! lost or corrupted, without processing every plugin again.
+
+use super::{
+    PluginDb, PluginDetailsIdeaPlugin, PluginVersion, RunState, current_layout, fetch_versions,
+    load_ide_mappings, load_state, resolve_dependencies, resolve_entry,
+};
+use crate::config::{Exclude, ReleaseChannels};
+use crate::events::LogEvents;
+use crate::fetch::Fetcher;
+use crate::hashing::Hasher;
+use crate::plugins::{DownloadUrls, RepositoryPlugin};
+use crate::run_context::RunContext;
+use futures::StreamExt;
+use futures::stream::iter;
+use log::{debug, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// How many plugins are repaired at once.
+const REPAIR_CONCURRENCY: usize = 16;
+
+/// Settings for [`db_repair`], like those of [`super::UpdateContext`].
+pub struct RepairContext<'a> {
+    pub fetcher: Arc<dyn Fetcher>,
+    pub hasher: Arc<dyn Hasher>,
+    /// All plugin IDs in the indices, to tell plugin dependencies from platform modules.
+    pub known_plugins: &'a HashSet<String>,
+    pub repositories: &'a HashMap<String, RepositoryPlugin>,
+    pub download_urls: &'a DownloadUrls,
+    /// Release channels besides stable and `eap` to look for referenced versions in.
+    pub release_channels: &'a ReleaseChannels,
+    /// Gets the metrics and downloaded bytes.
+    pub run: &'a RunContext,
+}
+
+/// What [`db_repair`] did.
+#[derive(Default)]
+pub struct RepairOutcome {
+    /// Referenced plugin versions that got their entry back.
+    pub restored: usize,
+    /// Referenced plugin versions that couldn't be restored and were removed from the IDE
+    /// mappings, with the reason.
+    pub dropped: Vec<(PluginVersion, String)>,
+}
+
+/// Loads the IDE mappings and the rest of the database in `out_dir` for [`db_repair`]. If
+/// all_plugins can't be read, it is left out and rebuilt from scratch.
+pub async fn load_for_repair(out_dir: &Path) -> anyhow::Result<PluginDb> {
+    let mut db = match super::db_load(out_dir).await {
+        Ok(db) => db,
+        Err(e) => {
+            warn!("Failed loading all_plugins, rebuilding it from scratch: {e:#}");
+            let mut db = PluginDb::new();
+            db.layout = current_layout(out_dir)?;
+            load_state(out_dir, &mut db).await?;
+            db
+        }
+    };
+    load_ide_mappings(out_dir, &mut db).await?;
+    Ok(db)
+}
+
+/// Hashes the plugin versions that the IDE mappings of `db` reference, but that have no entry
+/// (see [`PluginDb::dangling`]), and records their entries with the dependencies from the
+/// plugin details. Versions that can't be restored (e.g. because their download is gone) are
+/// removed from the mappings, so that the database can be saved. The next `generate` run adds
+/// them again if they come back.
+pub async fn db_repair(
+    db: &mut PluginDb,
+    ctx: &RepairContext<'_>,
+) -> anyhow::Result<RepairOutcome> {
+    let mut dangling: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for key in db.dangling() {
+        dangling.entry(key.name).or_default().insert(key.version);
+    }
+    let mut outcome = RepairOutcome::default();
+    if dangling.is_empty() {
+        return Ok(outcome);
+    }
+    info!(
+        "Restoring {} plugin versions of {} plugins...",
+        dangling.values().map(BTreeSet::len).sum::<usize>(),
+        dangling.len()
+    );
+    let exclude = Exclude::default();
+    let state = RunState {
+        db: RwLock::new(&mut *db),
+        fetcher: ctx.fetcher.clone(),
+        hasher: ctx.hasher.clone(),
+        events: &LogEvents,
+        run: ctx.run,
+        ides: &[],
+        eap: true,
+        known_plugins: ctx.known_plugins,
+        bulk: None,
+        repositories: ctx.repositories,
+        download_urls: ctx.download_urls,
+        exclude: &exclude,
+        release_channels: ctx.release_channels,
+        verify_artifacts: false,
+        entries: Default::default(),
+        republished: Default::default(),
+        skips: Default::default(),
+        durations: Default::default(),
+    };
+    let results: Vec<_> = iter(&dangling)
+        .map(|(pluginkey, versions)| repair_plugin(&state, pluginkey, versions))
+        .buffer_unordered(REPAIR_CONCURRENCY)
+        .collect()
+        .await;
+    drop(state);
+
+    for (pluginkey, versions) in results {
+        for (version, result) in versions {
+            let key = PluginVersion::new(&pluginkey, &version);
+            match result {
+                Ok(mut entry) => {
+                    entry.path.shrink_to_fit();
+                    db.all_plugins.insert(key, Arc::new(entry));
+                    db.changed_plugins.insert(pluginkey.clone());
+                    outcome.restored += 1;
+                }
+                Err(reason) => {
+                    warn!("{key}: can't be restored, removing it from the IDE mappings: {reason}");
+                    outcome.dropped.push((key, reason));
+                }
+            }
+        }
+    }
+    let dropped: HashSet<_> = outcome.dropped.iter().map(|(key, _)| key).collect();
+    for (ide, mapping) in &mut db.ides {
+        let before = mapping.plugins.clone();
+        mapping.plugins.retain(|name, channels| {
+            for version in [&mut channels.stable, &mut channels.eap] {
+                if version
+                    .as_ref()
+                    .is_some_and(|v| dropped.contains(&PluginVersion::new(name, v)))
+                {
+                    *version = None;
+                }
+            }
+            channels.stable.is_some() || channels.eap.is_some()
+        });
+        if mapping.plugins != before {
+            mapping.generated_at = None;
+            db.dirty_ides.insert(ide.clone());
+        }
+    }
+    Ok(outcome)
+}
+
+type RepairedVersions = Vec<(String, Result<super::PluginDbEntry, String>)>;
+
+/// Restores the entries of `versions` of `pluginkey`. Failures are returned per version.
+async fn repair_plugin(
+    state: &RunState<'_>,
+    pluginkey: &str,
+    versions: &BTreeSet<String>,
+) -> (String, RepairedVersions) {
+    let details = match repair_details(state, pluginkey, versions).await {
+        Ok(details) => details,
+        Err(e) => {
+            let reason = format!("failed fetching the plugin details: {e:#}");
+            return (
+                pluginkey.to_string(),
+                versions
+                    .iter()
+                    .map(|version| (version.clone(), Err(reason.clone())))
+                    .collect(),
+            );
+        }
+    };
+    let mut repaired = Vec::new();
+    for version in versions {
+        let details = details.iter().find(|details| details.version == *version);
+        let channel = details.and_then(|details| details.channel.as_deref());
+        let result = match resolve_entry(state, pluginkey, version, channel).await {
+            Ok(Some(entry)) => {
+                let mut entry = Arc::unwrap_or_clone(entry);
+                if let Some(details) = details {
+                    entry.dependencies = resolve_dependencies(state, pluginkey, details);
+                }
+                entry.channel = channel.map(str::to_string);
+                Ok(entry)
+            }
+            Ok(None) => Err("the version is no longer available".to_string()),
+            Err(e) => Err(format!("{e:#}")),
+        };
+        repaired.push((version.clone(), result));
+    }
+    (pluginkey.to_string(), repaired)
+}
+
+/// The details of `versions` of `pluginkey`, for their dependencies and channels. Release
+/// channels are only asked for versions that aren't stable. Empty for plugins from custom
+/// repositories.
+async fn repair_details(
+    state: &RunState<'_>,
+    pluginkey: &str,
+    versions: &BTreeSet<String>,
+) -> anyhow::Result<Vec<PluginDetailsIdeaPlugin>> {
+    if state.repositories.contains_key(pluginkey) {
+        return Ok(Vec::new());
+    }
+    let mut details = fetch_versions(&*state.fetcher, pluginkey, pluginkey, None)
+        .await?
+        .unwrap_or_default();
+    let mut channels = state.release_channels.of(pluginkey);
+    if !channels.contains(&"eap") {
+        channels.insert(0, "eap");
+    }
+    for channel in channels {
+        if versions
+            .iter()
+            .all(|version| details.iter().any(|details| details.version == *version))
+        {
+            break;
+        }
+        match fetch_versions(&*state.fetcher, pluginkey, pluginkey, Some(channel)).await {
+            Ok(versions) => details.extend(versions.unwrap_or_default()),
+            Err(e) => debug!("{pluginkey}: failed fetching the {channel} channel: {e:#}"),
+        }
+    }
+    Ok(details)
+}
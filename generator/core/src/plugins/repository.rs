@@ -0,0 +1,165 @@
+//! Custom plugin repositories, serving the `updatePlugins.xml` format understood by the IDEs,
+//! and plugins listed in `custom_plugins.toml`. Their plugins are processed like marketplace
+//! plugins, but downloaded from the URLs listed there, which are stored fully qualified in the
+//! database.
+
+use super::{PluginDetailsIdeaPlugin, PluginDetailsIdeaVersion, PluginDetailsVendor};
+use crate::fetch::Fetcher;
+use log::{info, warn};
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::exists;
+use std::path::Path;
+use tokio::fs::read_to_string;
+
+#[derive(Deserialize)]
+struct UpdatePlugins {
+    #[serde(default)]
+    plugin: Vec<UpdatePlugin>,
+}
+
+#[derive(Deserialize)]
+struct UpdatePlugin {
+    #[serde(rename = "@id")]
+    id: String,
+    #[serde(rename = "@url")]
+    url: String,
+    #[serde(rename = "@version")]
+    version: String,
+    #[serde(rename = "idea-version", default)]
+    idea_version: PluginDetailsIdeaVersion,
+    #[serde(default)]
+    depends: Vec<String>,
+    name: Option<String>,
+    vendor: Option<PluginDetailsVendor>,
+    description: Option<String>,
+}
+
+/// `custom_plugins.toml`, listing plugins that are only distributed as direct downloads.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CustomPlugins {
+    #[serde(default)]
+    plugin: Vec<CustomPlugin>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct CustomPlugin {
+    id: String,
+    version: String,
+    url: String,
+    since_build: Option<String>,
+    until_build: Option<String>,
+    /// IDs of required plugins.
+    #[serde(default)]
+    depends: Vec<String>,
+    name: Option<String>,
+    vendor: Option<String>,
+    description: Option<String>,
+}
+
+/// All versions of a plugin found in the custom repositories.
+#[derive(Default)]
+pub struct RepositoryPlugin {
+    pub(super) versions: Vec<PluginDetailsIdeaPlugin>,
+    /// Version -> absolute download URL.
+    pub(super) urls: HashMap<String, String>,
+}
+
+impl RepositoryPlugin {
+    /// Adds a version, unless it is already known.
+    fn add(&mut self, url: String, version: PluginDetailsIdeaPlugin) {
+        if self.urls.contains_key(&version.version) {
+            return;
+        }
+        self.urls.insert(version.version.clone(), url);
+        self.versions.push(version);
+    }
+}
+
+/// Adds the plugins of `custom_plugins.toml` at `path` (if it exists) to `plugins`.
+pub async fn load_custom_plugins(
+    path: &Path,
+    plugins: &mut HashMap<String, RepositoryPlugin>,
+) -> anyhow::Result<()> {
+    if !exists(path)? {
+        return Ok(());
+    }
+    let custom: CustomPlugins = toml::from_str(&read_to_string(path).await?)
+        .map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+    info!(
+        "{}: {} plugin versions.",
+        path.display(),
+        custom.plugin.len()
+    );
+    for plugin in custom.plugin {
+        let version = PluginDetailsIdeaPlugin {
+            id: plugin.id.clone(),
+            version: plugin.version,
+            idea_version: PluginDetailsIdeaVersion {
+                since_build: plugin.since_build,
+                until_build: plugin.until_build,
+            },
+            depends: plugin.depends,
+            name: plugin.name,
+            vendor: plugin.vendor.map(|name| PluginDetailsVendor { name }),
+            description: plugin.description,
+            pricing: None,
+            channel: None,
+        };
+        plugins
+            .entry(plugin.id)
+            .or_default()
+            .add(plugin.url, version);
+    }
+    Ok(())
+}
+
+/// Adds the plugins of all `repositories` (URLs of `updatePlugins.xml` files) to `plugins`.
+/// If several sources list the same plugin version, the first one wins.
+pub async fn fetch_repositories(
+    fetcher: &dyn Fetcher,
+    repositories: &[String],
+    plugins: &mut HashMap<String, RepositoryPlugin>,
+) -> anyhow::Result<()> {
+    for repository in repositories {
+        let base = Url::parse(repository)?;
+        let body = fetcher
+            .get(repository)
+            .await?
+            .success(&format!("{repository}: failed fetching plugin repository"))?
+            .body;
+        let update_plugins: UpdatePlugins = serde_xml_rs::from_str(&body)?;
+        info!(
+            "{repository}: {} plugin versions.",
+            update_plugins.plugin.len()
+        );
+        for plugin in update_plugins.plugin {
+            let url = match base.join(&plugin.url) {
+                Ok(url) => url.to_string(),
+                Err(e) => {
+                    warn!(
+                        "{repository}: {}: invalid URL {}: {e}",
+                        plugin.id, plugin.url
+                    );
+                    continue;
+                }
+            };
+            let version = PluginDetailsIdeaPlugin {
+                id: plugin.id.clone(),
+                version: plugin.version,
+                idea_version: plugin.idea_version,
+                depends: plugin.depends,
+                name: plugin.name,
+                vendor: plugin.vendor,
+                description: plugin.description,
+                pricing: None,
+                channel: None,
+            };
+            plugins.entry(plugin.id).or_default().add(url, version);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! The plugins a run left out of the database and why, written to skips.json for tools that
+//! need to tell an incompatible plugin from one that is broken on our side.
+
+use super::RunState;
+use crate::events::SkipReason;
+use crate::fs::write_if_changed;
+use crate::ides::IdeVersion;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::fs::{read_to_string, try_exists};
+
+const SKIPS_JSON: &str = "skips.json";
+
+/// Plugin ID -> why it was skipped.
+pub type Skips = BTreeMap<String, Skip>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Skip {
+    pub reason: SkipKind,
+    /// The excluded vendor, for [`SkipKind::ExcludedVendor`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    /// The versions that can't be downloaded, for [`SkipKind::Unavailable`].
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub versions: BTreeSet<String>,
+    /// The IDE versions (`<nix key>-<version>`) the plugin is missing from because of it.
+    pub ides: BTreeSet<String>,
+    /// RFC 3339.
+    pub skipped_at: String,
+}
+
+/// [`SkipReason`] without its details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SkipKind {
+    /// No version is compatible with any of the processed IDEs.
+    Incompatible,
+    /// Known to break the marketplace endpoints.
+    Broken,
+    /// The marketplace has no details for the plugin.
+    NoDetails,
+    /// The vendor is excluded in the configuration.
+    ExcludedVendor,
+    /// The artifact of a compatible version can't be downloaded.
+    Unavailable,
+}
+
+impl RunState<'_> {
+    /// Reports a skipped plugin to the events and records it for skips.json. `ides` are the
+    /// IDEs it is missing from, if already known.
+    pub(super) fn skip(&self, pluginkey: &str, reason: SkipReason<'_>, ides: &[IdeVersion]) {
+        self.events.on_plugin_skipped(pluginkey, reason);
+        let mut skips = self.skips.lock().unwrap();
+        let skip = skips.entry(pluginkey.to_string()).or_insert_with(|| Skip {
+            reason: match reason {
+                SkipReason::Incompatible => SkipKind::Incompatible,
+                SkipReason::Broken => SkipKind::Broken,
+                SkipReason::NoDetails => SkipKind::NoDetails,
+                SkipReason::ExcludedVendor(_) => SkipKind::ExcludedVendor,
+                SkipReason::Unavailable { .. } => SkipKind::Unavailable,
+            },
+            vendor: None,
+            versions: BTreeSet::new(),
+            ides: BTreeSet::new(),
+            skipped_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        });
+        match reason {
+            SkipReason::ExcludedVendor(vendor) => skip.vendor = Some(vendor.to_string()),
+            SkipReason::Unavailable { version } => {
+                skip.versions.insert(version.to_string());
+            }
+            _ => {}
+        }
+        skip.ides.extend(ides.iter().map(IdeVersion::to_string));
+    }
+
+    /// Records that `pluginkey` is missing from `ide` because of an earlier skip, e.g. of an
+    /// unavailable version compatible with several IDEs.
+    pub(super) fn skipped_for(&self, pluginkey: &str, ide: &IdeVersion) {
+        if let Some(skip) = self.skips.lock().unwrap().get_mut(pluginkey) {
+            skip.ides.insert(ide.to_string());
+        }
+    }
+}
+
+/// Writes the skips of a run to skips.json in `out_dir`. When resuming, the skips of the
+/// previous runs are kept unless the plugin was skipped again.
+pub async fn save(out_dir: &Path, skips: &Skips, resume: bool) -> anyhow::Result<()> {
+    let path = out_dir.join(SKIPS_JSON);
+    let mut all = if resume && try_exists(&path).await? {
+        serde_json::from_str(&read_to_string(&path).await?)?
+    } else {
+        Skips::new()
+    };
+    all.extend(skips.iter().map(|(k, v)| (k.clone(), v.clone())));
+    write_if_changed(&path, serde_json::to_string_pretty(&all)?).await?;
+    Ok(())
+}
@@ -0,0 +1,299 @@
+use super::storage::Storage;
+use super::{
+    ArtifactKind, IdeMapping, PluginChannels, PluginDb, PluginDbEntry, PluginVersion,
+    current_compact, current_compression, current_deterministic, current_layout,
+    current_nix_output,
+};
+use crate::events::GeneratorEvents;
+use crate::ides::IdeVersion;
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use log::info;
+use rusqlite::{Connection, Transaction, params};
+use std::fs::exists;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::task::block_in_place;
+
+/// Bumped whenever the tables below change incompatibly.
+const SQLITE_SCHEMA_VERSION: i64 = 6;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS plugins (
+        key TEXT PRIMARY KEY,
+        path TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        dependencies TEXT NOT NULL,
+        unpackable INTEGER NOT NULL,
+        size INTEGER,
+        etag TEXT,
+        mirror TEXT,
+        channel TEXT
+    );
+    CREATE TABLE IF NOT EXISTS plugin_meta (
+        plugin TEXT PRIMARY KEY,
+        meta TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS ides (
+        file TEXT PRIMARY KEY,
+        build_number TEXT NOT NULL,
+        generated_at TEXT
+    );
+    CREATE TABLE IF NOT EXISTS ide_plugins (
+        ide TEXT NOT NULL REFERENCES ides (file) ON DELETE CASCADE,
+        plugin TEXT NOT NULL,
+        stable TEXT,
+        eap TEXT,
+        PRIMARY KEY (ide, plugin)
+    );
+";
+
+/// Persists the database to a SQLite file. The JSON tree in the output directory is only
+/// written by [`Storage::save`], flushes only touch the SQLite file.
+///
+/// If the SQLite file does not exist yet, the database is imported from the JSON tree.
+pub struct SqliteStorage {
+    out_dir: PathBuf,
+    conn: Mutex<Connection>,
+    import: bool,
+}
+
+impl SqliteStorage {
+    pub fn open(out_dir: &Path, path: &Path) -> anyhow::Result<Self> {
+        let import = !exists(path)?;
+        let conn = Connection::open(path)?;
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version == 0 {
+            conn.execute_batch(SCHEMA)?;
+            conn.pragma_update(None, "user_version", SQLITE_SCHEMA_VERSION)?;
+        } else if version != SQLITE_SCHEMA_VERSION {
+            return Err(anyhow!(
+                "{}: unsupported schema version {version}, delete it to re-import the JSON files",
+                path.display()
+            ));
+        }
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Ok(Self {
+            out_dir: out_dir.to_path_buf(),
+            conn: Mutex::new(conn),
+            import,
+        })
+    }
+
+    async fn load_db(&self, full: bool) -> anyhow::Result<PluginDb> {
+        if self.import {
+            info!("Importing the JSON files into the SQLite database...");
+            let mut db = super::db_load_full(&self.out_dir).await?;
+            block_in_place(|| self.write(&db, WriteMode::Import))?;
+            if !full {
+                db.ides.clear();
+            }
+            return Ok(db);
+        }
+        let layout = current_layout(&self.out_dir)?;
+        let nix_output = current_nix_output(&self.out_dir)?;
+        let compact = current_compact(&self.out_dir)?;
+        let compression = current_compression(&self.out_dir)?;
+        let deterministic = current_deterministic(&self.out_dir)?;
+        let mut db = block_in_place(|| self.read(full))?;
+        db.layout = layout;
+        db.nix_output = nix_output;
+        db.compact = compact;
+        db.compression = compression;
+        db.deterministic = deterministic;
+        db.tombstones = super::tombstones::load(&self.out_dir).await?;
+        db.status = super::status::load(&self.out_dir).await?;
+        db.bundled = super::bundled::load(&self.out_dir).await?;
+        db.unreferenced = super::unreferenced::load(&self.out_dir).await?;
+        Ok(db)
+    }
+
+    fn read(&self, full: bool) -> anyhow::Result<PluginDb> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            "SELECT key, path, hash, kind, dependencies, unpackable, size, etag, mirror, channel
+            FROM plugins",
+        )?;
+        let entries = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, bool>(5)?,
+                    row.get::<_, Option<i64>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                    row.get::<_, Option<String>>(9)?,
+                ))
+            })?
+            .map(|row| {
+                let (key, path, hash, kind, dependencies, unpackable, size, etag, mirror, channel) =
+                    row?;
+                let entry = PluginDbEntry {
+                    path,
+                    hash,
+                    dependencies: serde_json::from_str(&dependencies)?,
+                    kind: serde_json::from_value::<ArtifactKind>(kind.into())?,
+                    unpackable,
+                    size: size.map(|size| size as u64),
+                    etag,
+                    mirror,
+                    channel,
+                };
+                Ok((key.parse::<PluginVersion>()?, entry))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut db = PluginDb::init(entries);
+
+        let mut stmt = conn.prepare("SELECT plugin, meta FROM plugin_meta")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let meta: String = row.get(1)?;
+            db.meta.insert(row.get(0)?, serde_json::from_str(&meta)?);
+        }
+
+        if full {
+            let mut stmt = conn.prepare("SELECT file, build_number, generated_at FROM ides")?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let file: String = row.get(0)?;
+                let Some(mut ideversion) = IdeVersion::from_json_filename(&file) else {
+                    return Err(anyhow!("invalid IDE in SQLite database: {file}"));
+                };
+                ideversion.build_number = row.get(1)?;
+                let mut mapping = IdeMapping {
+                    plugins: Default::default(),
+                    generated_at: row.get(2)?,
+                };
+                let mut stmt = conn
+                    .prepare_cached("SELECT plugin, stable, eap FROM ide_plugins WHERE ide = ?")?;
+                let mut plugins = stmt.query([&file])?;
+                while let Some(plugin) = plugins.next()? {
+                    let channels = PluginChannels {
+                        stable: plugin.get(1)?,
+                        eap: plugin.get(2)?,
+                    };
+                    mapping.plugins.insert(plugin.get(0)?, channels);
+                }
+                db.ides.insert(ideversion, mapping);
+            }
+        }
+        Ok(db)
+    }
+
+    fn write(&self, db: &PluginDb, mode: WriteMode) -> anyhow::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        if mode != WriteMode::Flush {
+            // IDE mappings are kept, like the IDE files in the output directory.
+            tx.execute_batch("DELETE FROM plugins; DELETE FROM plugin_meta;")?;
+        }
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO plugins
+                (key, path, hash, kind, dependencies, unpackable, size, etag, mirror, channel)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )?;
+            for (key, entry) in &db.all_plugins {
+                let kind = serde_json::to_value(entry.kind)?;
+                stmt.execute(params![
+                    key.to_string(),
+                    entry.path,
+                    entry.hash,
+                    kind.as_str(),
+                    serde_json::to_string(&entry.dependencies)?,
+                    entry.unpackable,
+                    entry.size.map(|size| size as i64),
+                    entry.etag,
+                    entry.mirror,
+                    entry.channel,
+                ])?;
+            }
+            let mut stmt =
+                tx.prepare("INSERT OR REPLACE INTO plugin_meta (plugin, meta) VALUES (?, ?)")?;
+            for (plugin, meta) in &db.meta {
+                stmt.execute(params![plugin, serde_json::to_string(meta)?])?;
+            }
+        }
+        // All other IDE mappings are already up to date in the database.
+        if mode == WriteMode::Import {
+            for (ide, mapping) in &db.ides {
+                write_ide_mapping(&tx, ide, mapping)?;
+            }
+        } else {
+            for ide in &db.dirty_ides {
+                write_ide_mapping(&tx, ide, &db.ides[ide])?;
+            }
+        }
+        Ok(tx.commit()?)
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum WriteMode {
+    /// Upsert the plugin entries and write the IDE mappings changed since the last flush.
+    Flush,
+    /// Like [`WriteMode::Flush`], but also remove plugin entries no longer in the database.
+    Save,
+    /// Write the whole database.
+    Import,
+}
+
+fn write_ide_mapping(
+    tx: &Transaction<'_>,
+    ide: &IdeVersion,
+    mapping: &IdeMapping,
+) -> anyhow::Result<()> {
+    let file = ide.to_json_filename();
+    tx.execute(
+        "INSERT OR REPLACE INTO ides (file, build_number, generated_at) VALUES (?, ?, ?)",
+        params![file, ide.build_number, mapping.generated_at],
+    )?;
+    tx.execute("DELETE FROM ide_plugins WHERE ide = ?", [&file])?;
+    let mut stmt = tx
+        .prepare_cached("INSERT INTO ide_plugins (ide, plugin, stable, eap) VALUES (?, ?, ?, ?)")?;
+    for (plugin, channels) in &mapping.plugins {
+        stmt.execute(params![file, plugin, channels.stable, channels.eap])?;
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self) -> BoxFuture<'_, anyhow::Result<PluginDb>> {
+        Box::pin(self.load_db(false))
+    }
+
+    fn load_full(&self) -> BoxFuture<'_, anyhow::Result<PluginDb>> {
+        Box::pin(self.load_db(true))
+    }
+
+    fn save<'a>(
+        &'a self,
+        db: &'a PluginDb,
+        events: &'a dyn GeneratorEvents,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            block_in_place(|| self.write(db, WriteMode::Save))?;
+            super::db_save(&self.out_dir, db, events).await
+        })
+    }
+
+    /// Only writes the SQLite file, so no IDE files are written.
+    fn flush<'a>(
+        &'a self,
+        db: &'a mut PluginDb,
+        _events: &'a dyn GeneratorEvents,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            block_in_place(|| self.write(db, WriteMode::Flush))?;
+            db.dirty_ides.clear();
+            Ok(())
+        })
+    }
+}
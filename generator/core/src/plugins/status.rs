@@ -0,0 +1,142 @@
+//! When each plugin was last processed successfully and why it failed since, in
+//! plugin_status.json, so that plugins stuck on an old mapping don't go unnoticed.
+
+use super::{PluginDb, UpdateOutcome};
+use crate::fs::write_if_changed;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::fs::{read_to_string, remove_file, try_exists};
+
+const PLUGIN_STATUS_JSON: &str = "plugin_status.json";
+
+pub type PluginStatuses = BTreeMap<String, PluginStatus>;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginStatus {
+    /// RFC 3339, the end of the last run that processed the plugin successfully.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<String>,
+    /// Set while the plugin fails, cleared by the next success.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<PluginFailure>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginFailure {
+    /// RFC 3339, the first run of this streak of failures.
+    pub since: String,
+    /// Number of runs in a row the plugin failed in.
+    pub runs: u32,
+    /// The last error.
+    pub error: String,
+}
+
+impl PluginStatus {
+    /// Whether the plugin wasn't processed successfully since `cutoff`. Plugins that never
+    /// succeeded are only stale if they failed.
+    fn stale_since(&self, cutoff: SystemTime) -> anyhow::Result<bool> {
+        Ok(match &self.last_success {
+            Some(last_success) => humantime::parse_rfc3339(last_success)? < cutoff,
+            None => self.failure.is_some(),
+        })
+    }
+}
+
+impl PluginDb {
+    pub fn plugin_status(&self) -> &PluginStatuses {
+        &self.status
+    }
+
+    /// Records the successes and failures of an update run.
+    pub fn record_status(&mut self, outcome: &UpdateOutcome) {
+        let now = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+        for pluginkey in &outcome.succeeded {
+            let status = self.status.entry(pluginkey.clone()).or_default();
+            status.last_success = Some(now.clone());
+            status.failure = None;
+        }
+        for (pluginkey, e) in &outcome.failed {
+            let status = self.status.entry(pluginkey.clone()).or_default();
+            let failure = status.failure.get_or_insert_with(|| PluginFailure {
+                since: now.clone(),
+                runs: 0,
+                error: String::new(),
+            });
+            failure.runs += 1;
+            failure.error = format!("{e:#}");
+        }
+    }
+
+    /// Moves the plugins of `pluginkeys` that weren't processed successfully for longer than
+    /// `max_age` to the front, the least recently processed first. Returns how many there are.
+    pub fn prioritize_stale(
+        &self,
+        pluginkeys: &mut [String],
+        max_age: Duration,
+    ) -> anyhow::Result<usize> {
+        let cutoff = SystemTime::now() - max_age;
+        let mut stale = HashMap::new();
+        for pluginkey in pluginkeys.iter() {
+            if let Some(status) = self.status.get(pluginkey)
+                && status.stale_since(cutoff)?
+            {
+                stale.insert(pluginkey.clone(), status.last_success.clone());
+            }
+        }
+        let count = stale.len();
+        // RFC 3339 timestamps in UTC sort chronologically, and plugins that never succeeded
+        // (`None`) before all others.
+        pluginkeys.sort_by_cached_key(|pluginkey| match stale.get(pluginkey) {
+            Some(last_success) => (false, last_success.clone()),
+            None => (true, None),
+        });
+        Ok(count)
+    }
+
+    /// Logs the plugins that failed in several runs in a row.
+    pub fn log_persistent_failures(&self) {
+        let failing: Vec<_> = self
+            .status
+            .iter()
+            .filter_map(|(pluginkey, status)| {
+                let failure = status.failure.as_ref()?;
+                (failure.runs > 1).then(|| format!("{pluginkey} (since {})", failure.since))
+            })
+            .collect();
+        if !failing.is_empty() {
+            warn!(
+                "{} plugins failed in several runs in a row and keep their last known versions: {}",
+                failing.len(),
+                failing.join(", ")
+            );
+        }
+    }
+}
+
+/// Reads plugin_status.json in `out_dir`, if there is one.
+pub async fn load(out_dir: &Path) -> anyhow::Result<PluginStatuses> {
+    let path = out_dir.join(PLUGIN_STATUS_JSON);
+    if !try_exists(&path).await? {
+        return Ok(PluginStatuses::new());
+    }
+    Ok(serde_json::from_str(&read_to_string(path).await?)?)
+}
+
+/// Writes the plugin statuses of `db` to plugin_status.json in `out_dir`, or removes it if
+/// there are none.
+pub async fn save(out_dir: &Path, db: &PluginDb) -> anyhow::Result<()> {
+    let path = out_dir.join(PLUGIN_STATUS_JSON);
+    if db.status.is_empty() {
+        if try_exists(&path).await? {
+            remove_file(&path).await?;
+        }
+        return Ok(());
+    }
+    write_if_changed(&path, serde_json::to_string_pretty(&db.status)?).await?;
+    Ok(())
+}
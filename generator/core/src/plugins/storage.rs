@@ -0,0 +1,235 @@
+use super::PluginDb;
+use super::sqlite::SqliteStorage;
+use crate::events::GeneratorEvents;
+use futures::future::BoxFuture;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum DbBackend {
+    /// Persist the database as the JSON tree in the output directory.
+    #[default]
+    Json,
+    /// Persist the database to a SQLite file and export the JSON tree when saving.
+    Sqlite,
+}
+
+impl DbBackend {
+    pub fn build(self, out_dir: &Path, sqlite_path: &Path) -> anyhow::Result<Arc<dyn Storage>> {
+        Ok(match self {
+            DbBackend::Json => Arc::new(JsonStorage {
+                out_dir: out_dir.to_path_buf(),
+            }),
+            DbBackend::Sqlite => Arc::new(SqliteStorage::open(out_dir, sqlite_path)?),
+        })
+    }
+}
+
+/// Where the plugin database is persisted between runs.
+pub trait Storage: Send + Sync {
+    /// Loads the plugin entries and metadata, but not the IDE mappings.
+    fn load(&self) -> BoxFuture<'_, anyhow::Result<PluginDb>>;
+
+    /// Loads the whole database, including the IDE mappings.
+    fn load_full(&self) -> BoxFuture<'_, anyhow::Result<PluginDb>>;
+
+    /// Saves the whole database, including the JSON tree in the output directory.
+    fn save<'a>(
+        &'a self,
+        db: &'a PluginDb,
+        events: &'a dyn GeneratorEvents,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Saves the plugin entries and the IDE mappings changed since the last flush.
+    fn flush<'a>(
+        &'a self,
+        db: &'a mut PluginDb,
+        events: &'a dyn GeneratorEvents,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// The JSON tree in the output directory is the database.
+pub struct JsonStorage {
+    out_dir: PathBuf,
+}
+
+impl Storage for JsonStorage {
+    fn load(&self) -> BoxFuture<'_, anyhow::Result<PluginDb>> {
+        Box::pin(super::db_load(&self.out_dir))
+    }
+
+    fn load_full(&self) -> BoxFuture<'_, anyhow::Result<PluginDb>> {
+        Box::pin(super::db_load_full(&self.out_dir))
+    }
+
+    fn save<'a>(
+        &'a self,
+        db: &'a PluginDb,
+        events: &'a dyn GeneratorEvents,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(super::db_save(&self.out_dir, db, events))
+    }
+
+    fn flush<'a>(
+        &'a self,
+        db: &'a mut PluginDb,
+        events: &'a dyn GeneratorEvents,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(super::db_flush(&self.out_dir, db, events))
+    }
+}
+
+/// Keeps the database in memory and writes nothing, e.g. for tests or to inspect the result of
+/// [`db_update`](super::db_update) without an output directory.
+#[derive(Default)]
+pub struct MemoryStorage {
+    db: Mutex<PluginDb>,
+}
+
+impl MemoryStorage {
+    pub fn new(db: PluginDb) -> Self {
+        Self { db: Mutex::new(db) }
+    }
+
+    /// A copy of the last saved (or flushed) database.
+    pub fn snapshot(&self) -> PluginDb {
+        self.db.lock().unwrap().clone()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn load(&self) -> BoxFuture<'_, anyhow::Result<PluginDb>> {
+        let mut db = self.snapshot();
+        db.ides.clear();
+        Box::pin(async move { Ok(db) })
+    }
+
+    fn load_full(&self) -> BoxFuture<'_, anyhow::Result<PluginDb>> {
+        let db = self.snapshot();
+        Box::pin(async move { Ok(db) })
+    }
+
+    fn save<'a>(
+        &'a self,
+        db: &'a PluginDb,
+        _events: &'a dyn GeneratorEvents,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        *self.db.lock().unwrap() = db.clone();
+        Box::pin(async { Ok(()) })
+    }
+
+    fn flush<'a>(
+        &'a self,
+        db: &'a mut PluginDb,
+        _events: &'a dyn GeneratorEvents,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        db.dirty_ides.clear();
+        *self.db.lock().unwrap() = db.clone();
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ides::{IdeProduct, IdeVersion};
+    use crate::plugins::{ArtifactKind, Channel, PluginDbEntry, PluginVersion};
+    use std::collections::BTreeMap;
+
+    struct Quiet;
+    impl GeneratorEvents for Quiet {}
+
+    fn ide(version: &str, build_number: &str) -> IdeVersion {
+        IdeVersion {
+            ide: IdeProduct::IntelliJIdea,
+            version: version.to_string(),
+            build_number: build_number.to_string(),
+        }
+    }
+
+    fn entry(path: &str) -> PluginDbEntry {
+        PluginDbEntry {
+            path: path.to_string(),
+            hash: "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string(),
+            dependencies: Vec::new(),
+            kind: ArtifactKind::from_path(path),
+            unpackable: true,
+            size: Some(885),
+            etag: None,
+            mirror: None,
+            channel: None,
+        }
+    }
+
+    fn example_db() -> PluginDb {
+        let mut db = PluginDb::new();
+        db.insert(
+            &ide("2025.1", "251.1"),
+            Channel::Stable,
+            "com.example.fixture",
+            "1.0.0",
+            entry("1/1/fixture-1.0.0.zip"),
+        );
+        db.insert(
+            &ide("2025.2", "252.1"),
+            Channel::Stable,
+            "com.example.fixture",
+            "1.1.0",
+            entry("1/2/fixture-1.1.0.jar"),
+        );
+        db
+    }
+
+    /// The IDE mappings by IDE version label, as build numbers aren't stored in the JSON tree.
+    fn mappings(db: &PluginDb) -> BTreeMap<String, Vec<PluginVersion>> {
+        db.ides
+            .iter()
+            .map(|(ide, mapping)| {
+                let versions = mapping
+                    .plugins
+                    .iter()
+                    .flat_map(|(name, channels)| {
+                        channels
+                            .versions()
+                            .map(|version| PluginVersion::new(name, version))
+                    })
+                    .collect();
+                (ide.version.clone(), versions)
+            })
+            .collect()
+    }
+
+    // Saving the JSON tree uses block_in_place.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn memory_storage_round_trips_like_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = JsonStorage {
+            out_dir: dir.path().to_path_buf(),
+        };
+        let memory = MemoryStorage::default();
+        let db = example_db();
+        for storage in [&json as &dyn Storage, &memory] {
+            storage.save(&db, &Quiet).await.unwrap();
+
+            let loaded = storage.load().await.unwrap();
+            assert_eq!(loaded.all_plugins, db.all_plugins);
+            assert!(loaded.ides.is_empty());
+
+            let loaded = storage.load_full().await.unwrap();
+            assert_eq!(loaded.all_plugins, db.all_plugins);
+            assert_eq!(mappings(&loaded), mappings(&db));
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_storage_flush_keeps_the_changes() {
+        let memory = MemoryStorage::default();
+        let mut db = example_db();
+        memory.flush(&mut db, &Quiet).await.unwrap();
+        assert!(db.dirty_ides.is_empty());
+        let loaded = memory.load_full().await.unwrap();
+        assert_eq!(loaded.all_plugins, db.all_plugins);
+        assert_eq!(mappings(&loaded), mappings(&db));
+    }
+}
@@ -0,0 +1,170 @@
+//! Records of plugin versions that disappeared from the marketplace, in tombstones.json, so
+//! that users can tell why a plugin is missing.
+
+use super::{PluginDb, PluginVersion};
+use crate::fs::write_if_changed;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::fs::{read_to_string, remove_file, try_exists};
+
+const TOMBSTONES_JSON: &str = "tombstones.json";
+
+pub type Tombstones = BTreeMap<PluginVersion, Tombstone>;
+
+/// Why and since when a plugin version is gone.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tombstone {
+    /// RFC 3339, when the version was first noticed missing.
+    pub removed_at: String,
+    pub reason: TombstoneReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TombstoneReason {
+    /// The download of the version returns 404. Its stored hash is kept, if it has one.
+    Unavailable,
+    /// The plugin is no longer in the marketplace indices, so it is no longer in the IDE
+    /// mappings.
+    Delisted,
+}
+
+impl Display for TombstoneReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TombstoneReason::Unavailable => write!(f, "download no longer available"),
+            TombstoneReason::Delisted => write!(f, "removed from the marketplace"),
+        }
+    }
+}
+
+impl PluginDb {
+    /// Marks `key` as gone, unless it already is. Versions without an entry are marked too,
+    /// e.g. new versions whose download is missing. Returns whether a tombstone was added.
+    pub fn tombstone(&mut self, key: &PluginVersion, reason: TombstoneReason) -> bool {
+        if self.tombstones.contains_key(key) {
+            return false;
+        }
+        self.tombstones.insert(
+            key.clone(),
+            Tombstone {
+                removed_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                reason,
+            },
+        );
+        true
+    }
+
+    /// Removes the tombstone of `key`, if it is back.
+    pub fn revive(&mut self, key: &PluginVersion) {
+        if self.tombstones.remove(key).is_some() {
+            info!("{key}: available again, removing its tombstone.");
+        }
+    }
+
+    pub fn tombstones(&self) -> &Tombstones {
+        &self.tombstones
+    }
+
+    /// Tombstones the versions of all plugins that are not in `indexed` anymore, and revives
+    /// delisted versions of plugins that are listed again.
+    pub fn mark_delisted(&mut self, indexed: &HashSet<String>) {
+        let delisted: Vec<_> = self
+            .all_plugins
+            .keys()
+            .filter(|key| !indexed.contains(&key.name))
+            .cloned()
+            .collect();
+        let mut plugins = BTreeSet::new();
+        for key in &delisted {
+            if self.tombstone(key, TombstoneReason::Delisted) {
+                plugins.insert(key.name.as_str());
+            }
+        }
+        if !plugins.is_empty() {
+            warn!(
+                "{} plugins were removed from the marketplace, recorded tombstones: {}",
+                plugins.len(),
+                plugins.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+        let relisted: Vec<_> = self
+            .tombstones
+            .iter()
+            .filter(|(key, tombstone)| {
+                tombstone.reason == TombstoneReason::Delisted && indexed.contains(&key.name)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in relisted {
+            self.revive(&key);
+        }
+    }
+
+    /// Removes the tombstones older than `grace` with their entries, and the versions of the
+    /// IDE mappings that reference them. Returns the purged plugin versions.
+    pub fn purge_tombstones(&mut self, grace: Duration) -> anyhow::Result<BTreeSet<PluginVersion>> {
+        let now = SystemTime::now();
+        let mut expired = BTreeSet::new();
+        for (key, tombstone) in &self.tombstones {
+            let removed_at = humantime::parse_rfc3339(&tombstone.removed_at)?;
+            if now.duration_since(removed_at).unwrap_or_default() > grace {
+                expired.insert(key.clone());
+            }
+        }
+        for (ide, mapping) in &mut self.ides {
+            let before = mapping.plugins.len();
+            let mut changed = false;
+            mapping.plugins.retain(|name, channels| {
+                for version in [&mut channels.stable, &mut channels.eap] {
+                    if version
+                        .as_ref()
+                        .is_some_and(|v| expired.contains(&PluginVersion::new(name, v)))
+                    {
+                        *version = None;
+                        changed = true;
+                    }
+                }
+                channels.stable.is_some() || channels.eap.is_some()
+            });
+            if changed || mapping.plugins.len() != before {
+                mapping.generated_at = None;
+                self.dirty_ides.insert(ide.clone());
+            }
+        }
+        self.all_plugins.retain(|key, _| !expired.contains(key));
+        self.changed_plugins
+            .extend(expired.iter().map(|key| key.name.clone()));
+        self.tombstones.retain(|key, _| !expired.contains(key));
+        Ok(expired)
+    }
+}
+
+/// Reads tombstones.json in `out_dir`, if there is one.
+pub async fn load(out_dir: &Path) -> anyhow::Result<Tombstones> {
+    let path = out_dir.join(TOMBSTONES_JSON);
+    if !try_exists(&path).await? {
+        return Ok(Tombstones::new());
+    }
+    Ok(serde_json::from_str(&read_to_string(path).await?)?)
+}
+
+/// Writes the tombstones of `db` to tombstones.json in `out_dir`, or removes it if there are
+/// none. Tombstones are kept after their entry is cleaned up, until they are purged.
+pub async fn save(out_dir: &Path, db: &PluginDb) -> anyhow::Result<()> {
+    let path = out_dir.join(TOMBSTONES_JSON);
+    let tombstones = &db.tombstones;
+    if tombstones.is_empty() {
+        if try_exists(&path).await? {
+            remove_file(&path).await?;
+        }
+        return Ok(());
+    }
+    write_if_changed(&path, serde_json::to_string_pretty(tombstones)?).await?;
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Entries that no IDE mapping references anymore, in unreferenced.json, so that cleanup can
+//! keep them for a grace period instead of removing them (and their hashes) right away.
+
+use super::{PluginDb, PluginVersion};
+use crate::fs::write_if_changed;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::fs::{read_to_string, remove_file, try_exists};
+
+const UNREFERENCED_JSON: &str = "unreferenced.json";
+
+pub type Unreferenced = BTreeMap<PluginVersion, UnreferencedSince>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnreferencedSince {
+    /// RFC 3339, the first cleanup that found the entry unreferenced.
+    pub last_referenced: String,
+    /// Number of cleanups in a row that found it unreferenced.
+    pub runs: u32,
+}
+
+/// How long cleanup keeps unreferenced entries. Without any limit, they are removed right away.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Grace {
+    pub period: Option<Duration>,
+    pub runs: Option<u32>,
+}
+
+impl Grace {
+    fn is_none(&self) -> bool {
+        self.period.is_none() && self.runs.is_none()
+    }
+
+    /// Whether `since` is past both limits.
+    fn expired(&self, since: &UnreferencedSince, now: SystemTime) -> anyhow::Result<bool> {
+        if let Some(period) = self.period {
+            let last_referenced = humantime::parse_rfc3339(&since.last_referenced)?;
+            if now.duration_since(last_referenced).unwrap_or_default() < period {
+                return Ok(false);
+            }
+        }
+        Ok(self.runs.is_none_or(|runs| since.runs >= runs))
+    }
+}
+
+impl PluginDb {
+    /// Marks the entries of `unused` as unreferenced (or counts another run for those that
+    /// already are) and forgets the marks of entries that are referenced again. Returns the
+    /// entries whose grace period is over.
+    pub(super) fn mark_unreferenced(
+        &mut self,
+        unused: &HashSet<PluginVersion>,
+        grace: Grace,
+    ) -> anyhow::Result<HashSet<PluginVersion>> {
+        self.unreferenced.retain(|key, _| unused.contains(key));
+        if grace.is_none() {
+            return Ok(unused.clone());
+        }
+        let now = SystemTime::now();
+        let last_referenced = humantime::format_rfc3339_seconds(now).to_string();
+        let mut expired = HashSet::new();
+        for key in unused {
+            let since = self
+                .unreferenced
+                .entry(key.clone())
+                .or_insert_with(|| UnreferencedSince {
+                    last_referenced: last_referenced.clone(),
+                    runs: 0,
+                });
+            since.runs += 1;
+            if grace.expired(since, now)? {
+                expired.insert(key.clone());
+            }
+        }
+        self.unreferenced.retain(|key, _| !expired.contains(key));
+        Ok(expired)
+    }
+
+    pub fn unreferenced(&self) -> &Unreferenced {
+        &self.unreferenced
+    }
+}
+
+/// Reads unreferenced.json in `out_dir`, if there is one.
+pub async fn load(out_dir: &Path) -> anyhow::Result<Unreferenced> {
+    let path = out_dir.join(UNREFERENCED_JSON);
+    if !try_exists(&path).await? {
+        return Ok(Unreferenced::new());
+    }
+    Ok(serde_json::from_str(&read_to_string(path).await?)?)
+}
+
+/// Writes the unreferenced entries of `db` to unreferenced.json in `out_dir`, or removes it if
+/// there are none.
+pub async fn save(out_dir: &Path, db: &PluginDb) -> anyhow::Result<()> {
+    let path = out_dir.join(UNREFERENCED_JSON);
+    if db.unreferenced.is_empty() {
+        if try_exists(&path).await? {
+            remove_file(&path).await?;
+        }
+        return Ok(());
+    }
+    write_if_changed(&path, serde_json::to_string_pretty(&db.unreferenced)?).await?;
+    Ok(())
+}
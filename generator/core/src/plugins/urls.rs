@@ -0,0 +1,116 @@
+use anyhow::anyhow;
+
+/// Prefix of the marketplace download URLs, which is not stored in the database. Must match
+/// `findPlugin` in plugins.nix.
+pub const MARKETPLACE_DOWNLOADS: &str = "https://downloads.marketplace.jetbrains.com/";
+
+/// Which artifact URLs are accepted, and where they are actually downloaded from.
+pub struct DownloadUrls {
+    /// Allowed prefixes of resolved marketplace download URLs. URLs starting with
+    /// [`MARKETPLACE_DOWNLOADS`] are stored without it, others are stored in full.
+    pub prefixes: Vec<String>,
+    /// `(from, to)`: URLs starting with `from` are downloaded from `to` + rest instead, e.g. an
+    /// internal mirror. The first matching rule applies.
+    pub rewrites: Vec<(String, String)>,
+}
+
+impl DownloadUrls {
+    /// The path stored in the database for the artifact at `url`.
+    pub fn store_path(&self, url: &str) -> anyhow::Result<String> {
+        if !self.prefixes.iter().any(|prefix| url.starts_with(prefix)) {
+            return Err(anyhow!(
+                "download URL {url} doesn't start with an allowed prefix"
+            ));
+        }
+        Ok(url
+            .strip_prefix(MARKETPLACE_DOWNLOADS)
+            .unwrap_or(url)
+            .to_string())
+    }
+
+    /// The URL to download the artifact at `url` from.
+    pub fn rewrite(&self, url: &str) -> String {
+        self.rewrites
+            .iter()
+            .find_map(|(from, to)| Some(format!("{to}{}", url.strip_prefix(from.as_str())?)))
+            .unwrap_or_else(|| url.to_string())
+    }
+}
+
+/// Parses a `FROM=TO` rewrite rule.
+pub fn parse_rewrite(rule: &str) -> Result<(String, String), String> {
+    match rule.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok((from.to_string(), to.to_string())),
+        _ => Err(format!("expected FROM=TO, got {rule:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls() -> DownloadUrls {
+        DownloadUrls {
+            prefixes: vec![
+                MARKETPLACE_DOWNLOADS.to_string(),
+                "https://plugins.example.com/".to_string(),
+            ],
+            rewrites: vec![
+                (
+                    MARKETPLACE_DOWNLOADS.to_string(),
+                    "http://mirror/jb/".to_string(),
+                ),
+                ("https://".to_string(), "http://mirror/".to_string()),
+            ],
+        }
+    }
+
+    #[test]
+    fn store_path() {
+        let urls = urls();
+        assert_eq!(
+            urls.store_path("https://downloads.marketplace.jetbrains.com/files/1/a.zip")
+                .unwrap(),
+            "files/1/a.zip"
+        );
+        assert_eq!(
+            urls.store_path("https://plugins.example.com/a.zip")
+                .unwrap(),
+            "https://plugins.example.com/a.zip"
+        );
+        assert!(urls.store_path("https://evil.example.com/a.zip").is_err());
+    }
+
+    #[test]
+    fn first_rewrite_wins() {
+        let urls = urls();
+        assert_eq!(
+            urls.rewrite("https://downloads.marketplace.jetbrains.com/files/1/a.zip"),
+            "http://mirror/jb/files/1/a.zip"
+        );
+        assert_eq!(
+            urls.rewrite("https://plugins.example.com/a.zip"),
+            "http://mirror/plugins.example.com/a.zip"
+        );
+        assert_eq!(urls.rewrite("file:///a.zip"), "file:///a.zip");
+    }
+
+    #[test]
+    fn parse_rewrite_rules() {
+        assert_eq!(
+            parse_rewrite("https://a/=http://b/"),
+            Ok(("https://a/".to_string(), "http://b/".to_string()))
+        );
+        // Only the first `=` separates, TO may contain more.
+        assert_eq!(
+            parse_rewrite("https://a/=http://b/?x=1"),
+            Ok(("https://a/".to_string(), "http://b/?x=1".to_string()))
+        );
+        assert_eq!(
+            parse_rewrite("https://a/="),
+            Ok(("https://a/".to_string(), String::new()))
+        );
+        assert!(parse_rewrite("=http://b/").is_err());
+        assert!(parse_rewrite("https://a/").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::info;
+use std::io::{IsTerminal, stderr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often to log a progress line when stderr is not a terminal.
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The progress bar drawn by a run, if any, shared with the logger so that log records don't
+/// garble it. Cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct ActiveBar(Arc<Mutex<Option<ProgressBar>>>);
+
+impl ActiveBar {
+    /// Runs `f` (which writes to stderr) with the active progress bar hidden.
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        let bar = self.0.lock().unwrap().clone();
+        match bar {
+            Some(bar) => bar.suspend(f),
+            None => f(),
+        }
+    }
+
+    fn set(&self, bar: Option<ProgressBar>) {
+        *self.0.lock().unwrap() = bar;
+    }
+}
+
+/// Tracks processed and failed plugins of a [`crate::plugins::db_update`] run.
+/// Draws a progress bar on a terminal, otherwise logs a progress line every [`REPORT_INTERVAL`].
+pub struct Progress {
+    bar: Option<ProgressBar>,
+    active_bar: ActiveBar,
+    total: u64,
+    processed: AtomicU64,
+    failed: AtomicU64,
+    started: Instant,
+    last_report: Mutex<Instant>,
+}
+
+impl Progress {
+    /// Registers the progress bar, if drawn, with `active_bar` until it is finished.
+    pub fn new(total: u64, active_bar: &ActiveBar) -> Self {
+        let bar = stderr().is_terminal().then(|| {
+            let bar = ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::stderr())
+                .with_style(
+                    ProgressStyle::with_template(
+                        "[{elapsed_precise}] {wide_bar} {pos}/{len} plugins, {msg}, ETA {eta}",
+                    )
+                    .expect("valid progress template"),
+                );
+            bar.set_message("0 failed");
+            active_bar.set(Some(bar.clone()));
+            bar
+        });
+        let now = Instant::now();
+        Self {
+            bar,
+            active_bar: active_bar.clone(),
+            total,
+            processed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            started: now,
+            last_report: Mutex::new(now),
+        }
+    }
+
+    pub fn inc(&self, failed: bool) {
+        let processed = self.processed.fetch_add(1, Ordering::Relaxed) + 1;
+        let failed = if failed {
+            self.failed.fetch_add(1, Ordering::Relaxed) + 1
+        } else {
+            self.failed.load(Ordering::Relaxed)
+        };
+
+        match &self.bar {
+            Some(bar) => {
+                bar.set_message(format!("{failed} failed"));
+                bar.inc(1);
+            }
+            None => {
+                let mut last_report = self.last_report.lock().unwrap();
+                if last_report.elapsed() >= REPORT_INTERVAL {
+                    *last_report = Instant::now();
+                    drop(last_report);
+                    self.report(processed, failed);
+                }
+            }
+        }
+    }
+
+    fn report(&self, processed: u64, failed: u64) {
+        let elapsed = self.started.elapsed();
+        let remaining = self.total.saturating_sub(processed);
+        let eta = elapsed.mul_f64(remaining as f64 / processed.max(1) as f64);
+        info!(
+            "progress: {processed}/{} plugins ({:.1}%), {failed} failed, elapsed {}, ETA {}",
+            self.total,
+            processed as f64 * 100.0 / self.total.max(1) as f64,
+            format_duration(elapsed),
+            format_duration(eta),
+        );
+    }
+
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+            self.active_bar.set(None);
+        }
+        self.report(
+            self.processed.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+        );
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.bar.is_some() {
+            self.active_bar.set(None);
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,293 @@
+use crate::http_client::HttpContext;
+use log::warn;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::time::{Instant, sleep};
+
+/// How often a request is retried after HTTP 429 (or 503 with `Retry-After`) before the
+/// response is returned as is.
+const MAX_THROTTLED_RETRIES: usize = 5;
+/// How long to back off after HTTP 429 without a (valid) `Retry-After` header.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+/// Upper bound for `Retry-After`, so a misbehaving server can't stall the run forever.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+/// Waits until `bytes` more of an artifact download may be received.
+pub async fn throttle_download(http: &HttpContext, bytes: usize) {
+    if let Some(limiter) = &http.bandwidth {
+        limiter.acquire(bytes as f64).await;
+    }
+}
+
+/// Waits until the next marketplace request may be made.
+pub async fn acquire(http: &HttpContext) {
+    http.run.metrics().requests.inc();
+    if let Some(limiter) = &http.requests {
+        limiter.acquire(1.0).await;
+    }
+}
+
+/// Sends a marketplace request, subject to the rate limit and authorized with the marketplace
+/// token. Throttled (HTTP 429) and unavailable (HTTP 503 with `Retry-After`) requests are
+/// retried after exactly the time the server asks for, during which all other requests wait as
+/// well. 503 without `Retry-After` is left to the retries of the caller.
+pub async fn send(http: &HttpContext, request: RequestBuilder) -> reqwest::Result<Response> {
+    let (client, request) = request.build_split();
+    let mut request = request?;
+    http.authorize(&mut request);
+    let mut attempt = 0;
+    loop {
+        acquire(http).await;
+        // Only requests without a streaming body can be retried, which are all of ours.
+        let Some(retry) = request
+            .try_clone()
+            .filter(|_| attempt < MAX_THROTTLED_RETRIES)
+        else {
+            return client
+                .execute(request)
+                .await
+                .inspect(|response| count_status(http, response));
+        };
+        let response = client.execute(retry).await?;
+        count_status(http, &response);
+        let Some(retry_after) = backoff(response.status(), retry_after(&response)) else {
+            return Ok(response);
+        };
+        warn!(
+            "{}: {}, pausing requests for {}s",
+            response.url(),
+            response.status(),
+            retry_after.as_secs_f64()
+        );
+        if let Some(limiter) = &http.requests {
+            limiter.pause(retry_after);
+        } else {
+            sleep(retry_after).await;
+        }
+        attempt += 1;
+    }
+}
+
+fn count_status(http: &HttpContext, response: &Response) {
+    let metrics = http.run.metrics();
+    match response.status() {
+        StatusCode::NOT_FOUND => metrics.not_found.inc(),
+        StatusCode::TOO_MANY_REQUESTS => metrics.throttled.inc(),
+        _ => {}
+    }
+}
+
+/// How long to back off before retrying a response with `status` and `retry_after`, `None` if
+/// it isn't retried here.
+fn backoff(status: StatusCode, retry_after: Option<Duration>) -> Option<Duration> {
+    let backoff = match (status, retry_after) {
+        (StatusCode::TOO_MANY_REQUESTS, retry_after) => retry_after.unwrap_or(DEFAULT_RETRY_AFTER),
+        (StatusCode::SERVICE_UNAVAILABLE, Some(retry_after)) => retry_after,
+        _ => return None,
+    };
+    Some(backoff.min(MAX_RETRY_AFTER))
+}
+
+/// The `Retry-After` header of `response`.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, SystemTime::now())
+}
+
+/// Parses a `Retry-After` value, either in seconds or as an HTTP date (relative to `now`).
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// Token bucket rate limiter, of requests or bytes.
+pub(crate) struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    state: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    paused_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate,
+            burst,
+            state: Mutex::new(Bucket {
+                tokens: burst,
+                refilled: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Takes `cost` tokens. Costs above the available tokens are taken as soon as there is
+    /// one, and the debt delays the following callers.
+    async fn acquire(&self, cost: f64) {
+        loop {
+            let wait = {
+                let mut bucket = self.state.lock().unwrap();
+                let now = Instant::now();
+                match bucket.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ if self.rate <= 0.0 => return,
+                    _ => {
+                        let elapsed = now - bucket.refilled;
+                        bucket.tokens =
+                            (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+                        bucket.refilled = now;
+                        if bucket.tokens >= 1.0 {
+                            bucket.tokens -= cost;
+                            return;
+                        }
+                        Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+                    }
+                }
+            };
+            sleep(wait).await;
+        }
+    }
+
+    /// Holds back all requests for `duration`.
+    fn pause(&self, duration: Duration) {
+        let mut bucket = self.state.lock().unwrap();
+        let until = Instant::now() + duration;
+        bucket.paused_until = Some(
+            bucket
+                .paused_until
+                .map_or(until, |paused| paused.max(until)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::UNIX_EPOCH;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn retry_after_in_seconds() {
+        let now = SystemTime::now();
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("-1", now), None);
+        assert_eq!(parse_retry_after("1.5", now), None);
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn retry_after_as_http_date() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_750_000_000);
+        let later = httpdate::fmt_http_date(now + Duration::from_secs(90));
+        assert_eq!(
+            parse_retry_after(&later, now),
+            Some(Duration::from_secs(90))
+        );
+        let earlier = httpdate::fmt_http_date(now - Duration::from_secs(90));
+        assert_eq!(parse_retry_after(&earlier, now), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn backoff_defaults_and_cap() {
+        let secs = Duration::from_secs;
+        assert_eq!(
+            backoff(StatusCode::TOO_MANY_REQUESTS, None),
+            Some(DEFAULT_RETRY_AFTER)
+        );
+        assert_eq!(
+            backoff(StatusCode::TOO_MANY_REQUESTS, Some(secs(5))),
+            Some(secs(5))
+        );
+        assert_eq!(
+            backoff(StatusCode::TOO_MANY_REQUESTS, Some(secs(3600))),
+            Some(MAX_RETRY_AFTER)
+        );
+        assert_eq!(
+            backoff(StatusCode::SERVICE_UNAVAILABLE, Some(secs(10))),
+            Some(secs(10))
+        );
+        assert_eq!(backoff(StatusCode::SERVICE_UNAVAILABLE, None), None);
+        assert_eq!(backoff(StatusCode::OK, Some(secs(10))), None);
+    }
+
+    #[tokio::test]
+    async fn throttled_requests_are_retried_five_times() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                counted.fetch_add(1, Ordering::SeqCst);
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream
+                    .write_all(
+                        b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\n\
+                          Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .await;
+            }
+        });
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let response = send(&HttpContext::default(), client.get(&url))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(requests.load(Ordering::SeqCst), MAX_THROTTLED_RETRIES + 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_is_admitted_at_once() {
+        let limiter = RateLimiter::new(4.0, 3.0);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire(1.0).await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_after_the_burst_are_spaced_by_the_rate() {
+        let limiter = RateLimiter::new(4.0, 1.0);
+        let start = Instant::now();
+        limiter.acquire(1.0).await;
+        for i in 1..=3 {
+            limiter.acquire(1.0).await;
+            assert_eq!(start.elapsed(), Duration::from_millis(250) * i);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pause_blocks_every_caller() {
+        for limiter in [RateLimiter::new(4.0, 10.0), RateLimiter::new(0.0, 0.0)] {
+            let start = Instant::now();
+            limiter.pause(Duration::from_secs(2));
+            let waited = futures::future::join_all((0..3).map(|_| async {
+                limiter.acquire(1.0).await;
+                start.elapsed()
+            }))
+            .await;
+            assert_eq!(waited, [Duration::from_secs(2); 3]);
+        }
+    }
+}
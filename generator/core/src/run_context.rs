@@ -0,0 +1,118 @@
+use crate::fs::write_atomic;
+use crate::metrics::Metrics;
+use crate::progress::ActiveBar;
+use crate::run_summary::{FeedSnapshot, RunSummary};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::fs::create_dir_all;
+use tokio_util::sync::CancellationToken;
+
+/// The state of a single run: its cancellation, progress bar and metrics, and what it records
+/// for its [`RunSummary`]. Cheap to clone. Create one per run, so that runs in the same process
+/// don't see each other's records.
+#[derive(Clone, Default)]
+pub struct RunContext(Arc<RunRecords>);
+
+#[derive(Default)]
+struct RunRecords {
+    shutdown: CancellationToken,
+    active_bar: ActiveBar,
+    metrics: Arc<Metrics>,
+    /// Where copies of the feeds are archived. Unset means they aren't.
+    feed_archive: Mutex<Option<PathBuf>>,
+    /// Bytes of responses and plugin artifacts downloaded.
+    downloaded: AtomicU64,
+    /// The upstream feeds (plugin indices and IDE version lists) used, by URL.
+    feeds: Mutex<BTreeMap<String, FeedSnapshot>>,
+    summary: Mutex<Option<RunSummary>>,
+}
+
+impl RunContext {
+    /// A run that stops processing plugins once `shutdown` is cancelled and registers its
+    /// progress bar with `active_bar`, see [`crate::logging::setup_logging`].
+    pub fn new(shutdown: CancellationToken, active_bar: ActiveBar) -> Self {
+        Self(Arc::new(RunRecords {
+            shutdown,
+            active_bar,
+            ..RunRecords::default()
+        }))
+    }
+
+    pub fn shutdown(&self) -> &CancellationToken {
+        &self.0.shutdown
+    }
+
+    pub fn active_bar(&self) -> &ActiveBar {
+        &self.0.active_bar
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.0.metrics
+    }
+
+    /// Archives a copy of every feed recorded with [`Self::record_feed`] in `dir`, named by its
+    /// hash, or stops doing so.
+    pub fn set_feed_archive(&self, dir: Option<PathBuf>) {
+        *self.0.feed_archive.lock().unwrap() = dir;
+    }
+
+    pub fn add_downloaded(&self, bytes: u64) {
+        self.0.downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn downloaded(&self) -> u64 {
+        self.0.downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Records the contents of the feed at `url` for the run summary, and archives them if
+    /// configured.
+    pub async fn record_feed(&self, url: &str, body: &str) -> anyhow::Result<()> {
+        let sha256 = format!("{:x}", Sha256::digest(body.as_bytes()));
+        let archive = self.0.feed_archive.lock().unwrap().clone();
+        let archived = match archive {
+            Some(dir) => {
+                let name = url
+                    .rsplit('/')
+                    .next()
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or("feed");
+                let path = dir.join(format!("{sha256}-{name}"));
+                if !std::fs::exists(&path)? {
+                    create_dir_all(&dir).await?;
+                    write_atomic(&path, body).await?;
+                }
+                Some(path)
+            }
+            None => None,
+        };
+        let snapshot = FeedSnapshot {
+            url: url.to_string(),
+            sha256,
+            size: body.len(),
+            archived,
+        };
+        self.0
+            .feeds
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), snapshot);
+        Ok(())
+    }
+
+    /// The feeds recorded so far, by URL.
+    pub fn feeds(&self) -> Vec<FeedSnapshot> {
+        self.0.feeds.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn set_summary(&self, summary: RunSummary) {
+        *self.0.summary.lock().unwrap() = Some(summary);
+    }
+
+    /// The summary of the run, once it got as far as processing plugins.
+    pub fn take_summary(&self) -> Option<RunSummary> {
+        self.0.summary.lock().unwrap().take()
+    }
+}
@@ -0,0 +1,319 @@
+use crate::error;
+use crate::fs::write_atomic;
+use crate::ides::UnknownProduct;
+use crate::plugins::{CleanupReport, RecoveredFile, Regressions, UpdateOutcome};
+use crate::run_context::RunContext;
+use log::warn;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+pub const RUN_SUMMARY_JSON: &str = "run_summary.json";
+/// Number of failures (and dropped plugins) listed in the GitHub job summary.
+const MAX_LISTED_FAILURES: usize = 20;
+/// Number of slowest plugins listed in the run summary.
+const MAX_SLOW_PLUGINS: usize = 20;
+
+/// An upstream feed as used in a run, see [`RunContext::record_feed`].
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedSnapshot {
+    pub(crate) url: String,
+    pub(crate) sha256: String,
+    pub(crate) size: usize,
+    /// Path of the archived copy, see [`RunContext::set_feed_archive`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) archived: Option<PathBuf>,
+}
+
+/// Machine-readable outcome of a `generate` run, written to the output directory.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummary {
+    generated_at: String,
+    /// The run was stopped by Ctrl-C before all plugins were processed.
+    interrupted: bool,
+    /// Corrupted all_plugins files that the run continued without the unreadable entries of.
+    recovered_files: Vec<RecoveredFile>,
+    plugins: PluginCounts,
+    failed_plugins: Vec<String>,
+    /// Number of plugins per IDE version (`<nix key>-<version>`) processed in this run.
+    ides: BTreeMap<String, usize>,
+    /// IDE versions that had no mapping before this run.
+    new_ides: Vec<String>,
+    /// Products in updates.xml with unknown product codes, only with `--discover-products`.
+    unknown_products: Vec<UnknownProduct>,
+    timings: Timings,
+    /// Approximate, artifacts hashed by Nix are counted by their advertised size.
+    bytes_downloaded: u64,
+    /// The upstream feeds this run was generated from.
+    feeds: Vec<FeedSnapshot>,
+    /// Plugin versions whose artifact changed upstream, with `--verify-artifacts`.
+    republished: Vec<String>,
+    /// Plugins that vanished from IDE mappings, or would have without confirmation.
+    regressions: Regressions,
+    /// The plugins that took longest to process, slowest first.
+    slowest_plugins: Vec<SlowPlugin>,
+}
+
+/// Wall-clock time spent on a plugin, including retries.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SlowPlugin {
+    plugin: String,
+    seconds: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PluginCounts {
+    /// Plugins in the indices.
+    total: usize,
+    processed: usize,
+    added: usize,
+    updated: usize,
+    /// Not processed in this run: already done before resuming or left out on interruption.
+    skipped: usize,
+    failed: usize,
+}
+
+/// Wall-clock durations of the phases of the run, in seconds.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Timings {
+    pub index: f64,
+    pub update: f64,
+    pub save: f64,
+    pub total: f64,
+}
+
+impl Timings {
+    pub fn new(index: Duration, update: Duration, save: Duration, total: Duration) -> Self {
+        Self {
+            index: index.as_secs_f64(),
+            update: update.as_secs_f64(),
+            save: save.as_secs_f64(),
+            total: total.as_secs_f64(),
+        }
+    }
+}
+
+impl RunSummary {
+    pub fn new(
+        total_plugins: usize,
+        outcome: &UpdateOutcome,
+        new_ides: Vec<String>,
+        unknown_products: Vec<UnknownProduct>,
+        recovered_files: Vec<RecoveredFile>,
+        timings: Timings,
+        run: &RunContext,
+    ) -> Self {
+        let failed = outcome.failed.len();
+        Self {
+            generated_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            interrupted: run.shutdown().is_cancelled(),
+            recovered_files,
+            plugins: PluginCounts {
+                total: total_plugins,
+                processed: outcome.processed,
+                added: outcome.added,
+                updated: outcome.updated,
+                skipped: total_plugins.saturating_sub(outcome.processed + failed),
+                failed,
+            },
+            failed_plugins: outcome
+                .failed
+                .iter()
+                .map(|(pluginkey, _)| pluginkey.clone())
+                .collect(),
+            ides: outcome.ide_plugins.clone(),
+            new_ides,
+            unknown_products,
+            timings,
+            bytes_downloaded: run.downloaded(),
+            feeds: run.feeds(),
+            republished: outcome.republished.clone(),
+            regressions: outcome.regressions.clone(),
+            slowest_plugins: slowest_plugins(outcome),
+        }
+    }
+
+    pub async fn write(&self, output_path: &Path) -> anyhow::Result<()> {
+        write_atomic(
+            &output_path.join(RUN_SUMMARY_JSON),
+            serde_json::to_string_pretty(self)?,
+        )
+        .await
+    }
+
+    /// Appends a Markdown summary to the file in `GITHUB_STEP_SUMMARY`, if set.
+    pub fn write_github_summary(&self, outcome: &UpdateOutcome) -> anyhow::Result<()> {
+        let Some(path) = env::var_os("GITHUB_STEP_SUMMARY") else {
+            warn!("GITHUB_STEP_SUMMARY is not set, not writing a job summary");
+            return Ok(());
+        };
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        std::io::Write::write_all(&mut file, self.to_markdown(outcome).as_bytes())?;
+        Ok(())
+    }
+
+    fn to_markdown(&self, outcome: &UpdateOutcome) -> String {
+        let mut md = String::new();
+        let plugins = &self.plugins;
+        md.push_str("## Plugin update\n\n");
+        if self.interrupted {
+            md.push_str("**Interrupted** before all plugins were processed.\n\n");
+        }
+        for file in &self.recovered_files {
+            _ = writeln!(
+                md,
+                "**Corrupted** `{}` was backed up to `{}`, only {} entries could be salvaged: \
+                {}\n",
+                file.path, file.backup, file.salvaged, file.error
+            );
+        }
+        md.push_str("| Plugins | Count |\n| --- | ---: |\n");
+        for (label, count) in [
+            ("Total", plugins.total),
+            ("Processed", plugins.processed),
+            ("Added", plugins.added),
+            ("Updated", plugins.updated),
+            ("Skipped", plugins.skipped),
+            ("Failed", plugins.failed),
+        ] {
+            _ = writeln!(md, "| {label} | {count} |");
+        }
+        _ = writeln!(
+            md,
+            "\n{} downloaded in {:.0}s.\n",
+            format_bytes(self.bytes_downloaded),
+            self.timings.total
+        );
+
+        md.push_str("### New IDE versions\n\n");
+        if self.new_ides.is_empty() {
+            md.push_str("None.\n\n");
+        } else {
+            md.push_str("| IDE version | Plugins |\n| --- | ---: |\n");
+            for ide in &self.new_ides {
+                let count = self.ides.get(ide).copied().unwrap_or_default();
+                _ = writeln!(md, "| {ide} | {count} |");
+            }
+            md.push('\n');
+        }
+
+        if !self.unknown_products.is_empty() {
+            md.push_str("### Unknown products\n\n| Product | Codes |\n| --- | --- |\n");
+            for product in &self.unknown_products {
+                _ = writeln!(md, "| {} | {} |", product.name, product.codes.join(", "));
+            }
+            md.push('\n');
+        }
+
+        if !self.republished.is_empty() {
+            md.push_str("### Republished artifacts\n\n");
+            for plugin in &self.republished {
+                _ = writeln!(md, "- `{plugin}`");
+            }
+            md.push('\n');
+        }
+
+        for (title, versions) in [
+            ("Dropped plugins", &self.regressions.dropped),
+            ("Kept plugins (drop not confirmed)", &self.regressions.kept),
+        ] {
+            if versions.is_empty() {
+                continue;
+            }
+            _ = writeln!(
+                md,
+                "### {title} ({} of {})\n\n| IDE version | Plugin | Version | Reason |\n\
+                | --- | --- | --- | --- |",
+                versions.len().min(MAX_LISTED_FAILURES),
+                versions.len()
+            );
+            for dropped in versions.iter().take(MAX_LISTED_FAILURES) {
+                _ = writeln!(
+                    md,
+                    "| {} | `{}` | {} | {} |",
+                    dropped.ide, dropped.plugin, dropped.version, dropped.reason
+                );
+            }
+            md.push('\n');
+        }
+
+        if !self.slowest_plugins.is_empty() {
+            md.push_str("### Slowest plugins\n\n| Plugin | Time |\n| --- | ---: |\n");
+            for slow in &self.slowest_plugins {
+                _ = writeln!(md, "| `{}` | {:.1}s |", slow.plugin, slow.seconds);
+            }
+            md.push('\n');
+        }
+
+        if !outcome.failed.is_empty() {
+            _ = writeln!(
+                md,
+                "### Failures ({} of {})\n\n| Plugin | Kind | Error |\n| --- | --- | --- |",
+                outcome.failed.len().min(MAX_LISTED_FAILURES),
+                outcome.failed.len()
+            );
+            for (pluginkey, e) in outcome.failed.iter().take(MAX_LISTED_FAILURES) {
+                let message = format!("{e:#}").replace('|', "\\|").replace('\n', " ");
+                _ = writeln!(
+                    md,
+                    "| `{pluginkey}` | {:?} | {message} |",
+                    error::classify(e)
+                );
+            }
+            md.push('\n');
+        }
+        md
+    }
+}
+
+/// The [`MAX_SLOW_PLUGINS`] plugins of `outcome` that took longest, ties by ID.
+fn slowest_plugins(outcome: &UpdateOutcome) -> Vec<SlowPlugin> {
+    let mut durations: Vec<_> = outcome.durations.iter().collect();
+    durations.sort_by(|(a, a_time), (b, b_time)| b_time.cmp(a_time).then_with(|| a.cmp(b)));
+    durations
+        .into_iter()
+        .take(MAX_SLOW_PLUGINS)
+        .map(|(pluginkey, duration)| SlowPlugin {
+            plugin: pluginkey.clone(),
+            seconds: duration.as_secs_f64(),
+        })
+        .collect()
+}
+
+/// Adds the report of a `cleanup` run to the run summary in `output_path` as `cleanup`, or
+/// writes one with only that if there is none yet.
+pub async fn record_cleanup(output_path: &Path, report: &CleanupReport) -> anyhow::Result<()> {
+    let path = output_path.join(RUN_SUMMARY_JSON);
+    let mut summary = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => serde_json::from_str(&contents)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => serde_json::json!({}),
+        Err(e) => return Err(e.into()),
+    };
+    let summary = summary
+        .as_object_mut()
+        .ok_or_else(|| anyhow::anyhow!("{} is not an object", path.display()))?;
+    summary.insert("cleanup".to_string(), serde_json::to_value(report)?);
+    write_atomic(&path, serde_json::to_string_pretty(summary)?).await
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
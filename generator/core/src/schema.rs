@@ -0,0 +1,113 @@
+//! Checks the JSON files in the output directory against the JSON Schemas in `core/schemas`,
+//! which are also the documentation of their format for consumers.
+
+use crate::plugins::{self, PluginDb};
+use anyhow::anyhow;
+use jsonschema::Validator;
+use serde_json::Value;
+use std::fs::exists;
+use std::path::{Path, PathBuf};
+use tokio::fs::{read_dir, read_to_string};
+
+pub const ALL_PLUGINS_SCHEMA: &str = include_str!("../schemas/all_plugins.schema.json");
+pub const IDE_SCHEMA: &str = include_str!("../schemas/ide.schema.json");
+pub const INDEX_SCHEMA: &str = include_str!("../schemas/index.schema.json");
+pub const PLUGINS_META_SCHEMA: &str = include_str!("../schemas/plugins_meta.schema.json");
+pub const ALIASES_SCHEMA: &str = include_str!("../schemas/aliases.schema.json");
+
+/// Validates all_plugins.json (or its shards), the IDE mappings, index.json,
+/// plugins_meta.json and aliases.json in `out_dir`, where present. Returns the violations as
+/// `<file>: <JSON pointer>: <error>`, empty if the output is valid.
+pub async fn validate_output(out_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut violations = Vec::new();
+    let all_plugins = validator(ALL_PLUGINS_SCHEMA)?;
+    validate_file(
+        &all_plugins,
+        &out_dir.join("all_plugins.json"),
+        &mut violations,
+    )
+    .await?;
+    for shard in json_files(&out_dir.join("all_plugins")).await? {
+        validate_file(&all_plugins, &shard, &mut violations).await?;
+    }
+    let ide = validator(IDE_SCHEMA)?;
+    for mapping in json_files(&out_dir.join("ides")).await? {
+        validate_file(&ide, &mapping, &mut violations).await?;
+    }
+    for (schema, file) in [
+        (INDEX_SCHEMA, "index.json"),
+        (PLUGINS_META_SCHEMA, "plugins_meta.json"),
+        (ALIASES_SCHEMA, "aliases.json"),
+    ] {
+        validate_file(&validator(schema)?, &out_dir.join(file), &mut violations).await?;
+    }
+    Ok(violations)
+}
+
+/// Like [`validate_output`] for the files that saving `db` writes, before they are written: they
+/// are written to a temporary directory and validated there. Paths in the violations are
+/// relative to the output directory.
+pub async fn validate_db(db: &PluginDb) -> anyhow::Result<Vec<String>> {
+    let dir = tempfile::tempdir()?;
+    plugins::write_json_files(dir.path(), db).await?;
+    let prefix = format!("{}/", dir.path().display());
+    Ok(validate_output(dir.path())
+        .await?
+        .into_iter()
+        .map(|violation| match violation.strip_prefix(&prefix) {
+            Some(relative) => relative.to_string(),
+            None => violation,
+        })
+        .collect())
+}
+
+fn validator(schema: &str) -> anyhow::Result<Validator> {
+    jsonschema::validator_for(&serde_json::from_str(schema)?)
+        .map_err(|e| anyhow!("invalid JSON Schema: {e}"))
+}
+
+/// The `.json` files in `dir`, sorted. None if `dir` doesn't exist.
+async fn json_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !exists(dir)? {
+        return Ok(files);
+    }
+    let mut entries = read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension() == Some("json".as_ref()) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Adds the violations of `path` to `violations`. Missing files are skipped.
+async fn validate_file(
+    validator: &Validator,
+    path: &Path,
+    violations: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    if !exists(path)? {
+        return Ok(());
+    }
+    let instance: Value = match serde_json::from_str(&read_to_string(path).await?) {
+        Ok(instance) => instance,
+        Err(e) => {
+            violations.push(format!("{}: not valid JSON: {e}", path.display()));
+            return Ok(());
+        }
+    };
+    violations.extend(validator.iter_errors(&instance).map(|error| {
+        format!(
+            "{}: {}: {error}",
+            path.display(),
+            match error.instance_path().as_str() {
+                "" => "/",
+                pointer => pointer,
+            }
+        )
+    }));
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use std::cmp::Ordering;
+
+/// One component of a version string, in ascending order of precedence.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Token<'a> {
+    /// Any word, ranked by [`qualifier_rank`], then compared case-insensitively.
+    Qualifier(u8, String),
+    /// A run of digits. Compared by value, also for numbers too large for an integer type.
+    Number(Number<'a>),
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Number<'a> {
+    // Declaration order matters: fewer digits (after stripping leading zeros) is smaller.
+    len: usize,
+    digits: &'a str,
+}
+
+const ZERO: Token = Token::Number(Number { len: 0, digits: "" });
+
+/// Qualifiers that mark the release itself, ignored so that `1.0.RELEASE` = `1.0`.
+const RELEASE_QUALIFIERS: [&str; 3] = ["release", "final", "ga"];
+
+/// Rank of qualifiers like `beta` in `1.0.0-beta2`. Every qualifier sorts before the release
+/// itself (so `1.0-rc1` < `1.0` = `1.0.0` < `1.0.1`), known pre-release stages in their usual order.
+fn qualifier_rank(word: &str) -> u8 {
+    match word {
+        "snapshot" | "dev" | "nightly" => 0,
+        "alpha" | "a" => 1,
+        "beta" | "b" => 2,
+        "m" | "milestone" | "eap" | "pre" | "preview" => 3,
+        "rc" | "cr" => 4,
+        _ => 5,
+    }
+}
+
+fn tokenize(version: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = version;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphanumeric()) {
+        rest = &rest[start..];
+        let is_digit = rest.starts_with(|c: char| c.is_ascii_digit());
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() || c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (token, tail) = rest.split_at(end);
+        tokens.push(if is_digit {
+            let digits = token.trim_start_matches('0');
+            Token::Number(Number {
+                len: digits.len(),
+                digits,
+            })
+        } else {
+            let word = token.to_ascii_lowercase();
+            if RELEASE_QUALIFIERS.contains(&word.as_str()) {
+                rest = tail;
+                continue;
+            }
+            Token::Qualifier(qualifier_rank(&word), word)
+        });
+        rest = tail;
+    }
+    tokens
+}
+
+/// Total order for plugin version strings such as `2024.1.3`, `241.15989.49` or `1.0.0-beta2`.
+///
+/// Versions are split into numeric and alphabetic components, ignoring separators, and compared
+/// component-wise with missing components counting as `0`. Versions that compare equal this way
+/// (e.g. `1.0` and `1.0.0`) are ordered by their raw string to keep the order total.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (ta, tb) = (tokenize(a), tokenize(b));
+    let len = ta.len().max(tb.len());
+    (0..len)
+        .map(|i| ta.get(i).unwrap_or(&ZERO).cmp(tb.get(i).unwrap_or(&ZERO)))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ascending(versions: &[&str]) {
+        for pair in versions.windows(2) {
+            assert_eq!(
+                compare_versions(pair[0], pair[1]),
+                Ordering::Less,
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+            assert_eq!(compare_versions(pair[1], pair[0]), Ordering::Greater);
+        }
+    }
+
+    #[test]
+    fn calendar_versions() {
+        assert_ascending(&["2023.3.8", "2024.1", "2024.1.3", "2024.1.10", "2024.2"]);
+    }
+
+    #[test]
+    fn build_number_versions() {
+        assert_ascending(&["241.14494.240", "241.15989.49", "241.15989.150", "242.1"]);
+    }
+
+    #[test]
+    fn pre_releases_before_release() {
+        assert_ascending(&[
+            "1.0.0-SNAPSHOT",
+            "1.0.0-alpha1",
+            "1.0.0-beta1",
+            "1.0.0-beta2",
+            "1.0.0-beta10",
+            "1.0.0-eap",
+            "1.0.0-rc1",
+            "1.0.0",
+            "1.0.1",
+        ]);
+        assert_ascending(&["1.0.0-beta2", "1.0.0-foo", "1.0.0"]);
+    }
+
+    #[test]
+    fn release_qualifiers_equal_release() {
+        for version in ["1.0.RELEASE", "1.0.final", "1.0-GA", "1.0.0.Final"] {
+            assert_ascending(&["1.0-rc1", version, "1.0.1"]);
+            let cmp = compare_versions(version, "1.0");
+            // Only the raw string tie-break tells them apart.
+            assert_eq!(cmp, version.cmp("1.0"), "{version}");
+        }
+    }
+
+    #[test]
+    fn trailing_zeros_and_large_numbers() {
+        assert_eq!(compare_versions("1.0", "1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.0", "1.0.0"), Ordering::Less);
+        assert_eq!(compare_versions("1.01", "1.1"), Ordering::Less);
+        assert_ascending(&["1.99999999999999999999", "1.100000000000000000000"]);
+    }
+}
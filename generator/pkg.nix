@@ -4,6 +4,8 @@
   rustc,
   pkg-config,
   openssl,
+  # Git revision recorded in the generated files.
+  rev ? null,
 }:
 rustPlatform.buildRustPackage {
   pname = "nix-jebrains-plugins-generator";
@@ -26,6 +28,8 @@ rustPlatform.buildRustPackage {
     openssl
   ];
 
+  env = if rev == null then { } else { GENERATOR_GIT_REV = rev; };
+
   meta = {
     mainProgram = "nix-jebrains-plugins-generator";
   };
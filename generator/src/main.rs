@@ -1,16 +1,88 @@
-mod ides;
-mod logging;
-mod plugins;
-
+use anyhow::anyhow;
 use clap::{Parser, Subcommand};
-use log::info;
-use std::path::PathBuf;
-use tokio::try_join;
+use log::{info, warn};
+use nix_jetbrains_plugins_core::compression::Compression;
+use nix_jetbrains_plugins_core::config::Config;
+use nix_jetbrains_plugins_core::http_client::{HttpConfig, HttpContext};
+use nix_jetbrains_plugins_core::lock::RunLock;
+use nix_jetbrains_plugins_core::logging::{self, LogFile, LogFormat};
+use nix_jetbrains_plugins_core::notify;
+use nix_jetbrains_plugins_core::pipeline::{
+    self, CleanupOptions, GenerateOptions, OutputOptions, RepairOptions,
+};
+use nix_jetbrains_plugins_core::plugins::{self, DbBackend, PluginsLayout, Storage};
+use nix_jetbrains_plugins_core::progress::ActiveBar;
+use nix_jetbrains_plugins_core::run_context::RunContext;
+use nix_jetbrains_plugins_core::run_summary::{RunSummary, format_bytes};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::signal::ctrl_c;
+use tokio_util::sync::CancellationToken;
 
 #[derive(Parser)]
 struct Cli {
     #[arg(short, long)]
     output_path: PathBuf,
+    /// Break the lock on the output directory left behind by another (crashed) run.
+    #[arg(long)]
+    force: bool,
+    /// How to store all_plugins.json. Defaults to the layout already present in the output directory.
+    #[arg(long, value_enum)]
+    layout: Option<PluginsLayout>,
+    /// Also write each JSON file as a Nix expression (`.nix`) when saving, or stop doing so.
+    /// Defaults to whether the output directory already has them.
+    #[arg(long)]
+    nix_output: Option<bool>,
+    /// Write all_plugins.json (or its shards) without indentation and line breaks, or stop doing
+    /// so. Defaults to how the existing files are written.
+    #[arg(long)]
+    compact: Option<bool>,
+    /// Also write all_plugins.json (or its shards) and the IDE mappings compressed next to the
+    /// JSON files when saving, e.g. `all_plugins.json.zst`. `none` removes them. Defaults to the
+    /// compression already in the output directory.
+    #[arg(long, value_enum)]
+    compress: Option<Compression>,
+    /// Leave the generation times out of the written files, so that the same database always
+    /// gives byte-identical output, or stop doing so. Defaults to whether the existing files
+    /// have them.
+    #[arg(long)]
+    deterministic: Option<bool>,
+    /// Where to persist the plugin database between runs. The JSON files in the output
+    /// directory are always written when saving.
+    #[arg(long, value_enum, default_value_t)]
+    db_backend: DbBackend,
+    /// The SQLite file used by `--db-backend sqlite`. Imported from the JSON files if missing.
+    #[arg(long, default_value = "plugins.sqlite")]
+    sqlite_path: PathBuf,
+    /// Generator configuration, see the README. Ignored if missing.
+    #[arg(long, default_value = "generator.toml")]
+    config: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    log_format: LogFormat,
+    /// Also log to this file, including debug records.
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+    /// Roll the log file over once it reaches this many MiB.
+    #[arg(long, default_value_t = 64)]
+    log_file_max_mib: u64,
+    /// How many rolled over log files to keep.
+    #[arg(long, default_value_t = 5)]
+    log_file_keep: u32,
+    /// Send all requests through this proxy. Without it, `HTTP_PROXY`/`HTTPS_PROXY` are honored.
+    #[arg(long)]
+    proxy: Option<String>,
+    /// Trust the CA certificates in this PEM file in addition to the system ones. Not used by
+    /// `--hasher nix`, which only trusts the certificates Nix is configured with. Can be given
+    /// multiple times.
+    #[arg(long = "extra-ca-cert")]
+    extra_ca_certs: Vec<PathBuf>,
+    /// JetBrains Hub token sent with marketplace requests, e.g. to index paid plugins. Not
+    /// used for downloads by `--hasher nix`.
+    #[arg(long, env = "JB_MARKETPLACE_TOKEN", hide_env_values = true)]
+    marketplace_token: Option<String>,
+    /// POST a JSON summary of the outcome to this URL when the command finishes.
+    #[arg(long)]
+    notify_webhook: Option<String>,
     #[clap(subcommand)]
     command: Command,
 }
@@ -18,63 +90,199 @@ struct Cli {
 #[derive(Subcommand)]
 enum Command {
     /// Generate the IDE JSON files and create/update all_plugins.json
-    Generate,
+    Generate(Box<GenerateOptions>),
     /// Remove all plugins from all_plugins.json that are no longer used in any IDE json file.
-    Cleanup,
+    Cleanup(CleanupOptions),
+    /// Print the total download size of the plugins of each IDE version and the largest plugin
+    /// artifacts.
+    Stats {
+        /// Number of largest artifacts to list.
+        #[arg(long, default_value_t = 20)]
+        largest: usize,
+    },
+    /// Rewrite all files of the database in their canonical form, e.g. after editing them by
+    /// hand, without changing their contents.
+    Normalize,
+    /// Rebuild the entries of all_plugins.json that the IDE mappings reference, e.g. after it
+    /// was lost, by hashing only those plugin versions again.
+    Repair(RepairOptions),
+    /// Delete the Nix store paths that `--hasher nix` added and that runs didn't get to delete,
+    /// e.g. because they crashed.
+    Gc,
+    /// Check the output directory: that every plugin version in the IDE mappings has an entry
+    /// and, with `--schema`, that the JSON files match their JSON Schemas.
+    Validate {
+        /// Also validate the JSON files against the JSON Schemas in `core/schemas`.
+        #[arg(long)]
+        schema: bool,
+    },
 }
 
-const PLUGIN_INDICES: &[&str] = &[
-    "https://downloads.marketplace.jetbrains.com/files/pluginsXMLIds.json",
-    "https://downloads.marketplace.jetbrains.com/files/jbPluginsXMLIds.json",
-];
+impl Command {
+    /// Whether the command writes to the output directory and so needs to lock it.
+    fn writes_output(&self) -> bool {
+        !matches!(self, Command::Stats { .. } | Command::Validate { .. })
+    }
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
-    _ = logging::setup_logging();
+    let log_file = cli.log_file.clone().map(|path| LogFile {
+        path,
+        max_size: cli.log_file_max_mib * 1024 * 1024,
+        keep: cli.log_file_keep,
+    });
+    let active_bar = ActiveBar::default();
+    if let Err(e) = logging::setup_logging(cli.log_format, log_file.as_ref(), &active_bar) {
+        eprintln!("failed to set up logging: {e:#}");
+    }
     info!("Starting...");
-
-    match cli.command {
-        Command::Generate => generate(cli).await,
-        Command::Cleanup => cleanup(cli).await,
+    let started = Instant::now();
+    let command = match cli.command {
+        Command::Generate(_) => "generate",
+        Command::Cleanup(_) => "cleanup",
+        Command::Stats { .. } => "stats",
+        Command::Normalize => "normalize",
+        Command::Repair(_) => "repair",
+        Command::Gc => "gc",
+        Command::Validate { .. } => "validate",
+    };
+    let notify_webhook = cli.notify_webhook.clone();
+    // Set up by `run`, so that the notification uses the configured proxy and headers.
+    let mut http = HttpContext::default();
+    let mut summary = None;
+    let result = run(cli, &active_bar, &mut http, &mut summary).await;
+    if let Some(url) = notify_webhook {
+        notify::send(
+            &http,
+            &url,
+            command,
+            &result,
+            started.elapsed(),
+            summary.as_ref(),
+        )
+        .await;
     }
+    result
 }
 
-async fn generate(cli: Cli) -> anyhow::Result<()> {
-    info!("running generate.");
-    let (ides, mut plugins, jb_plugins) = try_join!(
-        ides::collect_ids(),
-        plugins::index(PLUGIN_INDICES[0]),
-        plugins::index(PLUGIN_INDICES[1])
-    )?;
+async fn run(
+    cli: Cli,
+    active_bar: &ActiveBar,
+    http: &mut HttpContext,
+    summary: &mut Option<RunSummary>,
+) -> anyhow::Result<()> {
+    // Read-only commands may run next to a run that writes.
+    let _lock = cli
+        .command
+        .writes_output()
+        .then(|| RunLock::acquire(&cli.output_path, cli.force))
+        .transpose()?;
 
-    info!(
-        "Indexing {} IDE versions, {} plugins and {} Jetbrains plugins.",
-        ides.len(),
-        plugins.len(),
-        jb_plugins.len()
-    );
-    plugins.extend_from_slice(&jb_plugins);
+    let config = Config::load(&cli.config).await?;
+    *http = HttpContext::new(HttpConfig::new(
+        cli.proxy.as_deref(),
+        &cli.extra_ca_certs,
+        cli.marketplace_token.as_deref(),
+        &config.http,
+    )?);
+    let storage = cli.db_backend.build(&cli.output_path, &cli.sqlite_path)?;
+    let output = OutputOptions {
+        layout: cli.layout,
+        nix_output: cli.nix_output,
+        compact: cli.compact,
+        compression: cli.compress,
+        deterministic: cli.deterministic,
+    };
+    match cli.command {
+        Command::Generate(args) => {
+            let run_context = RunContext::new(shutdown_on_ctrl_c(), active_bar.clone());
+            let result = pipeline::generate(
+                &cli.output_path,
+                &config,
+                http,
+                &run_context,
+                &*storage,
+                output,
+                *args,
+            )
+            .await;
+            *summary = run_context.take_summary();
+            result
+        }
+        Command::Cleanup(options) => {
+            pipeline::cleanup(&cli.output_path, &*storage, output, options).await
+        }
+        Command::Stats { largest } => stats(&*storage, largest).await,
+        Command::Normalize => pipeline::normalize(&*storage, output).await,
+        Command::Repair(options) => {
+            pipeline::repair(&cli.output_path, &config, http, &*storage, output, options).await
+        }
+        Command::Gc => pipeline::gc(&cli.output_path).await,
+        Command::Validate { schema } => validate(&cli.output_path, &*storage, schema).await,
+    }
+}
 
-    info!("Loading old database.");
-    let mut db = plugins::db_load(&cli.output_path).await?;
-    info!("Beginning plugin download...");
-    plugins::db_update(&mut db, &ides, &plugins).await?;
-    info!("Saving DB...");
-    plugins::db_save(&cli.output_path, db).await?;
+/// Returns a token that is cancelled on the first Ctrl-C. A second Ctrl-C exits immediately.
+fn shutdown_on_ctrl_c() -> CancellationToken {
+    let token = CancellationToken::new();
+    let shutdown = token.clone();
+    tokio::spawn(async move {
+        if ctrl_c().await.is_err() {
+            return;
+        }
+        warn!("Interrupted: finishing in-flight plugins and saving. Press Ctrl-C again to abort.");
+        shutdown.cancel();
+        if ctrl_c().await.is_ok() {
+            warn!("Aborted.");
+            std::process::exit(130);
+        }
+    });
+    token
+}
 
+async fn validate(output_path: &Path, storage: &dyn Storage, schema: bool) -> anyhow::Result<()> {
+    // First, as the database can't be loaded from some invalid files at all.
+    if schema {
+        pipeline::validate_schema(output_path).await?;
+    }
+    info!("Loading database and IDE mappings.");
+    let db = storage.load_full().await?;
+    let dangling = db.dangling();
+    if let Some(first) = dangling.first() {
+        return Err(anyhow!(
+            "{} plugin versions in the IDE mappings have no entry, e.g. {first}",
+            dangling.len()
+        ));
+    }
+    info!("Every plugin version in the IDE mappings has an entry.");
     Ok(())
 }
 
-async fn cleanup(cli: Cli) -> anyhow::Result<()> {
+async fn stats(storage: &dyn Storage, largest: usize) -> anyhow::Result<()> {
     info!("Loading database and IDE mappings.");
-    let mut db = plugins::db_load_full(&cli.output_path).await?;
-
-    info!("Running cleanup...");
-    plugins::db_cleanup(&mut db).await?;
-
-    info!("Saving DB...");
-    plugins::db_save(&cli.output_path, db).await?;
+    let db = storage.load_full().await?;
+    let stats = plugins::db_stats(&db, largest);
 
+    println!(
+        "{:<40} {:>8} {:>12} {:>12}",
+        "IDE", "Plugins", "Size", "Unknown size"
+    );
+    for ide in &stats.ides {
+        println!(
+            "{:<40} {:>8} {:>12} {:>12}",
+            ide.ide,
+            ide.plugins,
+            format_bytes(ide.bytes),
+            ide.unknown_size
+        );
+    }
+    if !stats.largest.is_empty() {
+        println!("\nLargest artifacts:");
+        for (plugin, size) in &stats.largest {
+            println!("{:>12}  {plugin}", format_bytes(*size));
+        }
+    }
     Ok(())
 }
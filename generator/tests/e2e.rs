@@ -0,0 +1,340 @@
+//! Runs the generator end-to-end against a mock marketplace, see [`support::MockMarketplace`].
+
+mod support;
+
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use support::{Canned, MockMarketplace, Routes, WorkDir};
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+const UPDATES_XML: &str = r#"<products>
+  <product name="IntelliJ IDEA">
+    <code>IU</code>
+    <channel id="IC-IU-RELEASE-licensing-RELEASE" status="release">
+      <build number="262.100" fullNumber="262.100.1" version="2026.2"/>
+      <build number="261.100" fullNumber="261.100.1" version="2026.1"/>
+    </channel>
+  </product>
+</products>"#;
+
+/// a.plugin 2.0 is only compatible with 2026.2, 1.0 only with 2026.1.
+const A_DETAILS: &str = r#"<plugin-repository>
+  <category name="Tools">
+    <idea-plugin>
+      <id>a.plugin</id>
+      <name>A Plugin</name>
+      <version>2.0</version>
+      <vendor>ACME</vendor>
+      <idea-version since-build="262.0"/>
+      <description>Does A. And more.</description>
+    </idea-plugin>
+    <idea-plugin>
+      <id>a.plugin</id>
+      <name>A Plugin</name>
+      <version>1.0</version>
+      <vendor>ACME</vendor>
+      <idea-version since-build="261.0" until-build="261.*"/>
+    </idea-plugin>
+  </category>
+</plugin-repository>"#;
+
+/// b.plugin depends on a.plugin and is distributed as a JAR.
+const B_DETAILS: &str = r#"<plugin-repository>
+  <category name="Tools">
+    <idea-plugin>
+      <id>b.plugin</id>
+      <name>B Plugin</name>
+      <version>1.0</version>
+      <idea-version since-build="261.0"/>
+      <depends>a.plugin</depends>
+      <depends>com.intellij.modules.platform</depends>
+    </idea-plugin>
+  </category>
+</plugin-repository>"#;
+
+/// A plugin ZIP with a JAR named after `plugin`, containing `contents`.
+fn zip_artifact(plugin: &str, contents: &str) -> Vec<u8> {
+    let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    zip.start_file(
+        format!("{plugin}/lib/{plugin}.jar"),
+        SimpleFileOptions::default(),
+    )
+    .unwrap();
+    zip.write_all(contents.as_bytes()).unwrap();
+    zip.finish().unwrap().into_inner()
+}
+
+fn marketplace() -> Routes {
+    let mut routes = Routes::default();
+    routes
+        .route(
+            "https://www.jetbrains.com/updates/updates.xml",
+            Canned::xml(UPDATES_XML),
+        )
+        .route(
+            "https://jb.gg/android-studio-releases-list.json",
+            Canned::json(r#"{"content":{"item":[]}}"#),
+        )
+        .route(
+            "https://downloads.marketplace.jetbrains.com/files/pluginsXMLIds.json",
+            Canned::json(r#"["a.plugin","b.plugin"]"#),
+        )
+        .route(
+            "https://downloads.marketplace.jetbrains.com/files/jbPluginsXMLIds.json",
+            Canned::json("[]"),
+        )
+        .route(
+            "https://plugins.jetbrains.com/plugins/list?pluginId=a.plugin",
+            Canned::xml(A_DETAILS),
+        )
+        .route(
+            "https://plugins.jetbrains.com/plugins/list?pluginId=b.plugin",
+            Canned::xml(B_DETAILS),
+        );
+    for (plugin, version, file) in [
+        ("a.plugin", "1.0", "files/1/10/a-1.0.zip"),
+        ("a.plugin", "2.0", "files/1/20/a-2.0.zip"),
+        ("b.plugin", "1.0", "files/2/10/b-1.0.jar"),
+    ] {
+        let artifact = format!("https://downloads.marketplace.jetbrains.com/{file}");
+        routes
+            .route(
+                &format!(
+                    "https://plugins.jetbrains.com/plugin/download?pluginId={plugin}&version={version}"
+                ),
+                Canned::Redirect(artifact.clone()),
+            )
+            .route(
+                &artifact,
+                Canned::artifact(if file.ends_with(".zip") {
+                    zip_artifact(plugin, file)
+                } else {
+                    file.as_bytes().to_vec()
+                }),
+            );
+    }
+    routes
+}
+
+/// Number of artifact downloads in `requests`, see [`MockMarketplace::requests`].
+fn downloads(requests: Vec<String>) -> usize {
+    requests
+        .into_iter()
+        .filter(|request| {
+            request.starts_with("GET https://downloads.marketplace.jetbrains.com/files/")
+        })
+        .filter(|request| !request.ends_with("XMLIds.json"))
+        .count()
+}
+
+#[tokio::test]
+async fn generate_against_mock_marketplace() {
+    let mock = MockMarketplace::start(marketplace()).await;
+    let dir = WorkDir::new();
+
+    mock.generate(&dir, &[], &["--validate-schema"]).await;
+
+    let all_plugins = dir.read_json("all_plugins.json");
+    assert_eq!(all_plugins["schemaVersion"], 3);
+    let entries = all_plugins["plugins"].as_object().unwrap();
+    assert_eq!(
+        entries.keys().collect::<Vec<_>>(),
+        ["a.plugin/--/1.0", "a.plugin/--/2.0", "b.plugin/--/1.0"]
+    );
+    let a = &entries["a.plugin/--/2.0"];
+    assert_eq!(a["p"], "files/1/20/a-2.0.zip");
+    assert_eq!(a["k"], "zip");
+    assert!(a["h"].as_str().unwrap().starts_with("sha256-"));
+    let b = &entries["b.plugin/--/1.0"];
+    assert_eq!(b["p"], "files/2/10/b-1.0.jar");
+    assert_eq!(b["k"], "jar");
+    assert_eq!(b["d"], json!(["a.plugin"]));
+    assert_ne!(a["h"], entries["a.plugin/--/1.0"]["h"]);
+
+    let ide = dir.read_json("ides/idea-2026.1.json");
+    assert_eq!(ide["meta"]["buildNumber"], "261.100.1");
+    assert_eq!(
+        ide["plugins"],
+        json!({"a.plugin": "1.0", "b.plugin": "1.0"})
+    );
+    let ide = dir.read_json("ides/idea-2026.2.json");
+    assert_eq!(
+        ide["plugins"],
+        json!({"a.plugin": "2.0", "b.plugin": "1.0"})
+    );
+
+    let meta = dir.read_json("plugins_meta.json");
+    assert_eq!(meta["a.plugin"]["name"], "A Plugin");
+    assert_eq!(meta["a.plugin"]["vendor"], "ACME");
+    let index = dir.read_json("index.json");
+    assert_eq!(index["latest"]["idea"], "2026.2");
+
+    // A second run finds every entry in the database and downloads nothing.
+    assert_eq!(downloads(mock.requests()), 3);
+    let before = mock.requests().len();
+    mock.generate(&dir, &[], &[]).await;
+    assert_eq!(downloads(mock.requests().split_off(before)), 0);
+    assert_eq!(dir.read_json("all_plugins.json"), all_plugins);
+}
+
+/// The files of the output directory by relative path, without the records of the run.
+fn output_files(out: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![out.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if !["run_summary.json", "plugin_status.json"]
+                .contains(&&*path.file_name().unwrap().to_string_lossy())
+            {
+                let contents = std::fs::read(&path).unwrap();
+                files.insert(path.strip_prefix(out).unwrap().to_path_buf(), contents);
+            }
+        }
+    }
+    files
+}
+
+#[tokio::test]
+async fn deterministic_output_is_byte_identical() {
+    let mock = MockMarketplace::start(marketplace()).await;
+    let mut outputs = Vec::new();
+    for _ in 0..2 {
+        let dir = WorkDir::new();
+        mock.generate(
+            &dir,
+            &["--deterministic", "true", "--nix-output", "true"],
+            &[],
+        )
+        .await;
+        outputs.push(output_files(&dir.out()));
+    }
+    assert!(outputs[0].contains_key(Path::new("ides/idea-2026.2.nix")));
+    assert!(
+        !outputs[0]
+            .values()
+            .any(|contents| String::from_utf8_lossy(contents).contains("generatedAt"))
+    );
+    assert!(outputs[0] == outputs[1], "outputs of identical runs differ");
+}
+
+#[tokio::test]
+async fn repair_restores_lost_all_plugins() {
+    let mock = MockMarketplace::start(marketplace()).await;
+    let dir = WorkDir::new();
+    mock.generate(&dir, &[], &[]).await;
+    let all_plugins = dir.read_json("all_plugins.json");
+    std::fs::remove_file(dir.out().join("all_plugins.json")).unwrap();
+
+    let before = mock.requests().len();
+    mock.repair(&dir, &[]).await;
+    assert_eq!(
+        dir.read_json("all_plugins.json")["plugins"],
+        all_plugins["plugins"]
+    );
+    // Only the referenced plugins were looked at, not every one in the indices.
+    assert!(
+        !mock
+            .requests()
+            .split_off(before)
+            .iter()
+            .any(|request| request.contains("updates.xml"))
+    );
+}
+
+#[tokio::test]
+async fn generate_recovers_corrupted_all_plugins() {
+    let mock = MockMarketplace::start(marketplace()).await;
+    let dir = WorkDir::new();
+    mock.generate(&dir, &[], &[]).await;
+    let all_plugins = dir.read_json("all_plugins.json");
+
+    // Cut off in the middle of the last entry, like an interrupted write.
+    let path = dir.out().join("all_plugins.json");
+    let text = std::fs::read_to_string(&path).unwrap();
+    let truncated = &text[..text.find("b.plugin/--/1.0").unwrap() + 30];
+    std::fs::write(&path, truncated).unwrap();
+
+    mock.generate(&dir, &[], &[]).await;
+    assert_eq!(
+        dir.read_json("all_plugins.json")["plugins"],
+        all_plugins["plugins"]
+    );
+    let summary = dir.read_json("run_summary.json");
+    let recovered = &summary["recoveredFiles"][0];
+    assert_eq!(recovered["path"], "all_plugins.json");
+    assert_eq!(recovered["salvaged"], 2);
+    let backup = dir.out().join(recovered["backup"].as_str().unwrap());
+    assert_eq!(std::fs::read_to_string(backup).unwrap(), truncated);
+}
+
+#[tokio::test]
+async fn generate_from_local_plugin_index() {
+    let mock = MockMarketplace::start(marketplace()).await;
+    let dir = WorkDir::new();
+    let index = dir.path().join("ids.json");
+    std::fs::write(&index, r#"["a.plugin"]"#).unwrap();
+    let index = format!("file://{}", index.display());
+
+    mock.generate(&dir, &[], &["--plugin-index", &index]).await;
+    let all_plugins = dir.read_json("all_plugins.json");
+    assert_eq!(
+        all_plugins["plugins"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect::<Vec<_>>(),
+        ["a.plugin/--/1.0", "a.plugin/--/2.0"]
+    );
+    assert!(
+        !mock
+            .requests()
+            .iter()
+            .any(|request| request.ends_with("XMLIds.json"))
+    );
+}
+
+#[tokio::test]
+async fn artifact_cache_avoids_downloads() {
+    let mock = MockMarketplace::start(marketplace()).await;
+    let cache = tempfile::tempdir().unwrap();
+    let cache = cache.path().to_str().unwrap();
+    let mut all_plugins = Vec::new();
+    for _ in 0..2 {
+        let dir = WorkDir::new();
+        mock.generate(&dir, &[], &["--artifact-cache", cache]).await;
+        all_plugins.push(dir.read_json("all_plugins.json")["plugins"].clone());
+    }
+    // The second run starts without a database, but hashes the cached artifacts.
+    assert_eq!(downloads(mock.requests()), 3);
+    assert_eq!(all_plugins[0], all_plugins[1]);
+}
+
+#[tokio::test]
+async fn missing_downloads_get_tombstones() {
+    let mut routes = marketplace();
+    routes.route(
+        "https://downloads.marketplace.jetbrains.com/files/1/20/a-2.0.zip",
+        Canned::not_found(),
+    );
+    let mock = MockMarketplace::start(routes).await;
+    let dir = WorkDir::new();
+
+    mock.generate(&dir, &[], &[]).await;
+    let tombstones = dir.read_json("tombstones.json");
+    assert_eq!(tombstones["a.plugin/--/2.0"]["reason"], "unavailable");
+    let all_plugins = dir.read_json("all_plugins.json");
+    assert!(all_plugins["plugins"].get("a.plugin/--/2.0").is_none());
+
+    // Once the download is back, the version gets its entry and loses its tombstone.
+    let mock = MockMarketplace::start(marketplace()).await;
+    mock.generate(&dir, &[], &[]).await;
+    assert!(!dir.out().join("tombstones.json").exists());
+    let all_plugins = dir.read_json("all_plugins.json");
+    assert!(all_plugins["plugins"].get("a.plugin/--/2.0").is_some());
+}
@@ -0,0 +1,231 @@
+//! A mock of the JetBrains IDE feeds and the plugin marketplace for end-to-end tests: serves
+//! canned responses over HTTP on localhost, with the generator pointed at it through
+//! `--request-rewrite` and `--download-rewrite`.
+
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{CONTENT_TYPE, LOCATION};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+use tokio::process::Command;
+
+/// A canned response.
+#[derive(Clone)]
+pub enum Canned {
+    Body {
+        status: StatusCode,
+        content_type: &'static str,
+        body: Bytes,
+    },
+    /// Redirects to the mock's copy of an upstream URL.
+    Redirect(String),
+}
+
+impl Canned {
+    pub fn xml(body: &str) -> Self {
+        Self::body("application/xml", body.to_string())
+    }
+
+    pub fn json(body: &str) -> Self {
+        Self::body("application/json", body.to_string())
+    }
+
+    pub fn artifact(body: Vec<u8>) -> Self {
+        Self::body("application/octet-stream", body)
+    }
+
+    /// A 404, e.g. for an artifact that is gone while the marketplace still lists it.
+    pub fn not_found() -> Self {
+        Self::Body {
+            status: StatusCode::NOT_FOUND,
+            content_type: "text/plain",
+            body: Bytes::new(),
+        }
+    }
+
+    fn body(content_type: &'static str, body: impl Into<Bytes>) -> Self {
+        Self::Body {
+            status: StatusCode::OK,
+            content_type,
+            body: body.into(),
+        }
+    }
+}
+
+/// Canned responses by upstream URL (e.g. `https://plugins.jetbrains.com/plugins/list?pluginId=a`,
+/// with the query). Requests without one get a 404, like unknown plugins on the marketplace.
+#[derive(Default)]
+pub struct Routes(HashMap<String, Canned>);
+
+impl Routes {
+    pub fn route(&mut self, url: &str, response: Canned) -> &mut Self {
+        let key = url
+            .strip_prefix("https://")
+            .expect("upstream URLs are https");
+        self.0.insert(key.to_string(), response);
+        self
+    }
+}
+
+/// A temporary directory to run the generator in, with the output in `generated/`.
+pub struct WorkDir(TempDir);
+
+impl WorkDir {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("generated")).unwrap();
+        Self(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        self.0.path()
+    }
+
+    /// The output directory.
+    pub fn out(&self) -> PathBuf {
+        self.path().join("generated")
+    }
+
+    /// Parses the JSON file at `path` in the output directory.
+    pub fn read_json(&self, path: &str) -> Value {
+        serde_json::from_str(&std::fs::read_to_string(self.out().join(path)).unwrap()).unwrap()
+    }
+}
+
+pub struct MockMarketplace {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockMarketplace {
+    /// Serves `routes` on a free port until the test ends.
+    pub async fn start(routes: Routes) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let routes = Arc::new(routes.0);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let routes = routes.clone();
+                let recorded = recorded.clone();
+                let service = service_fn(move |request: Request<Incoming>| {
+                    let routes = routes.clone();
+                    let recorded = recorded.clone();
+                    async move { Ok::<_, Infallible>(respond(addr, &routes, &recorded, &request)) }
+                });
+                tokio::spawn(async move {
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        Self { addr, requests }
+    }
+
+    /// The base URL that upstream `https://` URLs are served under.
+    pub fn base_url(&self) -> String {
+        format!("http://{}/", self.addr)
+    }
+
+    /// The requests received so far, as `"<METHOD> <upstream URL>"`.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Runs `generate` against the mock with `args` in addition to the ones that point it
+    /// there, in `dir`. `global_args` go before the command.
+    pub async fn generate(&self, dir: &WorkDir, global_args: &[&str], args: &[&str]) -> Output {
+        let mut args = args.to_vec();
+        args.extend(["--nixpkgs-check", "off"]);
+        self.run(dir, global_args, "generate", &args).await
+    }
+
+    /// Runs `repair` against the mock, like [`Self::generate`].
+    pub async fn repair(&self, dir: &WorkDir, args: &[&str]) -> Output {
+        self.run(dir, &[], "repair", args).await
+    }
+
+    async fn run(
+        &self,
+        dir: &WorkDir,
+        global_args: &[&str],
+        command: &str,
+        args: &[&str],
+    ) -> Output {
+        let rewrite = format!("https://={}", self.base_url());
+        let output = Command::new(env!("CARGO_BIN_EXE_nix-jebrains-plugins-generator"))
+            .current_dir(dir.path())
+            .args(["-o", "generated"])
+            .args(global_args)
+            .arg(command)
+            .args([
+                "--request-rewrite",
+                &rewrite,
+                "--download-rewrite",
+                &rewrite,
+            ])
+            .args(args)
+            .output()
+            .await
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{command} failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output
+    }
+}
+
+fn respond(
+    addr: SocketAddr,
+    routes: &HashMap<String, Canned>,
+    requests: &Mutex<Vec<String>>,
+    request: &Request<Incoming>,
+) -> Response<Full<Bytes>> {
+    let key = request
+        .uri()
+        .path_and_query()
+        .map_or("", |path| path.as_str())
+        .trim_start_matches('/');
+    requests
+        .lock()
+        .unwrap()
+        .push(format!("{} https://{key}", request.method()));
+    let response = Response::builder();
+    match routes.get(key) {
+        Some(Canned::Body {
+            status,
+            content_type,
+            body,
+        }) => response
+            .status(status)
+            .header(CONTENT_TYPE, *content_type)
+            .body(Full::new(body.clone())),
+        Some(Canned::Redirect(url)) => response
+            .status(StatusCode::FOUND)
+            .header(
+                LOCATION,
+                url.replacen("https://", &format!("http://{addr}/"), 1),
+            )
+            .body(Full::default()),
+        None => response.status(StatusCode::NOT_FOUND).body(Full::default()),
+    }
+    .unwrap()
+}
@@ -14,15 +14,35 @@ let
       version,
       url,
       hash,
+      kind,
+      unpackable,
+      dependencies,
+      pricing,
+      bundled,
     }:
     let
-      isJar = hasSuffix ".jar" url;
-      fetcher = if isJar then fetchurl else fetchzip;
+      isJar = kind == "jar";
+      # ZIPs Nix can't unpack (e.g. because of invalid file names) are fetched as they are.
+      fetcher = if isJar || !unpackable then fetchurl else fetchzip;
     in
     fetcher {
-      name = if isJar then "${name}-${version}.jar" else "${name}-${version}";
+      name =
+        if isJar then
+          "${name}-${version}.jar"
+        else if !unpackable then
+          "${name}-${version}.zip"
+        else
+          "${name}-${version}";
       executable = isJar;
       inherit url hash;
+      # Marketplace plugin IDs this plugin needs, see lib.buildIdeWithPlugins.
+      passthru.dependencies = dependencies;
+      passthru.unpackable = unpackable;
+      # "free", "freemium", "paid" or null if unknown. Paid plugins need a license to work.
+      passthru.pricing = pricing;
+      passthru.paid = pricing == "paid";
+      # Whether the IDE version already bundles this plugin.
+      passthru.bundled = bundled;
     };
 
   readGeneratedDir = attrNames (
@@ -38,39 +58,147 @@ let
     }
   );
 
-  # Find and construct plugin from a list of plugins
+  # Find and construct plugin, `pluginsFor name` returns the plugin entries containing it.
   findPlugin =
-    pluginList: name: version:
+    pluginsFor: name: version:
     let
-      key = "${name}${SEPARATOR}${version}";
-      match = pluginList."${key}";
+      # `%` and `/` in names are escaped, so the first separator always ends the name.
+      key = "${replaceStrings [ "%" "/" ] [ "%25" "%2F" ] name}${SEPARATOR}${version}";
+      match = (pluginsFor name)."${key}";
     in
     {
       inherit name version;
-      url = "https://downloads.marketplace.jetbrains.com/${match.p}";
-      hash = "sha256-${match.h}";
+      # Plugins from custom repositories are stored with their full URL.
+      url =
+        if hasPrefix "https://" match.p || hasPrefix "http://" match.p then
+          match.p
+        else
+          "https://downloads.marketplace.jetbrains.com/${match.p}";
+      # Hashes are SRI strings, older databases only contain the base64 SHA-256 digest.
+      hash = if hasPrefix "sha256-" match.h then match.h else "sha256-${match.h}";
+      # Older databases don't record the artifact kind.
+      kind = match.k or (if hasSuffix ".jar" match.p then "jar" else "zip");
+      unpackable = match.u or true;
+      dependencies = match.d or [ ];
+      # Marketplace release channel of pre-release versions, null for stable ones.
+      channel = match.c or null;
+      pricing = pluginsMeta.${name}.pricing or null;
     };
 
-  allPlugins = fromJSON (readFile ./generated/all_plugins.json);
+  # Reads a JSON file in `dir`, or the Nix expression the generator writes next to it with
+  # `--nix-output true`, which is cheaper to evaluate.
+  readGenerated =
+    dir: jsonFile:
+    let
+      nixFile = dir + "/${removeSuffix ".json" jsonFile}.nix";
+    in
+    if pathExists nixFile then import nixFile else fromJSON (readFile (dir + "/${jsonFile}"));
+
+  # Since schema version 2 the entries are below `plugins`, before that the file only contains them.
+  readAllPlugins =
+    dir: file:
+    let
+      content = readGenerated dir file;
+    in
+    if content ? schemaVersion then content.plugins else content;
+
+  # Shard of a plugin in generated/all_plugins/, must match shard_of in the generator.
+  shardOf =
+    name:
+    let
+      c = toLower (substring 0 1 name);
+    in
+    if match "[a-z0-9]" c != null then c else "_";
+
+  # The entries are either in all_plugins.json or sharded into all_plugins/<shard>.json.
+  # Shards are only read when a plugin in them is used.
+  allPlugins =
+    if pathExists ./generated/all_plugins then
+      let
+        shards = mapAttrs' (file: _: {
+          name = removeSuffix ".json" file;
+          value = readAllPlugins ./generated/all_plugins file;
+        }) (filterAttrs (file: _: hasSuffix ".json" file) (readDir ./generated/all_plugins));
+      in
+      name: shards.${shardOf name} or { }
+    else
+      let
+        plugins = readAllPlugins ./generated "all_plugins.json";
+      in
+      _: plugins;
+
+  pluginsMeta =
+    if pathExists ./generated/plugins_meta.json then
+      fromJSON (readFile ./generated/plugins_meta.json)
+    else
+      { };
 
-  pluginsGrouped = (
+  # Marketplace plugins bundled with each IDE version, by IDE file name without `.json`.
+  bundledPlugins =
+    if pathExists ./generated/bundled.json then
+      fromJSON (readFile ./generated/bundled.json)
+    else
+      { };
+
+  # IDE mappings contain either a version string or { stable = "..."; eap = "..."; }
+  stableVersion = v: if isString v then v else v.stable or null;
+  eapVersion = v: if isString v then v else v.eap or v.stable or null;
+
+  # IDE files are { meta = { ... }; plugins = { ... }; }, older ones only contain the plugins.
+  readIdeMapping =
+    jsonFile:
+    let
+      content = readGenerated ./generated/ides jsonFile;
+    in
+    if content ? meta then content.plugins else content;
+
+  groupPlugins =
+    selectVersion:
     groupBy' buildIdeVersionMap { } (x: x.ideName) (
       map (
         jsonFile:
         let
-          # Split the JSON filename into IDENAME-VERSION and remove json suffix
-          parts = splitString "-" (removeSuffix ".json" jsonFile);
+          # Split the JSON filename into IDENAME-VERSION and remove json suffix.
+          # Both may contain dashes (e.g. android-studio-2025.3-eap), but versions start with a digit.
+          parts = match "([a-z-]+)-([0-9].*)" (removeSuffix ".json" jsonFile);
         in
         {
-          ideName = concatStrings (intersperse "-" (init parts));
-          version = elemAt parts ((length parts) - 1);
-          value = mapAttrs (k: v: downloadPlugin (findPlugin allPlugins k v)) (
-            fromJSON (readFile (./generated/ides + "/${jsonFile}"))
+          ideName = elemAt parts 0;
+          version = elemAt parts 1;
+          value = mapAttrs (
+            k: v:
+            downloadPlugin (
+              findPlugin allPlugins k v
+              // {
+                bundled = elem k (bundledPlugins.${removeSuffix ".json" jsonFile} or [ ]);
+              }
+            )
+          ) (
+            filterAttrs (_: v: v != null) (
+              mapAttrs (_: selectVersion) (readIdeMapping jsonFile)
+            )
           );
         }
       ) readGeneratedDir
-    )
+    );
+
+  # Newest stable version of each IDE, written by the generator to generated/index.json.
+  latestVersions =
+    if pathExists ./generated/index.json then
+      (fromJSON (readFile ./generated/index.json)).latest
+    else
+      { };
+
+  # Adds a `latest` alias to the versions of each IDE.
+  withLatest = mapAttrs (
+    ide: versions:
+    versions
+    // optionalAttrs (versions ? ${latestVersions.${ide} or ""}) {
+      latest = versions.${latestVersions.${ide}};
+    }
   );
+
+  pluginsGrouped = withLatest (groupPlugins stableVersion);
 in
 # Add aliases for -oss and the deprecated -community and -ultimate
 pluginsGrouped
@@ -81,4 +209,6 @@ pluginsGrouped
   pycharm-community = pluginsGrouped.pycharm;
   pycharm-professional = pluginsGrouped.pycharm;
   pycharm-oss = pluginsGrouped.pycharm;
+  # Same structure, but preferring newer EAP plugin versions where the generator recorded them.
+  eap = withLatest (groupPlugins eapVersion);
 }